@@ -0,0 +1,874 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Derive macros for `manticore`'s wire format traits.
+//!
+//! This crate should not be depended on directly; instead, the derives are
+//! re-exported from `manticore::protocol::wire`, alongside the traits they
+//! implement:
+//! ```text
+//! use manticore::protocol::wire::FromWire;
+//! use manticore::protocol::wire::ToWire;
+//!
+//! #[derive(FromWire, ToWire)]
+//! pub struct MyMessage<'a> {
+//!     pub index: u8,
+//!     #[wire(len_prefix = "u16")]
+//!     pub data: &'a [u8],
+//! }
+//! ```
+//!
+//! # Structs
+//!
+//! `#[derive(FromWire, ToWire)]` on a struct produces implementations that
+//! read and write each field in declaration order. How a field is encoded
+//! depends on its type and on its `#[wire(...)]` attributes:
+//! - `u8`, `u16`, `u32` and `u64` are encoded as little-endian integers,
+//!   unless the field is marked `#[wire(be)]`. `#[wire(le)]` or `#[wire(be)]`
+//!   may also be used to mark a type alias as an integer.
+//! - `[u8; N]` is encoded as exactly `N` bytes.
+//! - `&'a T`, for a non-slice `T`, is encoded as the bytes of `T`, and is
+//!   allocated in the arena. `T` must be a `zerocopy` type, such as
+//!   `[u8; 32]`.
+//! - `&'a [T]` is encoded as the bytes of its elements, and is allocated in
+//!   the arena. Its length must be specified, either as a length prefix,
+//!   with `#[wire(len_prefix = "u16")]`, or by consuming the remainder of
+//!   the message, with `#[wire(remaining)]`. A length prefix is encoded like
+//!   any other integer, so `#[wire(be)]` makes it big-endian.
+//! - `#[wire(bits = N)]` packs a field into `N` bits of a byte, using
+//!   `io::BitBuf`. Consecutive bit fields share a byte, from most to least
+//!   significant bit, and must add up to exactly eight bits;
+//!   `#[wire(bits = N, pad = M)]` follows the field with `M` reserved zero
+//!   bits, which are rejected as out of range if they are not zero. The
+//!   field's type must implement `wire::BitField`.
+//! - Any other type is encoded with its own `FromWire` and `ToWire`
+//!   implementations.
+//!
+//! A struct-level `#[wire(be)]` makes big-endian the default for all integer
+//! fields and length prefixes in that struct.
+//!
+//! If the struct has a single lifetime parameter, it is used as the `'wire`
+//! lifetime of the `FromWire` implementation.
+//!
+//! # Enums
+//!
+//! `#[derive(WireEnum)]` on a fieldless enum with a `#[repr(uN)]` attribute
+//! and explicit discriminants produces the same items as the `wire_enum!`
+//! macro: an implementation of `WireEnum`, which provides `FromWire` and
+//! `ToWire`, as well as `Display` and `FromStr` implementations using the
+//! variant names. The enum must also derive `Clone` and `Copy`.
+//!
+//! # Fuzzing
+//!
+//! `#[derive(FuzzSafe)]` on a struct implements `manticore`'s `FuzzSafe`
+//! trait, which the generated fuzz targets use to obtain arbitrary values
+//! of a message. A struct without a lifetime parameter is its own fuzz-safe
+//! type, and must implement `Arbitrary`. Otherwise, a hidden `<Name>FuzzSafe`
+//! struct is generated, which owns the data the message borrows, and whose
+//! `as_ref()` method borrows it back as the original struct:
+//! - `&'a [T]` is stored as `Box<[T]>`, and `&'a str` as `Box<str>`.
+//! - `&'a T` is stored as `T`.
+//! - Any other type is stored as-is, and must be `Copy`.
+//!
+//! The generated items only exist if the `arbitrary-derive` feature of the
+//! crate using the derive is enabled, and expect `arbitrary` and `Arbitrary`
+//! to be in scope, so this derive is only intended for use inside
+//! `manticore`; other crates should implement `FuzzSafe` directly.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+#![deny(unused)]
+#![deny(unsafe_code)]
+
+use proc_macro2::Span;
+use proc_macro2::TokenStream;
+use quote::format_ident;
+use quote::quote;
+use syn::spanned::Spanned as _;
+use syn::Data;
+use syn::DeriveInput;
+use syn::Error;
+use syn::Fields;
+use syn::Ident;
+use syn::Lit;
+use syn::Meta;
+use syn::NestedMeta;
+use syn::Type;
+
+/// Derives `manticore::protocol::wire::FromWire`.
+///
+/// See the [crate documentation](index.html) for details.
+#[proc_macro_derive(FromWire, attributes(wire))]
+pub fn derive_from_wire(
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    from_wire(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `manticore::protocol::wire::ToWire`.
+///
+/// See the [crate documentation](index.html) for details.
+#[proc_macro_derive(ToWire, attributes(wire))]
+pub fn derive_to_wire(
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    to_wire(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `manticore::protocol::wire::WireEnum`, as well as `Display` and
+/// `FromStr`.
+///
+/// See the [crate documentation](index.html) for details.
+#[proc_macro_derive(WireEnum)]
+pub fn derive_wire_enum(
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    wire_enum(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `manticore::protocol::FuzzSafe`.
+///
+/// See the [crate documentation](index.html) for details.
+#[proc_macro_derive(FuzzSafe)]
+pub fn derive_fuzz_safe(
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    fuzz_safe(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// A length specification for a slice field.
+enum Len {
+    /// A length prefix of the given integer type.
+    Prefix(Ident),
+    /// The rest of the message.
+    Remaining,
+}
+
+/// The ways in which a field can be encoded.
+enum Kind {
+    /// An integer, in the given endianness.
+    Int { be: bool },
+    /// A fixed-size array of bytes, of the given length.
+    ByteArray(Box<syn::Expr>),
+    /// A reference to a `zerocopy` type, allocated in the arena.
+    Ref,
+    /// A slice of `zerocopy` types, allocated in the arena, whose length
+    /// prefix (if any) has the given endianness.
+    Slice { len: Len, be: bool },
+    /// A bit field within a byte, followed by `pad` zero bits.
+    Bits { bits: usize, pad: usize },
+    /// A type with its own wire format implementations.
+    Nested,
+}
+
+/// A parsed struct field.
+struct Field {
+    /// The expression for accessing this field on `self`.
+    member: TokenStream,
+    /// The local variable name used when parsing this field.
+    local: Ident,
+    /// How this field is encoded.
+    kind: Kind,
+    /// The span to report errors at.
+    span: Span,
+}
+
+/// Options from `#[wire(...)]` attributes.
+#[derive(Default)]
+struct Attrs {
+    be: Option<bool>,
+    len: Option<Len>,
+    bits: Option<usize>,
+    pad: Option<usize>,
+}
+
+impl Attrs {
+    fn parse(attrs: &[syn::Attribute]) -> Result<Self, Error> {
+        let mut out = Attrs::default();
+        for attr in attrs {
+            if !attr.path.is_ident("wire") {
+                continue;
+            }
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => {
+                    return Err(Error::new(
+                        meta.span(),
+                        "expected `#[wire(...)]`",
+                    ))
+                }
+            };
+            for nested in list.nested {
+                let meta = match nested {
+                    NestedMeta::Meta(meta) => meta,
+                    NestedMeta::Lit(lit) => {
+                        return Err(Error::new(
+                            lit.span(),
+                            "unexpected literal",
+                        ))
+                    }
+                };
+                let name = meta
+                    .path()
+                    .get_ident()
+                    .map(Ident::to_string)
+                    .unwrap_or_default();
+                match (name.as_str(), &meta) {
+                    ("le", Meta::Path(_)) => out.be = Some(false),
+                    ("be", Meta::Path(_)) => out.be = Some(true),
+                    ("remaining", Meta::Path(_)) => {
+                        out.len = Some(Len::Remaining)
+                    }
+                    ("len_prefix", Meta::NameValue(nv)) => match &nv.lit {
+                        Lit::Str(s) => out.len = Some(Len::Prefix(s.parse()?)),
+                        lit => {
+                            return Err(Error::new(
+                                lit.span(),
+                                "expected an integer type, like `\"u16\"`",
+                            ))
+                        }
+                    },
+                    ("bits", Meta::NameValue(nv)) => {
+                        out.bits = Some(parse_usize(&nv.lit)?)
+                    }
+                    ("pad", Meta::NameValue(nv)) => {
+                        out.pad = Some(parse_usize(&nv.lit)?)
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            meta.span(),
+                            "unknown `wire` attribute",
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn parse_usize(lit: &Lit) -> Result<usize, Error> {
+    match lit {
+        Lit::Int(i) => i.base10_parse(),
+        lit => Err(Error::new(lit.span(), "expected an integer")),
+    }
+}
+
+/// Returns whether `ty` is syntactically one of the unsigned integer types
+/// supported by `LeInt`.
+fn is_int(ty: &Type) -> bool {
+    ["u8", "u16", "u32", "u64"]
+        .iter()
+        .any(|int| is_named(ty, int))
+}
+
+/// Returns whether `ty` is syntactically the type named `name`.
+fn is_named(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(p) if p.qself.is_none() => p.path.is_ident(name),
+        _ => false,
+    }
+}
+
+fn classify(ty: &Type, attrs: Attrs, be: bool) -> Result<Kind, Error> {
+    // Types that pass through a `macro_rules!` matcher may arrive
+    // parenthesized or wrapped in an invisible group.
+    match ty {
+        Type::Paren(p) => return classify(&p.elem, attrs, be),
+        Type::Group(g) => return classify(&g.elem, attrs, be),
+        _ => {}
+    }
+
+    if let Some(bits) = attrs.bits {
+        if bits == 0 || bits > 8 {
+            return Err(Error::new(
+                ty.span(),
+                "`bits` must be between 1 and 8",
+            ));
+        }
+        return Ok(Kind::Bits {
+            bits,
+            pad: attrs.pad.unwrap_or(0),
+        });
+    }
+    if attrs.pad.is_some() {
+        return Err(Error::new(ty.span(), "`pad` requires `bits`"));
+    }
+
+    if let Type::Reference(r) = ty {
+        if r.mutability.is_some() {
+            return Err(Error::new(ty.span(), "expected a shared reference"));
+        }
+        let be = attrs.be.unwrap_or(be);
+        return match (&*r.elem, attrs.len) {
+            (Type::Slice(_), Some(len)) => Ok(Kind::Slice { len, be }),
+            (Type::Slice(_), None) => Err(Error::new(
+                ty.span(),
+                "slices require `#[wire(len_prefix = \"...\")]` \
+                 or `#[wire(remaining)]`",
+            )),
+            (_, Some(_)) => Err(Error::new(
+                ty.span(),
+                "lengths may only be specified for slices",
+            )),
+            (_, None) => Ok(Kind::Ref),
+        };
+    }
+    if attrs.len.is_some() {
+        return Err(Error::new(
+            ty.span(),
+            "lengths may only be specified for slices",
+        ));
+    }
+
+    if let Type::Array(a) = ty {
+        if is_named(&a.elem, "u8") {
+            return Ok(Kind::ByteArray(Box::new(a.len.clone())));
+        }
+    }
+
+    match attrs.be {
+        Some(be) => Ok(Kind::Int { be }),
+        None if is_int(ty) => Ok(Kind::Int { be }),
+        None => Ok(Kind::Nested),
+    }
+}
+
+/// Parses the fields of a struct, or rejects a non-struct input.
+fn parse_fields(
+    input: &DeriveInput,
+    trait_name: &str,
+) -> Result<Vec<Field>, Error> {
+    let data = match &input.data {
+        Data::Struct(data) => data,
+        Data::Enum(_) => {
+            return Err(Error::new(
+                input.ident.span(),
+                format!(
+                    "`{}` cannot be derived for enums; \
+                     use `#[derive(WireEnum)]` instead",
+                    trait_name
+                ),
+            ))
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                input.ident.span(),
+                format!("`{}` cannot be derived for unions", trait_name),
+            ))
+        }
+    };
+
+    let be = Attrs::parse(&input.attrs)?.be.unwrap_or(false);
+    let fields = data
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let (member, local) = match &field.ident {
+                Some(name) => {
+                    (quote!(#name), format_ident!("__field_{}", name))
+                }
+                None => {
+                    let idx = syn::Index::from(i);
+                    (quote!(#idx), format_ident!("__field_{}", i))
+                }
+            };
+            let kind = classify(&field.ty, Attrs::parse(&field.attrs)?, be)?;
+            Ok(Field {
+                member,
+                local,
+                kind,
+                span: field.span(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // Check that bit fields form whole bytes.
+    let mut bits = 0;
+    for field in &fields {
+        match field.kind {
+            Kind::Bits { bits: n, pad } => {
+                bits += n + pad;
+                if bits > 8 {
+                    return Err(Error::new(
+                        field.span,
+                        "bit fields must not straddle a byte boundary",
+                    ));
+                }
+                if bits == 8 {
+                    bits = 0;
+                }
+            }
+            _ if bits != 0 => {
+                return Err(Error::new(
+                    field.span,
+                    "preceding bit fields do not add up to a whole byte",
+                ))
+            }
+            _ => {}
+        }
+    }
+    if bits != 0 {
+        return Err(Error::new(
+            input.ident.span(),
+            "trailing bit fields do not add up to a whole byte",
+        ));
+    }
+
+    Ok(fields)
+}
+
+/// Builds the expression for constructing `Self` out of parsed fields.
+fn constructor(input: &DeriveInput, fields: &[Field]) -> TokenStream {
+    let locals = fields.iter().map(|f| &f.local);
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(_) => {
+                let members = fields.iter().map(|f| &f.member);
+                quote!(Self { #(#members: #locals,)* })
+            }
+            Fields::Unnamed(_) => quote!(Self(#(#locals,)*)),
+            Fields::Unit => quote!(Self),
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn from_wire(input: &DeriveInput) -> Result<TokenStream, Error> {
+    let fields = parse_fields(input, "FromWire")?;
+    let wire = quote!(::manticore::protocol::wire);
+
+    let mut reads = Vec::new();
+    let mut bit_buf = None;
+    for field in &fields {
+        let local = &field.local;
+        let read = match &field.kind {
+            Kind::Int { be: false } => quote! {
+                let #local = ::manticore::io::Read::read_le(&mut __wire_r)?;
+            },
+            Kind::Int { be: true } => quote! {
+                let #local = #wire::derive_support::read_be(&mut __wire_r)?;
+            },
+            Kind::ByteArray(len) => quote! {
+                let mut #local = [0u8; #len];
+                ::manticore::io::Read::read_bytes(&mut __wire_r, &mut #local)?;
+            },
+            Kind::Ref => quote! {
+                let #local =
+                    #wire::derive_support::read_ref(&mut __wire_r, __wire_arena)?;
+            },
+            Kind::Slice {
+                len: Len::Prefix(int),
+                be,
+            } => {
+                let read_len = if *be {
+                    quote!(#wire::derive_support::read_be::<#int, _>(&mut __wire_r)?)
+                } else {
+                    quote!(::manticore::io::Read::read_le::<#int>(&mut __wire_r)?)
+                };
+                quote! {
+                    let #local = {
+                        let len = #read_len;
+                        #wire::derive_support::read_slice(
+                            &mut __wire_r, __wire_arena, len as usize)?
+                    };
+                }
+            }
+            Kind::Slice {
+                len: Len::Remaining,
+                ..
+            } => quote! {
+                let #local = #wire::derive_support::read_remaining(
+                    &mut __wire_r, __wire_arena)?;
+            },
+            Kind::Bits { bits, pad } => {
+                let start = if bit_buf.is_none() {
+                    quote! {
+                        let mut __wire_bits = ::manticore::io::bit_buf::BitBuf::from_bits(
+                            ::manticore::io::Read::read_le::<u8>(&mut __wire_r)?);
+                    }
+                } else {
+                    quote!()
+                };
+                let used = bit_buf.unwrap_or(0) + bits + pad;
+                bit_buf = if used == 8 { None } else { Some(used) };
+                quote! {
+                    #start
+                    let #local = #wire::BitField::from_bits(
+                        __wire_bits.read_bits(#bits)?
+                    ).ok_or(#wire::FromWireError::OutOfRange)?;
+                    if __wire_bits.read_bits(#pad)? != 0 {
+                        return ::core::result::Result::Err(
+                            #wire::FromWireError::OutOfRange);
+                    }
+                }
+            }
+            Kind::Nested => quote! {
+                let #local = #wire::FromWire::from_wire(&mut __wire_r, __wire_arena)?;
+            },
+        };
+        reads.push(read);
+    }
+
+    let name = &input.ident;
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut lifetimes = input.generics.lifetimes();
+    let (wire_lt, impl_generics) =
+        match (lifetimes.next(), lifetimes.next()) {
+            (None, _) => {
+                let mut generics = input.generics.clone();
+                generics.params.insert(0, syn::parse_quote!('wire));
+                (quote!('wire), generics)
+            }
+            (Some(lt), None) => {
+                let lt = &lt.lifetime;
+                (quote!(#lt), input.generics.clone())
+            }
+            (Some(_), Some(lt)) => return Err(Error::new(
+                lt.span(),
+                "`FromWire` can only be derived for types with at most one \
+                 lifetime parameter",
+            )),
+        };
+    let (impl_generics, _, _) = impl_generics.split_for_impl();
+    let constructor = constructor(input, &fields);
+
+    Ok(quote! {
+        impl #impl_generics #wire::FromWire<#wire_lt> for #name #ty_generics
+            #where_clause
+        {
+            #[allow(unused_mut, unused_variables)]
+            fn from_wire<R: ::manticore::io::Read, A: ::manticore::mem::Arena>(
+                mut __wire_r: R,
+                __wire_arena: &#wire_lt A,
+            ) -> ::core::result::Result<Self, #wire::FromWireError> {
+                #(#reads)*
+                ::core::result::Result::Ok(#constructor)
+            }
+        }
+    })
+}
+
+fn to_wire(input: &DeriveInput) -> Result<TokenStream, Error> {
+    let fields = parse_fields(input, "ToWire")?;
+    let wire = quote!(::manticore::protocol::wire);
+
+    let mut writes = Vec::new();
+    let mut bit_buf = None;
+    for field in &fields {
+        let member = &field.member;
+        let write = match &field.kind {
+            Kind::Int { be: false } => quote! {
+                ::manticore::io::Write::write_le(&mut __wire_w, self.#member)?;
+            },
+            Kind::Int { be: true } => quote! {
+                #wire::derive_support::write_be(&mut __wire_w, self.#member)?;
+            },
+            Kind::ByteArray(_) => quote! {
+                ::manticore::io::Write::write_bytes(&mut __wire_w, &self.#member)?;
+            },
+            Kind::Ref => quote! {
+                #wire::derive_support::write_ref(&mut __wire_w, self.#member)?;
+            },
+            Kind::Slice {
+                len: Len::Prefix(int),
+                be,
+            } => {
+                let write_len = if *be {
+                    quote!(#wire::derive_support::write_be(&mut __wire_w, len as #int)?)
+                } else {
+                    quote!(::manticore::io::Write::write_le(&mut __wire_w, len as #int)?)
+                };
+                quote! {
+                    {
+                        let len = self.#member.len();
+                        if len > #int::max_value() as usize {
+                            return ::core::result::Result::Err(
+                                ::manticore::io::Error::BufferExhausted.into());
+                        }
+                        #write_len;
+                        #wire::derive_support::write_slice(&mut __wire_w, self.#member)?;
+                    }
+                }
+            }
+            Kind::Slice {
+                len: Len::Remaining,
+                ..
+            } => quote! {
+                #wire::derive_support::write_slice(&mut __wire_w, self.#member)?;
+            },
+            Kind::Bits { bits, pad } => {
+                let start = if bit_buf.is_none() {
+                    quote! {
+                        let mut __wire_bits = ::manticore::io::bit_buf::BitBuf::new();
+                    }
+                } else {
+                    quote!()
+                };
+                let used = bit_buf.unwrap_or(0) + bits + pad;
+                bit_buf = if used == 8 { None } else { Some(used) };
+                let end = if bit_buf.is_none() {
+                    quote! {
+                        ::manticore::io::Write::write_le(&mut __wire_w, __wire_bits.bits())?;
+                    }
+                } else {
+                    quote!()
+                };
+                quote! {
+                    #start
+                    __wire_bits.write_bits(
+                        #bits, #wire::BitField::to_bits(self.#member))?;
+                    __wire_bits.write_zero_bits(#pad)?;
+                    #end
+                }
+            }
+            Kind::Nested => quote! {
+                #wire::ToWire::to_wire(&self.#member, &mut __wire_w)?;
+            },
+        };
+        writes.push(write);
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #wire::ToWire for #name #ty_generics #where_clause {
+            #[allow(unused_mut, unused_variables)]
+            fn to_wire<W: ::manticore::io::Write>(
+                &self,
+                mut __wire_w: W,
+            ) -> ::core::result::Result<(), #wire::ToWireError> {
+                #(#writes)*
+                ::core::result::Result::Ok(())
+            }
+        }
+    })
+}
+
+fn wire_enum(input: &DeriveInput) -> Result<TokenStream, Error> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "`WireEnum` can only be derived for enums",
+            ))
+        }
+    };
+
+    let mut repr = None;
+    for attr in &input.attrs {
+        if !attr.path.is_ident("repr") {
+            continue;
+        }
+        if let Meta::List(list) = attr.parse_meta()? {
+            for nested in list.nested {
+                if let NestedMeta::Meta(Meta::Path(path)) = nested {
+                    if let Some(ident) = path.get_ident() {
+                        if ["u8", "u16", "u32", "u64"]
+                            .iter()
+                            .any(|int| ident == int)
+                        {
+                            repr = Some(ident.clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let repr = repr.ok_or_else(|| {
+        Error::new(
+            input.ident.span(),
+            "`WireEnum` requires a `#[repr(uN)]` attribute",
+        )
+    })?;
+
+    let mut variants = Vec::new();
+    let mut values = Vec::new();
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new(
+                variant.span(),
+                "`WireEnum` variants may not have fields",
+            ));
+        }
+        match &variant.discriminant {
+            Some((_, value)) => values.push(value),
+            None => {
+                return Err(Error::new(
+                    variant.span(),
+                    "`WireEnum` variants require explicit discriminants",
+                ))
+            }
+        }
+        variants.push(&variant.ident);
+    }
+    let names = variants.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+    let wire = quote!(::manticore::protocol::wire);
+
+    Ok(quote! {
+        impl #impl_generics #wire::WireEnum for #name #ty_generics #where_clause {
+            type Wire = #repr;
+            fn to_wire_value(self) -> Self::Wire {
+                self as #repr
+            }
+            fn from_wire_value(wire: Self::Wire) -> ::core::option::Option<Self> {
+                #(
+                    if wire == (#values) {
+                        return ::core::option::Option::Some(Self::#variants);
+                    }
+                )*
+                ::core::option::Option::None
+            }
+            fn name(self) -> &'static str {
+                match self {
+                    #(Self::#variants => #names,)*
+                }
+            }
+            fn from_name(name: &str) -> ::core::option::Option<Self> {
+                match name {
+                    #(#names => ::core::option::Option::Some(Self::#variants),)*
+                    _ => ::core::option::Option::None,
+                }
+            }
+        }
+
+        impl #impl_generics ::core::fmt::Display for #name #ty_generics #where_clause {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                f.write_str(#wire::WireEnum::name(*self))
+            }
+        }
+
+        impl #impl_generics ::core::str::FromStr for #name #ty_generics #where_clause {
+            type Err = #wire::WireEnumFromStrError;
+            fn from_str(s: &str) -> ::core::result::Result<Self, Self::Err> {
+                #wire::WireEnum::from_name(s).ok_or(#wire::WireEnumFromStrError)
+            }
+        }
+    })
+}
+
+fn fuzz_safe(input: &DeriveInput) -> Result<TokenStream, Error> {
+    let data = match &input.data {
+        Data::Struct(data) => data,
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "`FuzzSafe` can only be derived for structs",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let lifetime = match input.generics.lifetimes().count() {
+        0 => {
+            return Ok(quote! {
+                #[cfg(feature = "arbitrary-derive")]
+                impl ::manticore::protocol::FuzzSafe for #name {
+                    type Safe = Self;
+                }
+            })
+        }
+        1 => &input.generics.lifetimes().next().unwrap().lifetime,
+        _ => {
+            return Err(Error::new(
+                input.generics.span(),
+                "`FuzzSafe` supports at most one lifetime parameter",
+            ))
+        }
+    };
+    if input.generics.type_params().count() != 0 {
+        return Err(Error::new(
+            input.generics.span(),
+            "`FuzzSafe` does not support type parameters",
+        ));
+    }
+
+    let fields = match &data.fields {
+        Fields::Named(fields) => &fields.named,
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "`FuzzSafe` requires named fields to borrow",
+            ))
+        }
+    };
+
+    let vis = &input.vis;
+    let safe = format_ident!("{}FuzzSafe", name);
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    let mut borrows = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let (ty, borrow) = match unparen(&field.ty) {
+            Type::Reference(r) => match unparen(&r.elem) {
+                Type::Slice(s) => {
+                    let elem = &s.elem;
+                    (quote!(::std::boxed::Box<[#elem]>), quote!(&self.#ident))
+                }
+                elem if is_named(elem, "str") => {
+                    (quote!(::std::boxed::Box<str>), quote!(&self.#ident))
+                }
+                elem => (quote!(#elem), quote!(&self.#ident)),
+            },
+            ty => (quote!(#ty), quote!(self.#ident)),
+        };
+        names.push(ident);
+        types.push(ty);
+        borrows.push(borrow);
+    }
+
+    Ok(quote! {
+        #[cfg(feature = "arbitrary-derive")]
+        #[derive(Clone, Debug, Arbitrary)]
+        #[doc(hidden)]
+        #vis struct #safe {
+            #(#names: #types,)*
+        }
+
+        #[cfg(feature = "arbitrary-derive")]
+        impl #safe {
+            /// Borrows this value as the message it stands in for.
+            pub fn as_ref<#lifetime>(&#lifetime self) -> #name<#lifetime> {
+                #name {
+                    #(#names: #borrows,)*
+                }
+            }
+        }
+
+        #[cfg(feature = "arbitrary-derive")]
+        impl<#lifetime> ::manticore::protocol::FuzzSafe for #name<#lifetime> {
+            type Safe = #safe;
+        }
+    })
+}
+
+/// Strips any parentheses or invisible groups around `ty`.
+fn unparen(ty: &Type) -> &Type {
+    match ty {
+        Type::Paren(p) => unparen(&p.elem),
+        Type::Group(g) => unparen(&g.elem),
+        ty => ty,
+    }
+}
@@ -5,9 +5,15 @@
 
 """
 Regenerates fuzz tests for boilerplate protocol tests by looking at the
-`proto_fuzz.txt` file, which is a file containing newline-delimited Rust
+`proto_types.txt` file, which is a file containing newline-delimited Rust
 paths, which refer to implementations of `manticore::protocol::Command`.
 
+Additional files in the same format can be passed with `--types`, e.g., to
+generate targets for vendor-defined commands that live in another crate. That
+crate must be added as a dependency in `fuzz/Cargo.toml`, above the generated
+section, and its request and response types must implement
+`manticore::protocol::FuzzSafe`, usually via `#[derive(FuzzSafe)]`.
+
 This script exists to minimize the boilerplate of doing so, seeing as such
 targets tend to be quite simple.
 """
@@ -54,6 +60,19 @@ def reset_generated_config(path, delim):
   return text[:text.index(delim)] + delim + '\n'
 
 def main(argv):
+  parser = argparse.ArgumentParser(description = __doc__)
+  parser.add_argument('--types', action = 'append', type = Path,
+      default = [], metavar = 'FILE',
+      help = 'additional files listing `Command` types to generate tests for')
+  args = parser.parse_args(argv[1:])
+
+  types = []
+  for config in [FUZZ_CONFIG] + args.types:
+    for ty in config.read_text().split('\n'):
+      if not ty or ty.startswith('#'):
+        continue
+      types.append(ty)
+
   eprint(f'Regenerating {FUZZ_GEN}')
   shutil.rmtree(str(FUZZ_GEN))
   FUZZ_GEN.mkdir()
@@ -65,9 +84,7 @@ def main(argv):
   yml_tpl = YML_TPL.read_text()
 
   count = 0
-  for ty in FUZZ_CONFIG.read_text().split('\n'):
-    if not ty or ty.startswith('#'):
-      continue
+  for ty in types:
     eprint(f'Generating tests for {ty}...')
 
     for tpl in FUZZ_TEMPLATES.iterdir():
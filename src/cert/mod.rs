@@ -205,7 +205,14 @@ pub enum PublicKeyParams<'cert> {
     },
 }
 
-impl PublicKeyParams<'_> {
+impl<'cert> PublicKeyParams<'cert> {
+    /// Parses a DER-encoded X.509 `SubjectPublicKeyInfo`, such as a
+    /// standalone public key file.
+    pub fn from_spki(spki: &'cert [u8]) -> Result<Self, Error> {
+        untrusted::Input::from(spki)
+            .read_all(Error::BadEncoding, x509::parse_spki)
+    }
+
     /// Returns whether these parameters are appropriate for the given
     /// algorithm.
     pub fn is_params_for(&self, algo: Algo) -> bool {
@@ -41,6 +41,39 @@ fn parse_algo(buf: &mut untrusted::Reader) -> Result<Algo, Error> {
     }
 }
 
+/// Parses an X.509 `SubjectPublicKeyInfo`.
+pub fn parse_spki<'cert>(
+    buf: &mut untrusted::Reader<'cert>,
+) -> Result<PublicKeyParams<'cert>, Error> {
+    der::tagged(Tag::SEQUENCE, buf, |buf| {
+        let (algo, aparams) = der::tagged(Tag::SEQUENCE, buf, |buf| {
+            let algo = der::oid(buf)?;
+            let aparams = buf.read_bytes_to_end();
+            Ok((algo, aparams))
+        })?;
+
+        der::bits_total(buf)?.read_all(Error::BadEncoding, |buf| match algo {
+            oid::RSA_ENCRYPTION => {
+                aparams.read_all(Error::BadEncoding, der::null)?;
+                der::tagged(Tag::SEQUENCE, buf, |buf| {
+                    let mut modulus = der::uint(buf)?.as_slice_less_safe();
+                    // DER inserts a leading zero sometimes (to disambiguate
+                    // negative integers) so we need to remove it.
+                    if modulus[0] == 0 {
+                        modulus = &modulus[1..];
+                    }
+                    let mut exponent = der::uint(buf)?.as_slice_less_safe();
+                    if exponent[0] == 0 {
+                        exponent = &exponent[1..];
+                    }
+                    Ok(PublicKeyParams::Rsa { modulus, exponent })
+                })
+            }
+            _ => Err(Error::UnknownAlgorithm),
+        })
+    })
+}
+
 /// Parses an X.509 certificate.
 ///
 /// This function performs several aggressive checks to reject any and all
@@ -143,33 +176,7 @@ fn parse_tbs<'cert>(
     // The subject is also opaque
     let subject = Name(der::parse(Tag::SEQUENCE, buf)?.as_slice_less_safe());
 
-    let subject_key = der::tagged(Tag::SEQUENCE, buf, |buf| {
-        let (algo, aparams) = der::tagged(Tag::SEQUENCE, buf, |buf| {
-            let algo = der::oid(buf)?;
-            let aparams = buf.read_bytes_to_end();
-            Ok((algo, aparams))
-        })?;
-
-        der::bits_total(buf)?.read_all(Error::BadEncoding, |buf| match algo {
-            oid::RSA_ENCRYPTION => {
-                aparams.read_all(Error::BadEncoding, der::null)?;
-                der::tagged(Tag::SEQUENCE, buf, |buf| {
-                    let mut modulus = der::uint(buf)?.as_slice_less_safe();
-                    // DER inserts a leading zero sometimes (to disambiguate
-                    // negative integers) so we need to remove it.
-                    if modulus[0] == 0 {
-                        modulus = &modulus[1..];
-                    }
-                    let mut exponent = der::uint(buf)?.as_slice_less_safe();
-                    if exponent[0] == 0 {
-                        exponent = &exponent[1..];
-                    }
-                    Ok(PublicKeyParams::Rsa { modulus, exponent })
-                })
-            }
-            _ => Err(Error::UnknownAlgorithm),
-        })
-    })?;
+    let subject_key = parse_spki(buf)?;
 
     // We don't care about the UIDs at all.
     let _issuer_uid = der::opt(Tag::context_specific(1), buf)?;
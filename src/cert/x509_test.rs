@@ -71,10 +71,7 @@ impl cert::Ciphers for RingCiphers {
                 .unwrap();
                 let rsa = ring::rsa::Builder::new();
                 self.verifier = Some(Box::new(rsa.new_verifier(key).unwrap()));
-                match &mut self.verifier {
-                    Some(x) => Some(&mut **x),
-                    None => None,
-                }
+                self.verifier.as_deref_mut().map(|x| x as _)
             }
         }
     }
@@ -92,3 +89,29 @@ fn self_signed() {
     assert!(cert.is_explicit_ca_cert());
     assert!(!cert.is_within_path_len_constraint(2));
 }
+
+#[test]
+fn spki() {
+    let spki = include_bytes!("../crypto/testdata/rsa_2048_public_key.pk8");
+    let key = PublicKeyParams::from_spki(spki).unwrap();
+
+    let data = testdata::X509_SELF_SIGNED.as_slice_less_safe();
+    let cert =
+        Cert::parse(data, CertFormat::RiotX509, None, &mut RingCiphers::new())
+            .unwrap();
+    match (key, cert.subject_key()) {
+        (
+            PublicKeyParams::Rsa { modulus, exponent },
+            PublicKeyParams::Rsa {
+                modulus: m,
+                exponent: e,
+            },
+        ) => {
+            assert_eq!(modulus.len(), 256);
+            assert_eq!(modulus, *m);
+            assert_eq!(exponent, *e);
+        }
+    }
+
+    assert!(PublicKeyParams::from_spki(&spki[1..]).is_err());
+}
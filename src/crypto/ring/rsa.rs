@@ -6,12 +6,16 @@
 //!
 //! Requires the `std` feature flag to be enabled.
 
+use core::task::Context;
+use core::task::Poll;
+
 use ring::error::Unspecified;
 use ring::signature::KeyPair as _;
 use ring::signature::RsaPublicKeyComponents;
 
 use crate::crypto::rsa;
 use crate::crypto::sig;
+use crate::manifest;
 
 #[cfg(doc)]
 use crate::crypto;
@@ -156,6 +160,27 @@ impl sig::Verify for Verify256 {
 }
 impl sig::VerifyFor<rsa::RsaPkcs1Sha256> for Verify256 {}
 
+/// `ring` verifies synchronously, so verification is always complete on the
+/// first poll.
+impl sig::AsyncVerify for Verify256 {
+    fn poll_verify(
+        &mut self,
+        _: &mut Context,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Poll<Result<(), sig::VerifyError<Self>>> {
+        use crate::crypto::sig::Verify as _;
+        Poll::Ready(self.verify(signature, message))
+    }
+}
+
+impl manifest::HasSigType for Verify256 {
+    fn sig_type(&self) -> manifest::SigType {
+        use crate::crypto::rsa::PublicKey as _;
+        manifest::SigType::rsa(self.key.len())
+    }
+}
+
 /// A `ring`-based [`sig::Sign`] for PKCS#1.5 RSA using SHA-256.
 pub struct Sign256 {
     keypair: KeyPair,
@@ -184,6 +209,13 @@ impl sig::Sign for Sign256 {
 }
 impl sig::SignFor<rsa::RsaPkcs1Sha256> for Sign256 {}
 
+impl manifest::HasSigType for Sign256 {
+    fn sig_type(&self) -> manifest::SigType {
+        use crate::crypto::rsa::KeyPair as _;
+        manifest::SigType::rsa(self.keypair.pub_len())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
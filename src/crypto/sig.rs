@@ -4,6 +4,11 @@
 
 //! Algorithm-generic signature traits.
 
+use core::task::Context;
+use core::task::Poll;
+
+use crate::crypto::sha256;
+
 /// An error returned by a signature operation.
 ///
 /// This type serves as a combination of built-in error types known to
@@ -63,6 +68,57 @@ pub trait Verify {
 /// an operation.
 pub trait VerifyFor<Algo>: Verify {}
 
+/// A [`Verify`] that can check a signature against a message that has already
+/// been hashed.
+///
+/// This allows a caller to stream a large message through a
+/// [`sha256::Hasher`] in small pieces, rather than needing to hold all of it
+/// in memory at once.
+///
+/// `ring` can only verify signatures over messages it hashes itself, so the
+/// `ring`-based verifiers do not implement this trait; it is intended for
+/// hardware engines that accept a pre-computed digest.
+pub trait VerifyDigest: Verify {
+    /// Uses this engine to verify `signature` against a message whose SHA-256
+    /// hash is `digest`.
+    ///
+    /// For any `message`, this function must behave exactly as
+    /// `verify(signature, message)` would, if passed `digest` equal to the
+    /// hash of `message`.
+    fn verify_digest(
+        &mut self,
+        signature: &[u8],
+        digest: &sha256::Digest,
+    ) -> Result<(), VerifyError<Self>>;
+}
+
+/// A [`Verify`] that can check a signature without blocking, such as a
+/// hardware engine that signals completion with an interrupt.
+///
+/// This allows a caller running on an async executor to await a
+/// long-running verification, rather than stalling the executor in
+/// [`Verify::verify()`].
+///
+/// Verifiers that always finish promptly can implement this trait by
+/// returning the result of `verify()` immediately.
+pub trait AsyncVerify: Verify {
+    /// Polls for the result of verifying `signature` against `message`.
+    ///
+    /// The first call begins verification; subsequent calls, which must pass
+    /// the same `signature` and `message`, poll for its completion. Once this
+    /// function returns `Poll::Ready`, the next call begins a new
+    /// verification.
+    ///
+    /// The result must be the same as what `verify(signature, message)` would
+    /// return.
+    fn poll_verify(
+        &mut self,
+        cx: &mut Context,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Poll<Result<(), VerifyError<Self>>>;
+}
+
 /// An signing engine, already primed with a keypair.
 ///
 /// There is no way to extract the keypair back out of a `Sign` value.
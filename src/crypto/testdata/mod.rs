@@ -8,9 +8,22 @@
 //! only. In particular, we keep the private key around to make it possible to
 //! re-sign test data to reduce the brittleness of tests.
 
+use crate::cert::PublicKeyParams;
 use crate::crypto::ring;
 use crate::crypto::rsa::Builder as _;
 use crate::crypto::rsa::KeyPair as _;
+use crate::crypto::rsa::ModulusLength;
+use crate::crypto::sha256;
+use crate::crypto::sha256::Builder as _;
+use crate::crypto::sig;
+use crate::crypto::sig::Sign as _;
+use crate::crypto::sig::VerifyDigest as _;
+use crate::io;
+use crate::manifest::HasSigType;
+use crate::manifest::SigType;
+use crate::protocol::key_set_update::KeyOp;
+use crate::protocol::key_set_update::KeySetUpdateRequest;
+use crate::protocol::wire::ToWire as _;
 
 /// A plaintext string.
 pub const PLAIN_TEXT: &[u8] = include_bytes!("plain.txt");
@@ -30,6 +43,10 @@ pub const PLAIN_SHA256: &[u8] = include_bytes!("plain_sha256.bin");
 pub const RSA_2048_PRIV_PKCS8: &[u8] =
     include_bytes!("rsa_2048_private_key.pk8");
 
+/// The public half of `RSA_2048_PRIV_PKCS8`, as a DER-encoded X.509
+/// `SubjectPublicKeyInfo`.
+pub const RSA_2048_PUB_SPKI: &[u8] = include_bytes!("rsa_2048_public_key.pk8");
+
 /// An RSA signature for `PLAIN_TEXT`, using `RSA_2048_PRIV_PKCS8` as the
 /// signing key.
 ///
@@ -46,3 +63,111 @@ pub fn rsa() -> (ring::rsa::Verify256, ring::rsa::Sign256) {
     let signer = rsa_builder.new_signer(keypair).unwrap();
     (rsa, signer)
 }
+
+/// Builds the wire encoding of a `KeySetUpdate` request, signed by the key
+/// returned by [`rsa()`] on behalf of `signer_id`.
+///
+/// Requests that add a key add the public half of that same key.
+pub fn signed_key_set_update(op: KeyOp, key_id: u8, signer_id: u8) -> Vec<u8> {
+    let (_, mut signer) = rsa();
+    let key = match op {
+        KeyOp::Add => RSA_2048_PUB_SPKI,
+        KeyOp::Revoke => &[],
+    };
+
+    let mut bytes = Vec::new();
+    KeySetUpdateRequest {
+        op,
+        key_id,
+        signer_id,
+        key,
+        signature: &[],
+    }
+    .to_wire(io::write::StdWrite(&mut bytes))
+    .unwrap();
+
+    let mut signature = vec![0; signer.sig_bytes()];
+    signer.sign(&bytes, &mut signature).unwrap();
+    bytes.extend_from_slice(&signature);
+    bytes
+}
+
+/// Parses an RSA public key, as a DER-encoded `SubjectPublicKeyInfo`, into a
+/// verifier.
+pub fn parse_rsa_key(spki: &[u8]) -> Option<ring::rsa::Verify256> {
+    let PublicKeyParams::Rsa { modulus, exponent } =
+        PublicKeyParams::from_spki(spki).ok()?;
+    let key = ring::rsa::PublicKey::new(modulus.into(), exponent.into())?;
+    ring::rsa::Builder::new().new_verifier(key).ok()
+}
+
+/// A fake signer and verifier, for exercising code that needs a
+/// [`sig::VerifyDigest`], which `ring` cannot provide.
+///
+/// A "signature" is the SHA-256 hash of the message, zero-padded to the
+/// length of a 2048-bit RSA signature.
+pub struct FakeDigestSig;
+
+impl FakeDigestSig {
+    fn expected(digest: &sha256::Digest) -> Vec<u8> {
+        let mut sig = vec![0; ModulusLength::Bits2048.byte_len()];
+        sig[..digest.len()].copy_from_slice(digest);
+        sig
+    }
+}
+
+impl sig::Verify for FakeDigestSig {
+    type Error = ();
+
+    fn verify(
+        &mut self,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Result<(), sig::VerifyError<Self>> {
+        let mut digest = [0; 32];
+        ring::sha256::Builder::new()
+            .hash_contiguous(message, &mut digest)
+            .map_err(|_| sig::Error::Custom(()))?;
+        self.verify_digest(signature, &digest)
+    }
+}
+
+impl sig::VerifyDigest for FakeDigestSig {
+    fn verify_digest(
+        &mut self,
+        signature: &[u8],
+        digest: &sha256::Digest,
+    ) -> Result<(), sig::VerifyError<Self>> {
+        if signature != Self::expected(digest).as_slice() {
+            return Err(sig::Error::Custom(()));
+        }
+        Ok(())
+    }
+}
+
+impl sig::Sign for FakeDigestSig {
+    type Error = ();
+
+    fn sig_bytes(&self) -> usize {
+        ModulusLength::Bits2048.byte_len()
+    }
+
+    fn sign(
+        &mut self,
+        message: &[u8],
+        signature: &mut [u8],
+    ) -> Result<(), sig::SignError<Self>> {
+        let mut digest = [0; 32];
+        ring::sha256::Builder::new()
+            .hash_contiguous(message, &mut digest)
+            .map_err(|_| sig::Error::Custom(()))?;
+        signature.copy_from_slice(&Self::expected(&digest));
+        Ok(())
+    }
+}
+
+impl HasSigType for FakeDigestSig {
+    fn sig_type(&self) -> SigType {
+        SigType::rsa(ModulusLength::Bits2048)
+    }
+}
@@ -29,6 +29,14 @@ pub trait Identity {
     /// Returns the "unique device identity" for the device. This is a binary
     /// value of unspecified format.
     fn unique_device_identity(&self) -> &[u8];
+
+    /// Returns the Platform ID of the platform this RoT protects, as it
+    /// appears in PFMs intended for it.
+    ///
+    /// If this returns `None`, PFMs are not checked against the platform.
+    fn platform_id(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
 /// Provides access to device reset-related information for a particular
@@ -57,6 +65,7 @@ pub(crate) mod fake {
         firmware_version: Vec<u8>,
         vendor_firmware_versions: HashMap<u8, Vec<u8>>,
         unique_id: Vec<u8>,
+        platform_id: Option<Vec<u8>>,
     }
 
     impl Identity {
@@ -83,8 +92,15 @@ pub(crate) mod fake {
                     .map(|(key, value)| (*key, pad_to_32(value)))
                     .collect(),
                 unique_id: unique_id.to_vec(),
+                platform_id: None,
             }
         }
+
+        /// Sets the Platform ID this `fake::Identity` reports.
+        pub fn with_platform_id(mut self, platform_id: &[u8]) -> Self {
+            self.platform_id = Some(platform_id.to_vec());
+            self
+        }
     }
 
     impl super::Identity for Identity {
@@ -99,6 +115,9 @@ pub(crate) mod fake {
         fn unique_device_identity(&self) -> &[u8] {
             &self.unique_id[..]
         }
+        fn platform_id(&self) -> Option<&[u8]> {
+            self.platform_id.as_deref()
+        }
     }
 
     /// A fake `Reset` that returns fixed values.
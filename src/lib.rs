@@ -47,6 +47,13 @@
 #![deny(warnings)]
 #![deny(unused)]
 #![deny(unsafe_code)]
+// Tests spell out wire bytes with digit groups that follow the fields they
+// encode, which newer versions of Clippy object to.
+#![cfg_attr(test, allow(clippy::unusual_byte_groupings))]
+
+// Allows code generated by `manticore-derive` to refer to this crate as
+// `::manticore` from within this crate, too.
+extern crate self as manticore;
 
 #[macro_use]
 pub mod protocol;
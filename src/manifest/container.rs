@@ -12,6 +12,7 @@ use core::mem;
 use zerocopy::AsBytes;
 use zerocopy::FromBytes;
 
+use crate::crypto::rsa;
 use crate::crypto::sha256;
 use crate::crypto::sha256::Hasher as _;
 use crate::crypto::sig;
@@ -20,6 +21,7 @@ use crate::hardware::flash::FlashExt as _;
 use crate::hardware::flash::FlashIo;
 use crate::hardware::flash::Region;
 use crate::io::Read as _;
+use crate::manifest::key_set::KeySet;
 use crate::manifest::provenance;
 use crate::manifest::Error;
 use crate::manifest::Manifest;
@@ -44,6 +46,12 @@ pub struct Metadata {
     /// When minting a new manifest, a signing authority should make sure to
     /// bump this value.
     pub version_id: u32,
+
+    /// The ID of the key that signed this manifest.
+    ///
+    /// See [`KeySet`](crate::manifest::key_set::KeySet).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub key_id: u8,
 }
 
 wire_enum! {
@@ -60,6 +68,69 @@ wire_enum! {
     }
 }
 
+wire_enum! {
+    /// A signature type for a manifest, describing the algorithm used to sign
+    /// it.
+    ///
+    /// On the wire, bits `7:6` encode the key type, bits `5:3` the key
+    /// strength, and bits `2:0` the hash type, as a [`HashType`]. All of the
+    /// signature types Manticore supports use SHA-256.
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum SigType: u8 {
+        /// PKCS#1 v1.5 RSA, with a 2048-bit modulus.
+        Rsa2048 = 0x00,
+        /// PKCS#1 v1.5 RSA, with a 3072-bit modulus.
+        Rsa3072 = 0x08,
+        /// PKCS#1 v1.5 RSA, with a 4096-bit modulus.
+        Rsa4096 = 0x10,
+        /// ECDSA over the NIST P-256 curve.
+        EcdsaP256 = 0x40,
+        /// ECDSA over the NIST P-384 curve.
+        EcdsaP384 = 0x48,
+    }
+}
+
+impl SigType {
+    /// Returns the `SigType` for PKCS#1 v1.5 RSA signatures with a modulus of
+    /// the given length.
+    pub fn rsa(len: rsa::ModulusLength) -> Self {
+        match len {
+            rsa::ModulusLength::Bits2048 => Self::Rsa2048,
+            rsa::ModulusLength::Bits3072 => Self::Rsa3072,
+            rsa::ModulusLength::Bits4096 => Self::Rsa4096,
+        }
+    }
+
+    /// Returns the length of a signature of this type, if all such signatures
+    /// have the same length.
+    ///
+    /// ECDSA signatures are DER-encoded, so their length varies.
+    pub fn sig_len(self) -> Option<usize> {
+        match self {
+            Self::Rsa2048 => Some(rsa::ModulusLength::Bits2048.byte_len()),
+            Self::Rsa3072 => Some(rsa::ModulusLength::Bits3072.byte_len()),
+            Self::Rsa4096 => Some(rsa::ModulusLength::Bits4096.byte_len()),
+            Self::EcdsaP256 | Self::EcdsaP384 => None,
+        }
+    }
+}
+
+/// The length of the longest signature any [`SigType`] can have.
+const MAX_SIG_LEN: usize = 512;
+
+/// A signature engine that produces or checks signatures of a particular
+/// [`SigType`].
+///
+/// Manifests record the algorithm they were signed with: this trait allows
+/// [`owned::Container::sign()`] to record it, and
+/// [`Container::parse_and_verify()`] to check that it matches the verifier's.
+///
+/// [`owned::Container::sign()`]: crate::manifest::owned::Container::sign
+pub trait HasSigType {
+    /// Returns the type of signature this engine produces or checks.
+    fn sig_type(&self) -> SigType;
+}
+
 /// A TOC entry's raw bits.
 #[derive(Copy, Clone, PartialEq, Eq, Default, Debug, AsBytes, FromBytes)]
 #[repr(C)]
@@ -89,7 +160,7 @@ pub struct TocEntry<'entry, 'toc, M> {
 
 impl<'entry, 'toc, M: Manifest> TocEntry<'entry, 'toc, M> {
     #[inline]
-    fn raw(self) -> &'toc RawTocEntry {
+    pub(crate) fn raw(self) -> &'toc RawTocEntry {
         &self.toc.entries[self.index]
     }
 
@@ -303,7 +374,7 @@ pub(crate) struct RawHeader {
     pub version_id: u32,
     pub sig_len: u16,
     pub sig_ty: u8,
-    pub reserved1: u8,
+    pub key_id: u8,
 
     pub entry_count: u8,
     pub hash_count: u8,
@@ -332,21 +403,63 @@ impl<'f, M: Manifest, F: Flash> Container<'f, M, F, provenance::Signed> {
     /// This is the only function capable of prodiucing a container with the
     /// `Signed` provenance.
     ///
+    /// The manifest's [`SigType`] must match that of `sig_verify`.
+    ///
     /// `buf` must be aligned to a four-byte boundary.
     pub fn parse_and_verify(
         flash: &'f F,
         sha: &impl sha256::Builder,
-        sig_verify: &mut impl sig::Verify,
+        sig_verify: &mut (impl sig::Verify + HasSigType),
+        toc_arena: &'f impl Arena,
+        verify_arena: &impl Arena,
+    ) -> Result<Self, Error> {
+        let c = Self::parse_inner(flash, toc_arena)?;
+
+        c.verify_toc_hash(sha)?;
+        c.verify_signature(sha, sig_verify, verify_arena)?;
+
+        Ok(c)
+    }
+
+    /// Parses and verifies a `Container`, like
+    /// [`Container::parse_and_verify()`], but using the key from `keys` whose
+    /// ID is recorded in the manifest header.
+    pub fn parse_and_verify_with_keys(
+        flash: &'f F,
+        sha: &impl sha256::Builder,
+        keys: &mut impl KeySet,
         toc_arena: &'f impl Arena,
         verify_arena: &impl Arena,
     ) -> Result<Self, Error> {
         let c = Self::parse_inner(flash, toc_arena)?;
 
         c.verify_toc_hash(sha)?;
+        let sig_verify = keys.verifier(c.header.key_id)?;
         c.verify_signature(sha, sig_verify, verify_arena)?;
 
         Ok(c)
     }
+
+    /// Parses and verifies a `Container`, like
+    /// [`Container::parse_and_verify()`], but using a verifier that accepts a
+    /// pre-hashed message.
+    ///
+    /// Both functions stream the signed region out of `flash` through `sha` in
+    /// small chunks; the only difference is that this one copies the
+    /// signature onto the stack, rather than reading it into an arena.
+    pub fn parse_and_verify_digest(
+        flash: &'f F,
+        sha: &impl sha256::Builder,
+        sig_verify: &mut (impl sig::VerifyDigest + HasSigType),
+        toc_arena: &'f impl Arena,
+    ) -> Result<Self, Error> {
+        let c = Self::parse_inner(flash, toc_arena)?;
+
+        c.verify_toc_hash(sha)?;
+        c.verify_signature_digest(sha, sig_verify)?;
+
+        Ok(c)
+    }
 }
 
 impl<'f, M: Manifest, F: Flash> Container<'f, M, F, provenance::Adhoc> {
@@ -405,9 +518,54 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
     pub(crate) fn verify_signature(
         &self,
         sha: &impl sha256::Builder,
-        sig_verify: &mut impl sig::Verify,
+        sig_verify: &mut (impl sig::Verify + HasSigType),
         verify_arena: &impl Arena,
     ) -> Result<(), Error> {
+        if sig_verify.sig_type() != self.sig_type() {
+            return Err(Error::SigTypeMismatch(self.sig_type()));
+        }
+        let digest = self.signed_digest(sha)?;
+
+        let sig =
+            self.flash
+                .read_direct(self.signature_region(), verify_arena, 1)?;
+        sig_verify.verify(sig, &digest)?;
+        Ok(())
+    }
+
+    /// Verifies the signature for this `Container`, without buffering
+    /// anything but the signature itself, which is copied onto the stack.
+    pub(crate) fn verify_signature_digest(
+        &self,
+        sha: &impl sha256::Builder,
+        sig_verify: &mut (impl sig::VerifyDigest + HasSigType),
+    ) -> Result<(), Error> {
+        if sig_verify.sig_type() != self.sig_type() {
+            return Err(Error::SigTypeMismatch(self.sig_type()));
+        }
+        let digest = self.signed_digest(sha)?;
+
+        // The signature is over the digest of the signed region, rather than
+        // the signed region itself, so the verifier needs to be handed the
+        // digest of *that*.
+        let mut message_digest = [0; 32];
+        sha.hash_contiguous(&digest, &mut message_digest)?;
+
+        let mut sig = [0; MAX_SIG_LEN];
+        let sig = sig
+            .get_mut(..self.signature_region().len as usize)
+            .ok_or(Error::BadSignatureLen)?;
+        self.flash.read(self.signature_region().offset, sig)?;
+        sig_verify.verify_digest(sig, &message_digest)?;
+        Ok(())
+    }
+
+    /// Computes the SHA-256 digest of the signed region, streaming it out of
+    /// flash a few bytes at a time.
+    fn signed_digest(
+        &self,
+        sha: &impl sha256::Builder,
+    ) -> Result<sha256::Digest, Error> {
         let mut bytes = [0u8; 16];
         let signed_region = self.signed_region();
         let mut r = FlashIo::new(&self.flash)?;
@@ -422,12 +580,7 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
 
         let mut digest = [0; 32];
         hasher.finish(&mut digest)?;
-
-        let sig =
-            self.flash
-                .read_direct(self.signature_region(), verify_arena, 1)?;
-        sig_verify.verify(sig, &digest)?;
-        Ok(())
+        Ok(digest)
     }
 
     /// Performs a parse without verifying the signature, returning the
@@ -437,7 +590,6 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
         flash: &'f F,
         toc_arena: &'f impl Arena,
     ) -> Result<Self, Error> {
-        // TODO(#58): Manticore currently ignores header.sig_type.
         let header = flash.read_object::<RawHeader>(0, toc_arena)?;
 
         if ManifestType::from_wire_value(header.manifest_type) != Some(M::TYPE)
@@ -449,6 +601,15 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
             return Err(Error::OutOfRange);
         }
 
+        let sig_type =
+            SigType::from_wire_value(header.sig_ty).ok_or(Error::OutOfRange)?;
+        match sig_type.sig_len() {
+            Some(len) if len != header.sig_len as usize => {
+                return Err(Error::BadSignatureLen)
+            }
+            _ => {}
+        }
+
         // TODO(#57): we don't deal with hash types that aren't SHA-256.
         match HashType::from_wire_value(header.hash_type) {
             Some(HashType::Sha256) => {}
@@ -457,7 +618,7 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
         }
 
         // Unused values are currently required to be zeroed by the spec.
-        if header.reserved1 != 0 || header.reserved2 != 0 {
+        if header.reserved2 != 0 {
             return Err(Error::OutOfRange);
         }
 
@@ -496,6 +657,12 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
             .expect("verified in parse_inner()")
     }
 
+    /// Returns the [`SigType`] this `Container` claims to be signed with.
+    pub fn sig_type(&self) -> SigType {
+        SigType::from_wire_value(self.header.sig_ty)
+            .expect("verified in parse_inner()")
+    }
+
     /// Checks whether this `Container` can replace `other`.
     ///
     /// In other words, `self` must:
@@ -510,6 +677,7 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
     pub fn metadata(&self) -> Metadata {
         Metadata {
             version_id: self.header.version_id,
+            key_id: self.header.key_id,
         }
     }
 
@@ -550,6 +718,96 @@ pub(crate) mod test {
 
     use serde_json::from_str;
 
+    #[test]
+    fn sig_type() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": []
+        }"#).unwrap();
+        let mut bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+        type Flash = Ram<Vec<u8>>;
+
+        let container: Container<'_, Pfm, Flash> = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        assert_eq!(container.sig_type(), SigType::Rsa2048);
+
+        // The signature is only checked after the signature type, so
+        // corrupting the type is detected as a mismatch.
+        bytes.0[10] = SigType::EcdsaP256.to_wire_value();
+        let result: Result<Container<'_, Pfm, Flash>, _> =
+            Container::parse_and_verify(
+                &bytes,
+                &sha,
+                &mut rsa,
+                &OutOfMemory,
+                &OutOfMemory,
+            );
+        assert!(matches!(
+            result,
+            Err(Error::SigTypeMismatch(SigType::EcdsaP256))
+        ));
+
+        // RSA signatures must be exactly as long as the modulus.
+        bytes.0[10] = SigType::Rsa3072.to_wire_value();
+        let result = Container::<'_, Pfm, Flash, provenance::Adhoc>::parse(
+            &bytes,
+            &OutOfMemory,
+        );
+        assert!(matches!(result, Err(Error::BadSignatureLen)));
+
+        bytes.0[10] = 0x07;
+        let result = Container::<'_, Pfm, Flash, provenance::Adhoc>::parse(
+            &bytes,
+            &OutOfMemory,
+        );
+        assert!(matches!(result, Err(Error::OutOfRange)));
+    }
+
+    #[test]
+    fn verify_digest() {
+        let sha = ring::sha256::Builder::new();
+        let mut signer = testdata::FakeDigestSig;
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "blah" }]
+        }"#).unwrap();
+        let mut bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+        type Flash = Ram<Vec<u8>>;
+
+        let container: Container<'_, Pfm, Flash> =
+            Container::parse_and_verify_digest(
+                &bytes,
+                &sha,
+                &mut signer,
+                &OutOfMemory,
+            )
+            .unwrap();
+        assert_eq!(container.metadata().version_id, 42);
+
+        let sig_start = container.signature_region().offset as usize;
+        bytes.0[sig_start] ^= 1;
+        let result: Result<Container<'_, Pfm, Flash>, _> =
+            Container::parse_and_verify_digest(
+                &bytes,
+                &sha,
+                &mut signer,
+                &OutOfMemory,
+            );
+        assert!(matches!(result, Err(Error::SignatureFailure)));
+    }
+
     // NOTE: To effectively run these tests, we use PFM-from-JSON to generate
     // some of the tests, but they're intended to be independent of the actual
     // manifest type.
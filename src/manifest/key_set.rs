@@ -0,0 +1,468 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sets of keys trusted to sign manifests.
+//!
+//! Every manifest header records the ID of the key that signed it. A
+//! [`KeySet`] maps these IDs to signature verifiers, so that a device can
+//! trust several signing keys at once, such as while a fleet is rotating
+//! keys; [`Container::parse_and_verify_with_keys()`] uses it to pick the key
+//! to verify a manifest with.
+//!
+//! [`KeyRing`] is a simple `KeySet` that supports revocation, and which can
+//! be updated with a [`KeySetUpdate`] request.
+//!
+//! [`Container::parse_and_verify_with_keys()`]:
+//!     crate::manifest::Container::parse_and_verify_with_keys
+//! [`KeySetUpdate`]: crate::protocol::KeySetUpdate
+
+use core::task::Context;
+
+use crate::crypto::sig;
+use crate::io;
+use crate::manifest::Error;
+use crate::manifest::HasSigType;
+use crate::mem::Arena;
+use crate::mem::ArenaExt as _;
+use crate::net::asynch::PollFn;
+use crate::protocol::key_set_update::KeyOp;
+use crate::protocol::key_set_update::KeySetUpdateRequest;
+use crate::protocol::wire::ToWire;
+use crate::protocol::wire::ToWireError;
+
+/// A set of keys trusted to sign manifests, indexed by key ID.
+pub trait KeySet {
+    /// The verifier for keys in this set.
+    type Verify: sig::Verify + HasSigType;
+
+    /// Returns a verifier for the key with the given ID.
+    ///
+    /// Returns [`Error::UnknownKey`] if there is no such key, and
+    /// [`Error::RevokedKey`] if it has been revoked.
+    fn verifier(&mut self, key_id: u8) -> Result<&mut Self::Verify, Error>;
+}
+
+/// A [`KeySet`] stored in caller-provided memory, which supports revoking
+/// keys.
+///
+/// Key IDs are never reused: once a key is revoked, no other key can take its
+/// ID. This ensures that replaying an old update cannot resurrect a key.
+///
+/// A `KeyRing` lives only in memory; it is up to the integration to persist
+/// its contents across resets.
+pub struct KeyRing<'a, V> {
+    keys: &'a mut [Option<(u8, V)>],
+    revoked: [u8; 32],
+}
+
+impl<'a, V> KeyRing<'a, V> {
+    /// Creates a new `KeyRing`, using `keys` as storage.
+    ///
+    /// Any keys already in `keys` are trusted; empty slots may be filled by
+    /// [`KeyRing::insert()`].
+    pub fn new(keys: &'a mut [Option<(u8, V)>]) -> Self {
+        Self {
+            keys,
+            revoked: [0; 32],
+        }
+    }
+
+    /// Returns whether this ring holds no trusted keys.
+    pub fn is_empty(&self) -> bool {
+        self.keys.iter().all(Option::is_none)
+    }
+
+    /// Returns whether the key with the given ID has been revoked.
+    pub fn is_revoked(&self, key_id: u8) -> bool {
+        self.revoked[key_id as usize / 8] & (1 << (key_id % 8)) != 0
+    }
+
+    /// Returns the IDs of all revoked keys.
+    pub fn revoked(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=u8::MAX).filter(move |&id| self.is_revoked(id))
+    }
+
+    /// Adds a key to this ring.
+    ///
+    /// Returns an error if `key_id` is already in use or has been revoked,
+    /// or if there is no room for another key.
+    pub fn insert(&mut self, key_id: u8, key: V) -> Result<(), Error> {
+        if self.is_revoked(key_id) {
+            return Err(Error::RevokedKey(key_id));
+        }
+        if self.keys.iter().flatten().any(|(id, _)| *id == key_id) {
+            return Err(Error::DuplicateKey(key_id));
+        }
+
+        let slot = self
+            .keys
+            .iter_mut()
+            .find(|k| k.is_none())
+            .ok_or(Error::OutOfMemory)?;
+        *slot = Some((key_id, key));
+        Ok(())
+    }
+
+    /// Permanently revokes the key with the given ID.
+    ///
+    /// This succeeds even if there is no such key, so that keys can be
+    /// revoked before they are ever added.
+    pub fn revoke(&mut self, key_id: u8) {
+        self.revoked[key_id as usize / 8] |= 1 << (key_id % 8);
+        for slot in self.keys.iter_mut() {
+            if matches!(slot, Some((id, _)) if *id == key_id) {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// A [`KeySetUpdate`] request whose signature has been checked by
+/// [`KeyRing::verify_async()`], ready to be applied with
+/// [`KeyRing::apply_verified()`].
+///
+/// [`KeySetUpdate`]: crate::protocol::KeySetUpdate
+#[derive(Copy, Clone, Debug)]
+pub struct Verified<'u> {
+    update: KeySetUpdateRequest<'u>,
+}
+
+impl<'u> Verified<'u> {
+    /// Returns the request that was verified.
+    pub fn update(&self) -> &KeySetUpdateRequest<'u> {
+        &self.update
+    }
+}
+
+impl<V: sig::Verify + HasSigType> KeyRing<'_, V> {
+    /// Applies a [`KeySetUpdate`] request to this ring, after checking that it
+    /// was signed by a key in the ring.
+    ///
+    /// The signed portion of the request is re-encoded into `arena` so that
+    /// it can be verified.
+    ///
+    /// `parse_key` is used to convert the key in an [`KeyOp::Add`] request
+    /// into a verifier, returning `None` if it is malformed or unsupported.
+    ///
+    /// [`KeySetUpdate`]: crate::protocol::KeySetUpdate
+    pub fn apply(
+        &mut self,
+        update: &KeySetUpdateRequest,
+        arena: &impl Arena,
+        parse_key: impl FnOnce(&[u8]) -> Option<V>,
+    ) -> Result<(), Error> {
+        let message = signed_message(update, arena)?;
+        let signer = self.verifier(update.signer_id)?;
+        signer.verify(update.signature, message)?;
+
+        self.apply_verified(Verified { update: *update }, parse_key)
+    }
+
+    /// Applies a [`KeySetUpdate`] request whose signature has already been
+    /// checked.
+    ///
+    /// The signer is looked up again, so an update is refused if its signer
+    /// was revoked after it was verified.
+    ///
+    /// [`KeySetUpdate`]: crate::protocol::KeySetUpdate
+    pub fn apply_verified(
+        &mut self,
+        verified: Verified,
+        parse_key: impl FnOnce(&[u8]) -> Option<V>,
+    ) -> Result<(), Error> {
+        let update = verified.update;
+        self.verifier(update.signer_id)?;
+
+        match update.op {
+            KeyOp::Add => {
+                let key = parse_key(update.key).ok_or(Error::OutOfRange)?;
+                self.insert(update.key_id, key)
+            }
+            KeyOp::Revoke => {
+                self.revoke(update.key_id);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<V: sig::AsyncVerify + HasSigType> KeyRing<'_, V> {
+    /// Checks that a [`KeySetUpdate`] request was signed by a key in the ring,
+    /// without blocking while the signature is verified.
+    ///
+    /// This is the first half of [`KeyRing::apply()`]; the returned value
+    /// may be passed to [`KeyRing::apply_verified()`] to finish applying it.
+    ///
+    /// [`KeySetUpdate`]: crate::protocol::KeySetUpdate
+    // Eliding `'u` would tie the result to `self` instead.
+    #[allow(clippy::needless_lifetimes)]
+    pub async fn verify_async<'u>(
+        &mut self,
+        update: &KeySetUpdateRequest<'u>,
+        arena: &impl Arena,
+    ) -> Result<Verified<'u>, Error> {
+        let message = signed_message(update, arena)?;
+        let signer = self.verifier(update.signer_id)?;
+        PollFn(|cx: &mut Context| {
+            signer.poll_verify(cx, update.signature, message)
+        })
+        .await?;
+
+        Ok(Verified { update: *update })
+    }
+}
+
+/// Re-encodes the signed portion of `update` into `arena`.
+fn signed_message<'a>(
+    update: &KeySetUpdateRequest,
+    arena: &'a impl Arena,
+) -> Result<&'a [u8], Error> {
+    let unsigned = KeySetUpdateRequest {
+        signature: &[],
+        ..*update
+    };
+    // Three one-byte fields, followed by the key and its u16 length.
+    let buf = arena.alloc_slice::<u8>(3 + 2 + update.key.len())?;
+    let mut cursor = io::Cursor::new(buf);
+    unsigned
+        .to_wire(&mut cursor)
+        .map_err(|ToWireError::Io(e)| Error::Io(e))?;
+    Ok(cursor.take_consumed_bytes())
+}
+
+impl<V: sig::Verify + HasSigType> KeySet for KeyRing<'_, V> {
+    type Verify = V;
+
+    fn verifier(&mut self, key_id: u8) -> Result<&mut V, Error> {
+        if self.is_revoked(key_id) {
+            return Err(Error::RevokedKey(key_id));
+        }
+        self.keys
+            .iter_mut()
+            .flatten()
+            .find(|(id, _)| *id == key_id)
+            .map(|(_, key)| key)
+            .ok_or(Error::UnknownKey(key_id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::task::Poll;
+
+    use crate::crypto::ring;
+    use crate::crypto::testdata;
+    use crate::hardware::flash::Ram;
+    use crate::manifest::owned;
+    use crate::manifest::pfm::Pfm;
+    use crate::manifest::Container;
+    use crate::mem::BumpArena;
+    use crate::mem::OutOfMemory;
+
+    use serde_json::from_str;
+
+    /// Signs a manifest with the test key, claiming it to be key `key_id`.
+    fn signed_pfm(key_id: u8) -> Ram<Vec<u8>> {
+        let sha = ring::sha256::Builder::new();
+        let (_, mut signer) = testdata::rsa();
+
+        #[rustfmt::skip]
+        let mut pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "blah" }]
+        }"#).unwrap();
+        pfm.metadata.key_id = key_id;
+        Ram(pfm.sign(0x0, &sha, &mut signer).unwrap())
+    }
+
+    #[test]
+    fn verify_with_keys() {
+        let sha = ring::sha256::Builder::new();
+        let (rsa, _) = testdata::rsa();
+        let mut keys = [Some((1, rsa)), None];
+        let mut ring = KeyRing::new(&mut keys);
+        type Flash = Ram<Vec<u8>>;
+
+        let flash = signed_pfm(1);
+        let container: Container<'_, Pfm, Flash> =
+            Container::parse_and_verify_with_keys(
+                &flash,
+                &sha,
+                &mut ring,
+                &OutOfMemory,
+                &OutOfMemory,
+            )
+            .unwrap();
+        assert_eq!(container.metadata().key_id, 1);
+
+        let flash = signed_pfm(2);
+        let result: Result<Container<'_, Pfm, Flash>, _> =
+            Container::parse_and_verify_with_keys(
+                &flash,
+                &sha,
+                &mut ring,
+                &OutOfMemory,
+                &OutOfMemory,
+            );
+        assert!(matches!(result, Err(Error::UnknownKey(2))));
+
+        ring.revoke(1);
+        let flash = signed_pfm(1);
+        let result: Result<Container<'_, Pfm, Flash>, _> =
+            Container::parse_and_verify_with_keys(
+                &flash,
+                &sha,
+                &mut ring,
+                &OutOfMemory,
+                &OutOfMemory,
+            );
+        assert!(matches!(result, Err(Error::RevokedKey(1))));
+    }
+
+    #[test]
+    fn update() {
+        use crate::protocol::wire::FromWire as _;
+
+        let (rsa, _) = testdata::rsa();
+        let mut keys = [Some((1, rsa)), None];
+        let mut ring = KeyRing::new(&mut keys);
+        let mut buf = vec![0; 4096];
+        let arena = BumpArena::new(&mut buf);
+
+        // Add the same key material under a second ID, then revoke the
+        // first.
+        let bytes = testdata::signed_key_set_update(KeyOp::Add, 2, 1);
+        let update =
+            KeySetUpdateRequest::from_wire(&mut &bytes[..], &arena).unwrap();
+        ring.apply(&update, &arena, testdata::parse_rsa_key)
+            .unwrap();
+        assert!(ring.verifier(2).is_ok());
+
+        let bytes = testdata::signed_key_set_update(KeyOp::Revoke, 1, 2);
+        let update =
+            KeySetUpdateRequest::from_wire(&mut &bytes[..], &arena).unwrap();
+        ring.apply(&update, &arena, testdata::parse_rsa_key)
+            .unwrap();
+        assert!(matches!(ring.verifier(1), Err(Error::RevokedKey(1))));
+        assert_eq!(ring.revoked().collect::<Vec<_>>(), vec![1]);
+
+        // Revoked keys can neither sign updates nor be re-added.
+        let bytes = testdata::signed_key_set_update(KeyOp::Add, 3, 1);
+        let update =
+            KeySetUpdateRequest::from_wire(&mut &bytes[..], &arena).unwrap();
+        assert!(matches!(
+            ring.apply(&update, &arena, testdata::parse_rsa_key),
+            Err(Error::RevokedKey(1))
+        ));
+        let bytes = testdata::signed_key_set_update(KeyOp::Add, 1, 2);
+        let update =
+            KeySetUpdateRequest::from_wire(&mut &bytes[..], &arena).unwrap();
+        assert!(matches!(
+            ring.apply(&update, &arena, testdata::parse_rsa_key),
+            Err(Error::RevokedKey(1))
+        ));
+
+        // Tampering with a signed update invalidates it.
+        let mut bytes = testdata::signed_key_set_update(KeyOp::Revoke, 2, 2);
+        bytes[1] = 3;
+        let update =
+            KeySetUpdateRequest::from_wire(&mut &bytes[..], &arena).unwrap();
+        assert!(matches!(
+            ring.apply(&update, &arena, testdata::parse_rsa_key),
+            Err(Error::SignatureFailure)
+        ));
+        assert!(!ring.is_revoked(3));
+    }
+
+    /// A verifier that makes each verification wait once before delegating
+    /// to `V`.
+    struct Slow<V> {
+        verify: V,
+        waited: bool,
+    }
+
+    impl<V: sig::Verify> sig::Verify for Slow<V> {
+        type Error = V::Error;
+
+        fn verify(
+            &mut self,
+            signature: &[u8],
+            message: &[u8],
+        ) -> Result<(), sig::VerifyError<Self>> {
+            self.verify.verify(signature, message)
+        }
+    }
+
+    impl<V: sig::Verify> sig::AsyncVerify for Slow<V> {
+        fn poll_verify(
+            &mut self,
+            cx: &mut Context,
+            signature: &[u8],
+            message: &[u8],
+        ) -> Poll<Result<(), sig::VerifyError<Self>>> {
+            use crate::crypto::sig::Verify as _;
+            self.waited = !self.waited;
+            if self.waited {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(self.verify(signature, message))
+        }
+    }
+
+    impl<V: HasSigType> HasSigType for Slow<V> {
+        fn sig_type(&self) -> crate::manifest::SigType {
+            self.verify.sig_type()
+        }
+    }
+
+    #[test]
+    fn update_async() {
+        use crate::net::asynch::test::block_on;
+        use crate::protocol::wire::FromWire as _;
+
+        let (rsa, _) = testdata::rsa();
+        let slow = |verify| Slow {
+            verify,
+            waited: false,
+        };
+        let mut keys = [Some((1, slow(rsa))), None];
+        let mut ring = KeyRing::new(&mut keys);
+        let mut buf = vec![0; 4096];
+        let arena = BumpArena::new(&mut buf);
+        let parse_key = |key: &[u8]| testdata::parse_rsa_key(key).map(slow);
+
+        let bytes = testdata::signed_key_set_update(KeyOp::Add, 2, 1);
+        let update =
+            KeySetUpdateRequest::from_wire(&mut &bytes[..], &arena).unwrap();
+        let (verified, pending) = block_on(ring.verify_async(&update, &arena));
+        assert_eq!(pending, 1);
+        let verified = verified.unwrap();
+        assert_eq!(verified.update(), &update);
+        ring.apply_verified(verified, parse_key).unwrap();
+        assert!(ring.verifier(2).is_ok());
+
+        // An update whose signer is revoked after it is verified is refused.
+        let bytes = testdata::signed_key_set_update(KeyOp::Revoke, 2, 1);
+        let update =
+            KeySetUpdateRequest::from_wire(&mut &bytes[..], &arena).unwrap();
+        let (verified, _) = block_on(ring.verify_async(&update, &arena));
+        let verified = verified.unwrap();
+        ring.revoke(1);
+        assert!(matches!(
+            ring.apply_verified(verified, parse_key),
+            Err(Error::RevokedKey(1))
+        ));
+        assert!(ring.verifier(2).is_ok());
+
+        let mut bytes = testdata::signed_key_set_update(KeyOp::Revoke, 3, 2);
+        bytes[1] = 4;
+        let update =
+            KeySetUpdateRequest::from_wire(&mut &bytes[..], &arena).unwrap();
+        let (verified, _) = block_on(ring.verify_async(&update, &arena));
+        assert!(matches!(verified, Err(Error::SignatureFailure)));
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Manifest linting.
+//!
+//! A manifest can be perfectly well-formed, and hence signable, while still
+//! describing a policy that makes no sense. This module checks [`owned`]
+//! manifests for such mistakes, so that they can be caught before a manifest
+//! is signed.
+//!
+//! Requires the `std` feature flag to be enabled.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::hardware::flash::Region;
+use crate::manifest::owned;
+use crate::manifest::owned::pfm::Element;
+
+/// A mistake found in a manifest.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Lint {
+    /// Indicates that the manifest has no `PlatformId` element.
+    MissingPlatformId,
+
+    /// Indicates that the manifest has no `FlashDevice` element.
+    MissingFlashDevice,
+
+    /// Indicates that two `AllowableFw` elements have the same firmware ID.
+    DuplicateFirmwareId(Vec<u8>),
+
+    /// Indicates that an `AllowableFw`'s `version_count` does not match the
+    /// number of `FwVersion` elements it contains.
+    VersionCountMismatch {
+        /// The ID of the offending firmware.
+        firmware_id: Vec<u8>,
+        /// The `version_count` field.
+        version_count: u8,
+        /// The actual number of `FwVersion` children.
+        children: usize,
+    },
+
+    /// Indicates that an `FwVersion` has no image regions, or has an image
+    /// region whose hash covers no flash regions.
+    EmptyImage {
+        /// The version string of the offending version.
+        version: Vec<u8>,
+    },
+
+    /// Indicates that a read-write region overlaps an image region in the
+    /// same `FwVersion`.
+    OverlappingRegions {
+        /// The version string of the offending version.
+        version: Vec<u8>,
+        /// The read-write region.
+        rw: Region,
+        /// The image region.
+        image: Region,
+    },
+
+    /// Indicates that a region extends past the end of flash.
+    OutOfBounds {
+        /// The version string of the offending version.
+        version: Vec<u8>,
+        /// The offending region.
+        region: Region,
+    },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        match self {
+            Self::MissingPlatformId => write!(f, "missing PlatformId"),
+            Self::MissingFlashDevice => write!(f, "missing FlashDevice"),
+            Self::DuplicateFirmwareId(id) => {
+                write!(f, "duplicate firmware ID {:?}", s(id))
+            }
+            Self::VersionCountMismatch {
+                firmware_id,
+                version_count,
+                children,
+            } => write!(
+                f,
+                "firmware {:?} has version_count {} but {} versions",
+                s(firmware_id),
+                version_count,
+                children
+            ),
+            Self::EmptyImage { version } => {
+                write!(f, "version {:?} has an empty image", s(version))
+            }
+            Self::OverlappingRegions { version, rw, image } => write!(
+                f,
+                "version {:?} has RW region {:#x}+{:#x} overlapping \
+                 image region {:#x}+{:#x}",
+                s(version),
+                rw.offset,
+                rw.len,
+                image.offset,
+                image.len
+            ),
+            Self::OutOfBounds { version, region } => write!(
+                f,
+                "version {:?} has region {:#x}+{:#x} past the end of flash",
+                s(version),
+                region.offset,
+                region.len
+            ),
+        }
+    }
+}
+
+/// Returns whether `a` and `b` share any bytes.
+fn overlaps(a: Region, b: Region) -> bool {
+    a.len != 0 && b.len != 0 && a.offset < b.end() && b.offset < a.end()
+}
+
+/// Checks a PFM for mistakes.
+///
+/// If `flash_size` is provided, every region is also checked to lie within
+/// a flash device of that size.
+pub fn pfm(pfm: &owned::Pfm, flash_size: Option<u32>) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    let has = |f: fn(&Element) -> bool| {
+        pfm.elements.iter().any(|node| f(&node.element))
+    };
+    if !has(|e| matches!(e, Element::PlatformId { .. })) {
+        lints.push(Lint::MissingPlatformId);
+    }
+    if !has(|e| matches!(e, Element::FlashDevice { .. })) {
+        lints.push(Lint::MissingFlashDevice);
+    }
+
+    let mut ids = HashSet::new();
+    for node in &pfm.elements {
+        let (version_count, firmware_id) = match &node.element {
+            Element::AllowableFw {
+                version_count,
+                firmware_id,
+                ..
+            } => (*version_count, firmware_id),
+            _ => continue,
+        };
+
+        if !ids.insert(firmware_id) {
+            lints.push(Lint::DuplicateFirmwareId(firmware_id.clone()));
+        }
+
+        let versions = node
+            .children
+            .iter()
+            .filter(|n| matches!(n.element, Element::FwVersion { .. }))
+            .count();
+        if versions != version_count as usize {
+            lints.push(Lint::VersionCountMismatch {
+                firmware_id: firmware_id.clone(),
+                version_count,
+                children: versions,
+            });
+        }
+
+        for child in &node.children {
+            if let Element::FwVersion {
+                version_str,
+                rw_regions,
+                image_regions,
+                ..
+            } = &child.element
+            {
+                lint_version(
+                    version_str,
+                    rw_regions,
+                    image_regions,
+                    flash_size,
+                    &mut lints,
+                );
+            }
+        }
+    }
+
+    lints
+}
+
+/// Checks a single `FwVersion` for mistakes.
+fn lint_version(
+    version: &[u8],
+    rw_regions: &[owned::pfm::Rw],
+    image_regions: &[owned::pfm::Image],
+    flash_size: Option<u32>,
+    lints: &mut Vec<Lint>,
+) {
+    if image_regions.is_empty()
+        || image_regions.iter().any(|i| i.regions.is_empty())
+    {
+        lints.push(Lint::EmptyImage {
+            version: version.to_vec(),
+        });
+    }
+
+    let images = image_regions.iter().flat_map(|i| i.regions.iter().copied());
+    for rw in rw_regions {
+        for image in images.clone() {
+            if overlaps(rw.region, image) {
+                lints.push(Lint::OverlappingRegions {
+                    version: version.to_vec(),
+                    rw: rw.region,
+                    image,
+                });
+            }
+        }
+    }
+
+    let flash_size = match flash_size {
+        Some(size) => size,
+        None => return,
+    };
+    for region in rw_regions.iter().map(|rw| rw.region).chain(images) {
+        let in_bounds = region
+            .offset
+            .checked_add(region.len)
+            .map(|end| end <= flash_size)
+            .unwrap_or(false);
+        if !in_bounds {
+            lints.push(Lint::OutOfBounds {
+                version: version.to_vec(),
+                region,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::from_str;
+
+    #[test]
+    fn clean() {
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [
+                { "platform_id": "my cool platform" },
+                { "blank_byte": "0xff" },
+                {
+                    "version_count": 1,
+                    "firmware_id": "my cool firmware",
+                    "flags": 0,
+                    "children": [{
+                        "version_addr": "0x10",
+                        "version_str": "ver-1.2.2",
+                        "rw_regions": [{
+                            "flags": 0,
+                            "region": { "offset": "0x8000", "len": "0x8000" }
+                        }],
+                        "image_regions": [{
+                            "flags": 0,
+                            "hash_type": "Sha256",
+                            "hash": [0, 0, 0, 0, 0, 0, 0, 0,
+                                     0, 0, 0, 0, 0, 0, 0, 0,
+                                     0, 0, 0, 0, 0, 0, 0, 0,
+                                     0, 0, 0, 0, 0, 0, 0, 0],
+                            "regions": [{ "offset": "0x0", "len": "0x8000" }]
+                        }]
+                    }]
+                }
+            ]
+        }"#).unwrap();
+
+        assert_eq!(super::pfm(&pfm, Some(0x10000)), vec![]);
+    }
+
+    #[test]
+    fn mistakes() {
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [
+                {
+                    "version_count": 2,
+                    "firmware_id": "my cool firmware",
+                    "flags": 0,
+                    "children": [{
+                        "version_addr": "0x10",
+                        "version_str": "ver-1.2.2",
+                        "rw_regions": [{
+                            "flags": 0,
+                            "region": { "offset": "0x7000", "len": "0x9000" }
+                        }],
+                        "image_regions": [{
+                            "flags": 0,
+                            "hash_type": "Sha256",
+                            "hash": [0, 0, 0, 0, 0, 0, 0, 0,
+                                     0, 0, 0, 0, 0, 0, 0, 0,
+                                     0, 0, 0, 0, 0, 0, 0, 0,
+                                     0, 0, 0, 0, 0, 0, 0, 0],
+                            "regions": [{ "offset": "0x0", "len": "0x8000" }]
+                        }]
+                    }]
+                },
+                {
+                    "version_count": 1,
+                    "firmware_id": "my cool firmware",
+                    "flags": 0,
+                    "children": [{
+                        "version_addr": "0x10",
+                        "version_str": "ver-1.2.3",
+                        "rw_regions": [],
+                        "image_regions": []
+                    }]
+                }
+            ]
+        }"#).unwrap();
+
+        let version = b"ver-1.2.2".to_vec();
+        assert_eq!(
+            super::pfm(&pfm, Some(0xc000)),
+            vec![
+                Lint::MissingPlatformId,
+                Lint::MissingFlashDevice,
+                Lint::VersionCountMismatch {
+                    firmware_id: b"my cool firmware".to_vec(),
+                    version_count: 2,
+                    children: 1,
+                },
+                Lint::OverlappingRegions {
+                    version: version.clone(),
+                    rw: Region::new(0x7000, 0x9000),
+                    image: Region::new(0x0, 0x8000),
+                },
+                Lint::OutOfBounds {
+                    version,
+                    region: Region::new(0x7000, 0x9000),
+                },
+                Lint::DuplicateFirmwareId(b"my cool firmware".to_vec()),
+                Lint::EmptyImage {
+                    version: b"ver-1.2.3".to_vec(),
+                },
+            ]
+        );
+    }
+}
@@ -21,8 +21,8 @@
 //!     manifest_type: u16, // See `ManifestType`.
 //!     version_id: u32,
 //!     signature_len: u16,
-//!     signature_type: u8,
-//!     _: u8,
+//!     signature_type: u8, // See `SigType`.
+//!     key_id: u8, // See `key_set::KeySet`.
 //!
 //!     // Table-of-contents.
 //!     entry_count: u8,
@@ -71,7 +71,8 @@
 //! This module also provides an "owned" API that eagerly parses the manifest
 //! into a tree based on his TOC. This requires the `std` feature, and is intended
 //! for use by tooling. The [`owned::Container`] type is the relevant entry
-//! point.
+//! point; the [`lint`] module can check owned manifests for mistakes before
+//! they are signed.
 
 use crate::crypto::sha256;
 use crate::crypto::sig;
@@ -85,11 +86,16 @@ use flash::Flash;
 
 mod container;
 pub use container::Container;
+pub use container::HasSigType;
 pub use container::HashType;
 pub use container::Metadata;
+pub use container::SigType;
 pub use container::Toc;
 pub use container::TocEntry;
 
+pub mod key_set;
+#[cfg(feature = "std")]
+pub mod lint;
 #[cfg(feature = "std")]
 pub mod owned;
 pub mod pfm;
@@ -186,6 +192,36 @@ pub enum Error {
 
     /// Indicates that a signature operation failed for some reason.
     SignatureFailure,
+
+    /// Indicates that a manifest's signature type did not match that of the
+    /// engine used to verify it.
+    ///
+    /// Contains the manifest's signature type.
+    SigTypeMismatch(SigType),
+
+    /// Indicates that a manifest was signed by a key that is not in the
+    /// [`key_set::KeySet`] used to verify it.
+    ///
+    /// Contains the ID of the key.
+    UnknownKey(u8),
+
+    /// Indicates that a manifest was signed by a key that has been revoked.
+    ///
+    /// Contains the ID of the key.
+    RevokedKey(u8),
+
+    /// Indicates that a key could not be added to a [`key_set::KeySet`],
+    /// because its ID is already in use.
+    ///
+    /// Contains the ID of the key.
+    DuplicateKey(u8),
+
+    /// Indicates that a manifest is intended for a different platform than
+    /// the one this device protects.
+    ///
+    /// This is also returned for unsigned manifests, whose Platform ID cannot
+    /// be trusted.
+    PlatformIdMismatch,
 }
 
 impl From<io::Error> for Error {
@@ -285,7 +321,7 @@ pub trait Parse<'f, Flash, Provenance>: Manifest {
     ) -> Result<(), Error>;
 
     /// The type of data this manifest guards.
-    type Guarded;
+    type Guarded: ?Sized;
     /// Validates that `manifest` is "valid"; that is, whatever state of
     /// the system this manifest protects is consistent with the manifest's
     /// expectation.
@@ -19,16 +19,19 @@ use crate::crypto::sha256;
 use crate::crypto::sig;
 use crate::hardware::flash::Flash;
 use crate::hardware::flash::Ram;
+use crate::hardware::flash::Region;
 use crate::io::write::StdWrite;
 use crate::io::Write as _;
 use crate::manifest;
 use crate::manifest::container::RawTocEntry;
 use crate::manifest::provenance;
 use crate::manifest::Error;
+use crate::manifest::HasSigType;
 use crate::manifest::HashType;
 use crate::manifest::Manifest;
 use crate::manifest::ManifestType;
 use crate::manifest::Metadata;
+use crate::manifest::SigType;
 use crate::mem::OutOfMemory;
 use crate::protocol::wire::WireEnum;
 
@@ -43,20 +46,21 @@ pub mod pfm;
 /// different kinds of Cerberus manifest types. In general, users should
 /// not have to implement this trait.
 ///
-/// `Element::ElementType` and `Element::TYPE` are analogous to the same
-/// trait items from [`Manifest`].
+/// `Element::TYPE` is analogous to the same trait item from [`Manifest`].
 #[doc(hidden)]
 pub trait Element: Sized {
-    /// A `WireEnum` representing the different kinds of valid element types
-    /// for a Manifest that Manticore understands.
-    type ElementType: WireEnum<Wire = u8>;
-
     /// The specific value of `ManifestType` representing the type implementing
     /// this trait.
     const TYPE: ManifestType;
 
-    /// Returns the element type of a specific element.
-    fn element_type(&self) -> Self::ElementType;
+    /// Returns the wire value of the element type of a specific element.
+    ///
+    /// This need not be a type Manticore understands, since an element may
+    /// have been carried over opaquely from a parsed manifest.
+    fn element_type(&self) -> u8;
+
+    /// Returns the format version of a specific element.
+    fn format_version(&self) -> u8;
 
     /// Attempts to encode this `Element` into bytes, using the given
     /// padding byte as "filler".
@@ -173,11 +177,20 @@ pub enum EncodingError {
     /// Indicates a range was empty when it shouldn't have been.
     EmptyRegion,
 
+    /// Indicates that a region fell outside of the data it refers to.
+    ///
+    /// The bad region is included in the error.
+    OutOfBounds(Region),
+
     /// Indicates an error while computing a hash.
     HashError(sha256::Error),
 
     /// Indicates an error while computing an RSA signature.
     SigError(sig::Error),
+
+    /// Indicates that a manifest failed verification after an externally
+    /// produced signature was attached to it.
+    BadSignature(Error),
 }
 
 impl<E> From<sha256::Error<E>> for EncodingError {
@@ -201,14 +214,17 @@ impl<E: Element> Container<E> {
     pub fn parse(
         bytes: &[u8],
         sha: &impl sha256::Builder,
-        sig_verify: Option<&mut impl sig::Verify>,
+        sig_verify: Option<&mut (impl sig::Verify + HasSigType)>,
     ) -> Result<Parse<E>, Error>
     where
         E: for<'f> FromUnowned<'f, Ram<&'f [u8]>>,
     {
         let mut parse = Parse {
             container: Self {
-                metadata: Metadata { version_id: 0 },
+                metadata: Metadata {
+                    version_id: 0,
+                    key_id: 0,
+                },
                 elements: Vec::new(),
             },
             bad_signature: false,
@@ -267,8 +283,48 @@ impl<E: Element> Container<E> {
         &self,
         padding_byte: u8,
         sha: &impl sha256::Builder,
-        signer: &mut impl sig::Sign,
+        signer: &mut (impl sig::Sign + HasSigType),
     ) -> Result<Vec<u8>, EncodingError> {
+        let mut bytes = self.to_be_signed(
+            padding_byte,
+            sha,
+            signer.sig_type(),
+            signer.sig_bytes(),
+        )?;
+
+        let mut signed = [0; 32];
+        let mut signature = vec![0; signer.sig_bytes()];
+        sha.hash_contiguous(&bytes, &mut signed)?;
+        signer.sign(&signed, &mut signature)?;
+        bytes.extend_from_slice(&signature);
+
+        Ok(bytes)
+    }
+
+    /// Encodes the signed portion of this `Container`: its header, TOC, and
+    /// body, without the trailing signature.
+    ///
+    /// This is intended for signing a manifest with a key that cannot be
+    /// used in-process, such as one held in an HSM. The header is encoded as
+    /// if a `sig_len`-byte signature of type `sig_type` followed it; that
+    /// signature can then be appended with [`Container::attach_signature()`].
+    ///
+    /// Note that the signature expected by Manticore is over the SHA-256
+    /// digest of the returned bytes, rather than the bytes themselves. For
+    /// example, for [`SigType::Rsa2048`], the external signer must produce an
+    /// RSA-PKCS#1-SHA256 signature of that 32-byte digest.
+    ///
+    /// [`SigType::Rsa2048`]: crate::manifest::SigType::Rsa2048
+    pub fn to_be_signed(
+        &self,
+        padding_byte: u8,
+        sha: &impl sha256::Builder,
+        sig_type: SigType,
+        sig_len: usize,
+    ) -> Result<Vec<u8>, EncodingError> {
+        let sig_len: u16 =
+            sig_len.try_into().map_err(|_| EncodingError::OutOfSpace)?;
+
         let mut bytes = Vec::new();
         let mut w = StdWrite(&mut bytes);
 
@@ -276,9 +332,9 @@ impl<E: Element> Container<E> {
         let _ = w.write_le(0u16); // To be filled in later.
         let _ = w.write_le(E::TYPE.to_wire_value());
         let _ = w.write_le(self.metadata.version_id);
-        let _ = w.write_le(signer.sig_bytes() as u16);
-        let _ = w.write_le(0u8); // Should be sig_type.
-        let _ = w.write_le(padding_byte);
+        let _ = w.write_le(sig_len);
+        let _ = w.write_le(sig_type.to_wire_value());
+        let _ = w.write_le(self.metadata.key_id);
 
         let mut index = 0;
         let mut hash_index = 0;
@@ -322,10 +378,10 @@ impl<E: Element> Container<E> {
                     .try_into()
                     .map_err(|_| EncodingError::OutOfSpace)?;
 
-                let element_type = node.element.element_type().to_wire_value();
+                let element_type = node.element.element_type();
                 let entry = RawTocEntry {
                     element_type,
-                    format_version: 0, // TODO(#59)
+                    format_version: node.element.format_version(),
                     offset: *offset,
                     len,
                     parent_type,
@@ -397,16 +453,49 @@ impl<E: Element> Container<E> {
             bytes.extend_from_slice(data);
         }
 
-        let total_len: u16 = (bytes.len() + signer.sig_bytes())
+        let total_len: u16 = (bytes.len() + sig_len as usize)
             .try_into()
             .map_err(|_| EncodingError::OutOfSpace)?;
         bytes[0..2].copy_from_slice(&total_len.to_le_bytes());
 
-        let mut signed = [0; 32];
-        let mut signature = vec![0; signer.sig_bytes()];
-        sha.hash_contiguous(&bytes, &mut signed)?;
-        signer.sign(&signed, &mut signature)?;
-        bytes.extend_from_slice(&signature);
+        Ok(bytes)
+    }
+
+    /// Appends an externally-produced `signature` to `to_be_signed`, the
+    /// output of [`Container::to_be_signed()`], and verifies the result.
+    ///
+    /// The completed manifest is only returned if its TOC hash and signature
+    /// check out against `sig_verify`; otherwise, the verification error is
+    /// returned as [`EncodingError::BadSignature`].
+    pub fn attach_signature(
+        to_be_signed: &[u8],
+        signature: &[u8],
+        sha: &impl sha256::Builder,
+        sig_verify: &mut (impl sig::Verify + HasSigType),
+    ) -> Result<Vec<u8>, EncodingError>
+    where
+        E: for<'f> FromUnowned<'f, Ram<&'f [u8]>>,
+    {
+        let sig_len = to_be_signed
+            .get(8..10)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or(EncodingError::BadSignature(Error::OutOfRange))?;
+        if signature.len() != sig_len {
+            return Err(EncodingError::BadSignature(Error::BadSignatureLen));
+        }
+
+        let mut bytes = to_be_signed.to_vec();
+        bytes.extend_from_slice(signature);
+
+        let ram = Ram(&bytes[..]);
+        manifest::Container::<'_, E::Manifest, _>::parse_and_verify(
+            &ram,
+            sha,
+            sig_verify,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .map_err(EncodingError::BadSignature)?;
 
         Ok(bytes)
     }
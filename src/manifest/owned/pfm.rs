@@ -6,22 +6,25 @@
 //!
 //! See [`owned::Pfm`](../type.Pfm.html).
 
-// TODO(#60): Provide a more ergonomic way of specifying flags bytes.
-
 use core::convert::TryInto;
 
 use crate::crypto::ring::sha256::Builder as RingSha;
 use crate::crypto::sha256;
+use crate::crypto::sha256::Hasher as _;
 use crate::hardware::flash::Flash;
 use crate::hardware::flash::Region;
 use crate::manifest;
 use crate::manifest::owned;
 use crate::manifest::owned::EncodingError;
 use crate::manifest::pfm::ElementType;
+use crate::manifest::pfm::FwFlags;
+use crate::manifest::pfm::ImageFlags;
+use crate::manifest::pfm::RwFlags;
 use crate::manifest::provenance;
 use crate::manifest::Error;
 use crate::manifest::HashType;
 use crate::manifest::ManifestType;
+use crate::manifest::Metadata;
 use crate::mem::misalign_of;
 use crate::mem::Arena as _;
 use crate::mem::BumpArena;
@@ -63,12 +66,9 @@ pub enum Element {
         firmware_id: Vec<u8>,
         #[cfg_attr(
             feature = "serde",
-            serde(
-                deserialize_with = "crate::serde::de_radix",
-                serialize_with = "crate::serde::se_bin",
-            )
+            serde(deserialize_with = "crate::serde::de_flags")
         )]
-        flags: u8,
+        flags: FwFlags,
     },
     FwVersion {
         #[cfg_attr(
@@ -100,6 +100,24 @@ pub enum Element {
         )]
         platform_id: Vec<u8>,
     },
+    /// An element Manticore does not understand, such as one of a newer
+    /// element type, which is carried over from a parsed manifest as-is.
+    Raw {
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        element_type: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(deserialize_with = "crate::serde::de_radix")
+        )]
+        format_version: u8,
+        data: Vec<u8>,
+    },
 }
 
 /// A read-write region.
@@ -109,12 +127,9 @@ pub enum Element {
 pub struct Rw {
     #[cfg_attr(
         feature = "serde",
-        serde(
-            deserialize_with = "crate::serde::de_radix",
-            serialize_with = "crate::serde::se_bin",
-        )
+        serde(deserialize_with = "crate::serde::de_flags")
     )]
-    pub flags: u8,
+    pub flags: RwFlags,
     pub region: Region,
 }
 
@@ -125,27 +140,32 @@ pub struct Rw {
 pub struct Image {
     #[cfg_attr(
         feature = "serde",
-        serde(
-            deserialize_with = "crate::serde::de_radix",
-            serialize_with = "crate::serde::se_bin",
-        )
+        serde(deserialize_with = "crate::serde::de_flags")
     )]
-    pub flags: u8,
+    pub flags: ImageFlags,
     pub hash_type: HashType,
     pub hash: sha256::Digest,
     pub regions: Vec<Region>,
 }
 
 impl owned::Element for Element {
-    type ElementType = ElementType;
     const TYPE: ManifestType = ManifestType::Pfm;
 
-    fn element_type(&self) -> ElementType {
+    fn element_type(&self) -> u8 {
         match self {
             Self::FlashDevice { .. } => ElementType::FlashDevice,
             Self::AllowableFw { .. } => ElementType::AllowableFw,
             Self::FwVersion { .. } => ElementType::FwVersion,
             Self::PlatformId { .. } => ElementType::PlatformId,
+            Self::Raw { element_type, .. } => return *element_type,
+        }
+        .to_wire_value()
+    }
+
+    fn format_version(&self) -> u8 {
+        match self {
+            Self::Raw { format_version, .. } => *format_version,
+            _ => 0, // TODO(#59)
         }
     }
 
@@ -166,7 +186,7 @@ impl owned::Element for Element {
                         EncodingError::StringTooLong(firmware_id.clone())
                     })?;
                 let mut bytes =
-                    vec![*version_count, id_len, *flags, padding_byte];
+                    vec![*version_count, id_len, flags.to_bits(), padding_byte];
 
                 bytes.extend_from_slice(&firmware_id);
                 for _ in 0..misalign_of(bytes.len(), 4) {
@@ -204,7 +224,7 @@ impl owned::Element for Element {
 
                 for rw in rw_regions {
                     let mut header = [padding_byte; 4];
-                    header[0] = rw.flags;
+                    header[0] = rw.flags.to_bits();
                     bytes.extend_from_slice(&header);
 
                     let (start, end) = rw
@@ -224,7 +244,7 @@ impl owned::Element for Element {
                     bytes.extend_from_slice(&[
                         image.hash_type.to_wire_value(),
                         reg_len,
-                        image.flags,
+                        image.flags.to_bits(),
                         padding_byte,
                     ]);
                     bytes.extend_from_slice(&image.hash);
@@ -254,6 +274,7 @@ impl owned::Element for Element {
 
                 Ok(bytes)
             }
+            Self::Raw { data, .. } => Ok(data.clone()),
         }
     }
 }
@@ -269,54 +290,96 @@ impl<'f, F: 'f + Flash> owned::FromUnowned<'f, F> for Element {
             provenance::Adhoc,
         >,
     ) -> Result<Vec<owned::Node<Self>>, Error> {
+        // Every TOC entry starts out as an opaque `Element::Raw`, which is
+        // replaced by a typed element if it is one we understand. Elements
+        // in a newer format than Manticore's may carry fields it would drop,
+        // or not parse as the format Manticore knows at all, so they are
+        // left opaque and never handed to a typed reader. Once this is done, the tree is assembled
+        // in TOC order, so that the manifest is re-encoded exactly as it was
+        // found.
+        let flash = container.flash();
+        let mut entries = Vec::new();
+        for entry in container.toc().entries() {
+            let raw = entry.raw();
+            let node = owned::Node {
+                element: Element::Raw {
+                    element_type: raw.element_type,
+                    format_version: raw.format_version,
+                    data: Vec::new(),
+                },
+                hashed: entry.hash().is_some(),
+                children: Vec::new(),
+            };
+            let parent = entry.parent().map(|p| p.index());
+            entries.push(Some((parent, entry.region(), node)));
+        }
+        let mut set = |index: usize, element: Element| {
+            if let Some((_, _, node)) = &mut entries[index] {
+                node.element = element;
+            }
+        };
+        let is_v0 = |ty| {
+            container
+                .toc()
+                .singleton(ty)
+                .map_or(false, |e| e.format_version() == 0)
+        };
+        let platform_id_v0 = is_v0(ElementType::PlatformId);
+        let flash_device_v0 = is_v0(ElementType::FlashDevice);
+
         let mut arena = vec![0; 2048];
         let mut arena = BumpArena::new(&mut arena);
         let pfm = manifest::pfm::ParsedPfm::new(container);
         let sha = RingSha::new();
-        let mut nodes = Vec::new();
 
-        if let Some(id) = pfm.platform_id(&sha, &arena)? {
-            nodes.push(owned::Node {
-                element: Element::PlatformId {
-                    platform_id: id.id_string().to_vec(),
-                },
-                hashed: id.entry().hash().is_some(),
-                children: Vec::new(),
-            })
+        if platform_id_v0 {
+            if let Some(id) = pfm.platform_id(&sha, &arena)? {
+                set(
+                    id.entry().index(),
+                    Element::PlatformId {
+                        platform_id: id.id_string().to_vec(),
+                    },
+                );
+            }
+            arena.reset();
         }
-        arena.reset();
 
-        if let Some(info) = pfm.flash_device_info(&sha, &arena)? {
-            nodes.push(owned::Node {
-                element: Element::FlashDevice {
-                    blank_byte: info.blank_byte(),
-                },
-                hashed: info.entry().hash().is_some(),
-                children: Vec::new(),
-            })
+        if flash_device_v0 {
+            if let Some(info) = pfm.flash_device_info(&sha, &arena)? {
+                set(
+                    info.entry().index(),
+                    Element::FlashDevice {
+                        blank_byte: info.blank_byte(),
+                    },
+                );
+            }
+            arena.reset();
         }
-        arena.reset();
 
         for allowable_fw in pfm.allowable_fws() {
+            if allowable_fw.entry().format_version() != 0 {
+                continue;
+            }
             let allowable_fw = allowable_fw.read(&sha, &arena)?;
-
-            let mut node = owned::Node {
-                element: Element::AllowableFw {
+            set(
+                allowable_fw.entry().index(),
+                Element::AllowableFw {
                     version_count: allowable_fw.firmware_count() as u8,
                     firmware_id: allowable_fw.firmware_id().to_vec(),
-                    flags: allowable_fw.raw_flags(),
+                    flags: allowable_fw.flags(),
                 },
-                hashed: allowable_fw.entry().hash().is_some(),
-                children: Vec::new(),
-            };
+            );
 
             for fw in allowable_fw.firmware_versions() {
+                if fw.entry().format_version() != 0 {
+                    continue;
+                }
                 let fw = fw.read(&sha, &arena)?;
 
                 let mut rw_regions = Vec::new();
                 for rw in fw.rw_regions() {
                     rw_regions.push(Rw {
-                        flags: rw.raw_flags(),
+                        flags: rw.flags(),
                         region: rw.region(),
                     });
                 }
@@ -324,7 +387,7 @@ impl<'f, F: 'f + Flash> owned::FromUnowned<'f, F> for Element {
                 let mut image_regions = Vec::new();
                 for image in fw.image_regions() {
                     image_regions.push(Image {
-                        flags: image.raw_flags(),
+                        flags: image.flags(),
                         hash_type: HashType::Sha256,
                         hash: *image.image_hash(),
                         regions: image.regions().collect(),
@@ -332,26 +395,198 @@ impl<'f, F: 'f + Flash> owned::FromUnowned<'f, F> for Element {
                 }
 
                 let (version_region, version_str) = fw.version();
-                node.children.push(owned::Node {
-                    element: Element::FwVersion {
+                set(
+                    fw.entry().index(),
+                    Element::FwVersion {
                         version_addr: version_region.offset,
                         version_str: version_str.to_vec(),
                         rw_regions,
                         image_regions,
                     },
-                    hashed: fw.entry().hash().is_some(),
-                    children: Vec::new(),
-                });
+                );
             }
-
-            nodes.push(node);
             arena.reset();
         }
 
+        // A parent always precedes its children in the TOC, so walking it
+        // backwards means each node is complete by the time it is attached
+        // to its parent.
+        let mut nodes = Vec::new();
+        for index in (0..entries.len()).rev() {
+            let (parent, region, mut node) =
+                entries[index].take().expect("each entry is taken once");
+            if let Element::Raw { data, .. } = &mut node.element {
+                data.resize(region.len as usize, 0);
+                flash.read(region.offset, data)?;
+            }
+
+            match parent.and_then(|p| entries[p].as_mut()) {
+                Some((_, _, parent)) => parent.children.insert(0, node),
+                None => nodes.push(node),
+            }
+        }
+        nodes.reverse();
+
         Ok(nodes)
     }
 }
 
+/// A description of how a single firmware image is laid out in host flash.
+///
+/// A `Layout` can be turned into a complete [`owned::Pfm`] by
+/// [`Layout::build()`], which reads the version string and computes image
+/// hashes out of the firmware image itself.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Layout {
+    /// The metadata for the resulting PFM.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub metadata: Metadata,
+
+    /// The platform ID for the resulting PFM.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_bytestring",
+            serialize_with = "crate::serde::se_bytestring",
+        )
+    )]
+    pub platform_id: Vec<u8>,
+
+    /// The value of a blank byte in host flash.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            serialize_with = "crate::serde::se_hex",
+        )
+    )]
+    pub blank_byte: u8,
+
+    /// The ID of the firmware the image contains.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_bytestring",
+            serialize_with = "crate::serde::se_bytestring",
+        )
+    )]
+    pub firmware_id: Vec<u8>,
+
+    /// Flags for the firmware's `AllowableFw` element.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_flags")
+    )]
+    pub flags: FwFlags,
+
+    /// The address of the version string within the image.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            serialize_with = "crate::serde::se_hex",
+        )
+    )]
+    pub version_addr: u32,
+
+    /// The length of the version string.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_radix")
+    )]
+    pub version_len: u8,
+
+    /// Read-write regions of the image.
+    pub rw_regions: Vec<Rw>,
+
+    /// Sets of regions of the image to hash.
+    pub image_regions: Vec<ImageLayout>,
+}
+
+/// A set of image regions to be hashed together by [`Layout::build()`].
+#[allow(missing_docs)]
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ImageLayout {
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_flags")
+    )]
+    pub flags: ImageFlags,
+    pub regions: Vec<Region>,
+}
+
+impl Layout {
+    /// Builds a PFM for `image`, a host flash image laid out as described by
+    /// `self`.
+    ///
+    /// Each [`ImageLayout`] becomes an [`Image`] whose hash is the SHA-256
+    /// hash of its regions, in order. The version string is read out of
+    /// `image` at `version_addr`.
+    pub fn build(
+        &self,
+        image: &[u8],
+        sha: &impl sha256::Builder,
+    ) -> Result<owned::Pfm, EncodingError> {
+        let read = |region: Region| {
+            let end = region.offset.checked_add(region.len);
+            end.and_then(|end| image.get(region.offset as usize..end as usize))
+                .ok_or(EncodingError::OutOfBounds(region))
+        };
+
+        let version = Region::new(self.version_addr, self.version_len as u32);
+        let version_str = read(version)?.to_vec();
+
+        let mut image_regions = Vec::new();
+        for layout in &self.image_regions {
+            let mut hasher = sha.new_hasher()?;
+            for &region in &layout.regions {
+                hasher.write(read(region)?)?;
+            }
+            let mut hash = [0; 32];
+            hasher.finish(&mut hash)?;
+
+            image_regions.push(Image {
+                flags: layout.flags,
+                hash_type: HashType::Sha256,
+                hash,
+                regions: layout.regions.clone(),
+            });
+        }
+
+        let leaf = |element| owned::Node {
+            hashed: true,
+            element,
+            children: Vec::new(),
+        };
+        Ok(owned::Container {
+            metadata: self.metadata,
+            elements: vec![
+                leaf(Element::PlatformId {
+                    platform_id: self.platform_id.clone(),
+                }),
+                leaf(Element::FlashDevice {
+                    blank_byte: self.blank_byte,
+                }),
+                owned::Node {
+                    children: vec![leaf(Element::FwVersion {
+                        version_addr: self.version_addr,
+                        version_str,
+                        rw_regions: self.rw_regions.clone(),
+                        image_regions,
+                    })],
+                    ..leaf(Element::AllowableFw {
+                        version_count: 1,
+                        firmware_id: self.firmware_id.clone(),
+                        flags: self.flags,
+                    })
+                },
+            ],
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -376,7 +611,10 @@ mod test {
         assert_eq!(
             pfm,
             owned::Container {
-                metadata: Metadata { version_id: 42 },
+                metadata: Metadata {
+                    version_id: 42,
+                    key_id: 0,
+                },
                 elements: vec![],
             }
         );
@@ -395,7 +633,10 @@ mod test {
         assert_eq!(
             pfm,
             owned::Container {
-                metadata: Metadata { version_id: 42 },
+                metadata: Metadata {
+                    version_id: 42,
+                    key_id: 0,
+                },
                 elements: vec![owned::Node {
                     element: Element::PlatformId {
                         platform_id: b"my cool platform".to_vec(),
@@ -467,7 +708,10 @@ mod test {
         assert_eq!(
             pfm,
             owned::Container {
-                metadata: Metadata { version_id: 42 },
+                metadata: Metadata {
+                    version_id: 42,
+                    key_id: 0,
+                },
                 elements: vec![
                     owned::Node {
                         element: Element::FlashDevice { blank_byte: 0xff },
@@ -478,19 +722,19 @@ mod test {
                         element: Element::AllowableFw {
                             version_count: 1,
                             firmware_id: b"my cool firmware".to_vec(),
-                            flags: 0xaa,
+                            flags: FwFlags::from_bits(0xaa),
                         },
                         children: vec![owned::Node {
                             element: Element::FwVersion {
                                 version_addr: 0x12345678,
                                 version_str: b"ver-1.2.2".to_vec(),
                                 rw_regions: vec![Rw {
-                                    flags: 0b00110011,
+                                    flags: RwFlags::from_bits(0b00110011),
                                     region: Region::new(0x8000, 0x8000),
                                 }],
                                 image_regions: vec![
                                     Image {
-                                        flags: 0o7,
+                                        flags: ImageFlags::from_bits(0o7),
                                         hash_type: HashType::Sha256,
                                         hash: [42; 32],
                                         regions: vec![
@@ -499,7 +743,7 @@ mod test {
                                         ],
                                     },
                                     Image {
-                                        flags: 0,
+                                        flags: ImageFlags::from_bits(0),
                                         hash_type: HashType::Sha256,
                                         hash: [77; 32],
                                         regions: vec![
@@ -522,7 +766,10 @@ mod test {
     #[test]
     fn round_trip() {
         let pfm = owned::Container {
-            metadata: Metadata { version_id: 42 },
+            metadata: Metadata {
+                version_id: 42,
+                key_id: 0,
+            },
             elements: vec![
                 owned::Node {
                     element: Element::PlatformId {
@@ -540,19 +787,19 @@ mod test {
                     element: Element::AllowableFw {
                         version_count: 1,
                         firmware_id: b"my cool firmware".to_vec(),
-                        flags: 0xaa,
+                        flags: FwFlags::from_bits(0xaa),
                     },
                     children: vec![owned::Node {
                         element: Element::FwVersion {
                             version_addr: 0x12345678,
                             version_str: b"ver-1.2.2".to_vec(),
                             rw_regions: vec![Rw {
-                                flags: 0b00110011,
+                                flags: RwFlags::from_bits(0b00110011),
                                 region: Region::new(0x8000, 0x8000),
                             }],
                             image_regions: vec![
                                 Image {
-                                    flags: 0o7,
+                                    flags: ImageFlags::from_bits(0o7),
                                     hash_type: HashType::Sha256,
                                     hash: [42; 32],
                                     regions: vec![
@@ -561,7 +808,7 @@ mod test {
                                     ],
                                 },
                                 Image {
-                                    flags: 0,
+                                    flags: ImageFlags::from_bits(0),
                                     hash_type: HashType::Sha256,
                                     hash: [77; 32],
                                     regions: vec![
@@ -589,4 +836,334 @@ mod test {
         assert!(pfm2.bad_hashes.is_empty());
         assert_eq!(pfm, pfm2.container);
     }
+
+    #[test]
+    fn raw_elements() {
+        use crate::manifest::owned::Element as _;
+
+        #[rustfmt::skip]
+        let pfm: Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [
+                { "platform_id": "my cool platform" },
+                {
+                    "element_type": "0x80",
+                    "format_version": 3,
+                    "data": [1, 2, 3, 4],
+                    "children": [{
+                        "element_type": "0x81",
+                        "format_version": 0,
+                        "data": [5, 6, 7, 8],
+                        "hashed": false
+                    }]
+                },
+                { "blank_byte": "0xff" },
+                {
+                    "version_count": 1,
+                    "firmware_id": "my cool firmware",
+                    "flags": 0,
+                    "children": [
+                        {
+                            "version_addr": "0x12345678",
+                            "version_str": "ver-1.2.2",
+                            "rw_regions": [],
+                            "image_regions": []
+                        },
+                        {
+                            "element_type": "0x13",
+                            "format_version": 1,
+                            "data": [9, 10, 11, 12]
+                        }
+                    ]
+                },
+                { "platform_id": "a second platform ID" }
+            ]
+        }"#).unwrap();
+        assert!(matches!(
+            pfm.elements[1].element,
+            Element::Raw {
+                element_type: 0x80,
+                format_version: 3,
+                ..
+            }
+        ));
+
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        let bytes = pfm.sign(0x00, &sha, &mut signer).unwrap();
+        let pfm2 =
+            owned::Container::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!pfm2.bad_signature);
+        assert!(pfm2.bad_hashes.is_empty());
+
+        // Only the first `PlatformId` is understood by Manticore, so the
+        // second comes back as an opaque element.
+        let mut expected = pfm;
+        expected.elements[4].element = Element::Raw {
+            element_type: 0x01,
+            format_version: 0,
+            data: Element::PlatformId {
+                platform_id: b"a second platform ID".to_vec(),
+            }
+            .to_bytes(0x00)
+            .unwrap(),
+        };
+        assert_eq!(pfm2.container, expected);
+
+        let json = serde_json::to_string(&pfm2.container).unwrap();
+        let pfm3: Pfm = from_str(&json).unwrap();
+        assert_eq!(pfm3.sign(0x00, &sha, &mut signer).unwrap(), bytes);
+    }
+
+    #[test]
+    fn newer_format_versions() {
+        use crate::manifest::owned::Element as _;
+
+        #[rustfmt::skip]
+        let mut pfm: Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [
+                { "platform_id": "my cool platform" },
+                { "blank_byte": "0xff" }
+            ]
+        }"#).unwrap();
+
+        // Pretend that both elements are in a newer format, which appends
+        // fields to the ones Manticore understands.
+        for node in &mut pfm.elements {
+            let mut data = node.element.to_bytes(0x00).unwrap();
+            data.extend_from_slice(&[1, 2, 3, 4]);
+            node.element = Element::Raw {
+                element_type: node.element.element_type(),
+                format_version: 1,
+                data,
+            };
+        }
+
+        // Newer formats need not parse as the one Manticore knows at all:
+        // read as a v0 Platform ID, this one would claim to be 255 bytes
+        // long, and this `AllowableFw` is too short to hold its header.
+        pfm.elements[0].element = Element::Raw {
+            element_type: ElementType::PlatformId.to_wire_value(),
+            format_version: 1,
+            data: vec![0xff, 0, 0, 0],
+        };
+        pfm.elements.push(owned::Node {
+            element: Element::Raw {
+                element_type: ElementType::AllowableFw.to_wire_value(),
+                format_version: 1,
+                data: vec![0xff, 0xff],
+            },
+            hashed: true,
+            children: Vec::new(),
+        });
+
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        let bytes = pfm.sign(0x00, &sha, &mut signer).unwrap();
+        let pfm2 =
+            owned::Container::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!pfm2.bad_signature);
+        assert_eq!(pfm2.container, pfm);
+        assert_eq!(
+            pfm2.container.sign(0x00, &sha, &mut signer).unwrap(),
+            bytes
+        );
+    }
+
+    #[test]
+    fn detached_signature() {
+        use crate::crypto::sha256::Builder as _;
+        use crate::crypto::sig::Sign as _;
+        use crate::manifest::HasSigType as _;
+
+        #[rustfmt::skip]
+        let pfm: Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{
+                "platform_id": "my cool platform"
+            }]
+        }"#).unwrap();
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        let tbs = pfm
+            .to_be_signed(0x00, &sha, signer.sig_type(), signer.sig_bytes())
+            .unwrap();
+
+        // Sign "externally", the same way `sign()` would.
+        let mut digest = [0; 32];
+        sha.hash_contiguous(&tbs, &mut digest).unwrap();
+        let mut sig = vec![0; signer.sig_bytes()];
+        signer.sign(&digest, &mut sig).unwrap();
+
+        let bytes = Pfm::attach_signature(&tbs, &sig, &sha, &mut rsa).unwrap();
+        assert_eq!(bytes, pfm.sign(0x00, &sha, &mut signer).unwrap());
+
+        sig[0] ^= 0xff;
+        assert!(matches!(
+            Pfm::attach_signature(&tbs, &sig, &sha, &mut rsa),
+            Err(owned::EncodingError::BadSignature(_)),
+        ));
+        assert!(matches!(
+            Pfm::attach_signature(&tbs, &sig[1..], &sha, &mut rsa),
+            Err(owned::EncodingError::BadSignature(_)),
+        ));
+    }
+
+    #[test]
+    fn build_from_layout() {
+        use crate::crypto::sha256::Builder as _;
+
+        #[rustfmt::skip]
+        let layout: Layout = from_str(r#"{
+            "version_id": 42,
+            "platform_id": "my cool platform",
+            "blank_byte": "0xff",
+            "firmware_id": "my cool firmware",
+            "flags": 1,
+            "version_addr": "0x10",
+            "version_len": 5,
+            "rw_regions": [{
+                "flags": 0,
+                "region": { "offset": "0x40", "len": "0x40" }
+            }],
+            "image_regions": [{
+                "flags": 1,
+                "regions": [
+                    { "offset": "0x00", "len": "0x20" },
+                    { "offset": "0x80", "len": "0x80" }
+                ]
+            }]
+        }"#).unwrap();
+
+        let mut image = vec![0xff; 0x100];
+        image[0x10..0x15].copy_from_slice(b"1.2.3");
+        image[0x80..0x88].copy_from_slice(b"firmware");
+
+        let sha = sha256::Builder::new();
+        let pfm = layout.build(&image, &sha).unwrap();
+
+        let mut expected = [0; 32];
+        let hashed = [&image[0x00..0x20], &image[0x80..0x100]].concat();
+        sha.hash_contiguous(&hashed, &mut expected).unwrap();
+
+        let fw = &pfm.elements[2].children[0].element;
+        match fw {
+            Element::FwVersion {
+                version_str,
+                image_regions,
+                ..
+            } => {
+                assert_eq!(version_str, b"1.2.3");
+                assert_eq!(image_regions[0].hash, expected);
+            }
+            _ => panic!("unexpected element: {:?}", fw),
+        }
+
+        let (mut rsa, mut signer) = testdata::rsa();
+        let bytes = pfm.sign(0x00, &sha, &mut signer).unwrap();
+        let pfm2 =
+            owned::Container::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!pfm2.bad_signature);
+        assert_eq!(pfm, pfm2.container);
+
+        image.truncate(0xc0);
+        assert!(matches!(
+            layout.build(&image, &sha),
+            Err(EncodingError::OutOfBounds(Region { offset: 0x80, .. })),
+        ));
+    }
+
+    #[test]
+    fn typed_flags() {
+        use crate::manifest::pfm::RwFailurePolicy;
+
+        #[rustfmt::skip]
+        let fw: owned::Node<Element> = from_str(r#"{
+            "version_count": 1,
+            "firmware_id": "my cool firmware",
+            "flags": { "runtime_update": true },
+            "children": [{
+                "version_addr": "0x12345678",
+                "version_str": "ver-1.2.2",
+                "rw_regions": [{
+                    "flags": {
+                        "failure_policy": "Erase",
+                        "reserved": "0b1000"
+                    },
+                    "region": { "offset": "0x8000", "len": "0x8000" }
+                }],
+                "image_regions": [{
+                    "flags": { "must_validate_on_boot": true },
+                    "hash_type": "Sha256",
+                    "hash": [42, 42, 42, 42, 42, 42, 42, 42,
+                             42, 42, 42, 42, 42, 42, 42, 42,
+                             42, 42, 42, 42, 42, 42, 42, 42,
+                             42, 42, 42, 42, 42, 42, 42, 42],
+                    "regions": [{ "offset": "0x10000", "len": "0x1000" }]
+                }]
+            }]
+        }"#).unwrap();
+
+        match &fw.element {
+            Element::AllowableFw { flags, .. } => {
+                assert!(flags.runtime_update);
+                assert_eq!(flags.to_bits(), 0b1);
+            }
+            e => panic!("unexpected element: {:?}", e),
+        }
+        match &fw.children[0].element {
+            Element::FwVersion {
+                rw_regions,
+                image_regions,
+                ..
+            } => {
+                let rw = rw_regions[0].flags;
+                assert_eq!(rw.failure_policy, RwFailurePolicy::Erase);
+                assert_eq!(rw.to_bits(), 0b1010);
+                assert!(image_regions[0].flags.must_validate_on_boot);
+            }
+            e => panic!("unexpected element: {:?}", e),
+        }
+
+        let json = serde_json::to_string(&fw).unwrap();
+        let fw2: owned::Node<Element> = from_str(&json).unwrap();
+        assert_eq!(fw, fw2);
+
+        // Unrecognized bits, including an unrecognized failure policy, must
+        // survive a round-trip.
+        for bits in 0..=0xff {
+            assert_eq!(FwFlags::from_bits(bits).to_bits(), bits);
+            assert_eq!(RwFlags::from_bits(bits).to_bits(), bits);
+            assert_eq!(ImageFlags::from_bits(bits).to_bits(), bits);
+        }
+        let rw = RwFlags::from_bits(0b11);
+        assert_eq!(rw.failure_policy, RwFailurePolicy::DoNothing);
+        assert_eq!(rw.reserved, 0b11);
+
+        // Reserved bits cannot override known flags.
+        let fw = FwFlags {
+            runtime_update: false,
+            reserved: 0b11,
+        };
+        assert_eq!(fw.to_bits(), 0b10);
+        let image = ImageFlags {
+            must_validate_on_boot: false,
+            reserved: 0b11,
+        };
+        assert_eq!(image.to_bits(), 0b10);
+        let rw = RwFlags {
+            failure_policy: RwFailurePolicy::Erase,
+            reserved: 0b101,
+        };
+        assert_eq!(rw.to_bits(), 0b110);
+        let rw = RwFlags {
+            failure_policy: RwFailurePolicy::RestoreFromRo,
+            reserved: 0b11,
+        };
+        assert_eq!(rw.to_bits(), 0b01);
+    }
 }
@@ -26,6 +26,7 @@ use zerocopy::FromBytes;
 use zerocopy::LayoutVerified;
 
 use crate::crypto::sha256;
+use crate::hardware;
 use crate::hardware::flash::Flash;
 use crate::hardware::flash::Region;
 use crate::manifest::provenance;
@@ -45,6 +46,9 @@ use crate::mem::Arena;
 use crate::mem::ArenaExt as _;
 use crate::protocol::wire::WireEnum as _;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 wire_enum! {
     /// A PFM element type.
     pub enum ElementType: u8 {
@@ -89,7 +93,7 @@ impl Manifest for Pfm {
     }
 }
 
-impl<'f, F: 'f + Flash, P> Parse<'f, F, P> for Pfm {
+impl<'f, F: 'f + Flash, P: Provenance> Parse<'f, F, P> for Pfm {
     type Parsed = ParsedPfm<'f, F, P>;
 
     fn parse(
@@ -121,12 +125,23 @@ impl<'f, F: 'f + Flash, P> Parse<'f, F, P> for Pfm {
         Ok(())
     }
 
-    type Guarded = ();
+    type Guarded = dyn hardware::Identity;
     fn validate(
-        _manifest: &Self::Parsed,
-        _when: ValidationTime,
-        _args: &Self::Guarded,
+        manifest: &Self::Parsed,
+        when: ValidationTime,
+        identity: &Self::Guarded,
     ) -> Result<(), Error> {
+        if let ValidationTime::Activation = when {
+            if let Some(expected) = identity.platform_id() {
+                // The Platform ID can only be trusted if the signature over
+                // it has been checked.
+                if !P::AUTHENTICATED
+                    || !manifest.platform_id_matches(expected)?
+                {
+                    return Err(Error::PlatformIdMismatch);
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -142,6 +157,53 @@ impl<'pfm, F, P> ParsedPfm<'pfm, F, P> {
     }
 }
 
+impl<'pfm, F: Flash, P> ParsedPfm<'pfm, F, P> {
+    /// Checks whether this PFM's Platform ID is `expected`, without
+    /// allocating it anywhere.
+    ///
+    /// A PFM with no Platform ID matches nothing. Unlike
+    /// [`ParsedPfm::platform_id()`], this function does not check the
+    /// element's hash, so its answer is only meaningful for a signed PFM,
+    /// whose signature covers the element.
+    fn platform_id_matches(&self, expected: &[u8]) -> Result<bool, Error> {
+        let entry =
+            match self.container.toc().singleton(ElementType::PlatformId) {
+                Some(x) => x,
+                None => return Ok(false),
+            };
+        let region = entry.region();
+        if region.len < 4 {
+            return Err(Error::OutOfRange);
+        }
+
+        let mut len = 0;
+        self.container
+            .flash()
+            .read(region.offset, core::slice::from_mut(&mut len))?;
+        let len = len as usize;
+        if region.len - 4 < len as u32 {
+            return Err(Error::TooShort {
+                toc_index: entry.index(),
+            });
+        }
+        if len != expected.len() {
+            return Ok(false);
+        }
+
+        let mut buf = [0; 32];
+        let mut offset = region.offset + 4;
+        for chunk in expected.chunks(buf.len()) {
+            let buf = &mut buf[..chunk.len()];
+            self.container.flash().read(offset, buf)?;
+            if buf != chunk {
+                return Ok(false);
+            }
+            offset += chunk.len() as u32;
+        }
+        Ok(true)
+    }
+}
+
 impl<'pfm, F: Flash, P> ParsedPfm<'pfm, F, P>
 where
     P: Provenance,
@@ -432,6 +494,11 @@ impl<'a, 'pfm, F: Flash, P> AllowableFw<'a, 'pfm, F, P> {
         self.fw_id
     }
 
+    /// Returns the flags for this element.
+    pub fn flags(&self) -> FwFlags {
+        FwFlags::from_bits(self.flags)
+    }
+
     /// Returns the raw encoded flags for this element.
     pub fn raw_flags(&self) -> u8 {
         self.flags
@@ -707,6 +774,7 @@ wire_enum! {
     /// Cerberus currently does not fully specify what these policies mean
     /// precisely, nor what failure mode they should be enacted with respect
     /// to.
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
     pub enum RwFailurePolicy: u8 {
         /// Do nothing.
         DoNothing = 0b00,
@@ -717,6 +785,203 @@ wire_enum! {
     }
 }
 
+impl Default for RwFailurePolicy {
+    fn default() -> Self {
+        Self::DoNothing
+    }
+}
+
+/// Flags for an [`AllowableFw`].
+///
+/// Any bits that Manticore does not understand are kept in `reserved`, so
+/// that converting to and from bits is lossless.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FwFlags {
+    /// Whether an update to this firmware is applied at run-time, rather than
+    /// on reset.
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip_serializing_if = "crate::serde::is_default")
+    )]
+    pub runtime_update: bool,
+
+    /// Unrecognized flag bits.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            skip_serializing_if = "crate::serde::is_default"
+        )
+    )]
+    #[cfg_attr(
+        all(feature = "serde", feature = "std"),
+        serde(serialize_with = "crate::serde::se_bin")
+    )]
+    pub reserved: u8,
+}
+
+impl FwFlags {
+    const RUNTIME_UPDATE: u8 = 0b1;
+
+    /// Decodes flags out of their wire representation.
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            runtime_update: bits & Self::RUNTIME_UPDATE != 0,
+            reserved: bits & !Self::RUNTIME_UPDATE,
+        }
+    }
+
+    /// Encodes these flags into their wire representation.
+    ///
+    /// Bits of `reserved` that overlap known flags are ignored.
+    pub fn to_bits(self) -> u8 {
+        let mut bits = self.reserved & !Self::RUNTIME_UPDATE;
+        if self.runtime_update {
+            bits |= Self::RUNTIME_UPDATE;
+        }
+        bits
+    }
+}
+
+impl From<u8> for FwFlags {
+    fn from(bits: u8) -> Self {
+        Self::from_bits(bits)
+    }
+}
+
+/// Flags for an [`RwRegion`].
+///
+/// Any bits that Manticore does not understand, including an unrecognized
+/// failure policy, are kept in `reserved`, so that converting to and from
+/// bits is lossless.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RwFlags {
+    /// The policy to enact when verification of this region fails.
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip_serializing_if = "crate::serde::is_default")
+    )]
+    pub failure_policy: RwFailurePolicy,
+
+    /// Unrecognized flag bits.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            skip_serializing_if = "crate::serde::is_default"
+        )
+    )]
+    #[cfg_attr(
+        all(feature = "serde", feature = "std"),
+        serde(serialize_with = "crate::serde::se_bin")
+    )]
+    pub reserved: u8,
+}
+
+impl RwFlags {
+    const FAILURE_POLICY: u8 = 0b11;
+
+    /// Decodes flags out of their wire representation.
+    pub fn from_bits(bits: u8) -> Self {
+        match RwFailurePolicy::from_wire_value(bits & Self::FAILURE_POLICY) {
+            Some(failure_policy) => Self {
+                failure_policy,
+                reserved: bits & !Self::FAILURE_POLICY,
+            },
+            None => Self {
+                failure_policy: RwFailurePolicy::default(),
+                reserved: bits,
+            },
+        }
+    }
+
+    /// Encodes these flags into their wire representation.
+    ///
+    /// Bits of `reserved` that overlap the failure policy are ignored, unless
+    /// they encode an unrecognized policy and `failure_policy` is the default,
+    /// as produced by [`RwFlags::from_bits()`].
+    pub fn to_bits(self) -> u8 {
+        let policy = self.reserved & Self::FAILURE_POLICY;
+        if RwFailurePolicy::from_wire_value(policy).is_none()
+            && self.failure_policy == RwFailurePolicy::default()
+        {
+            return self.reserved;
+        }
+        self.failure_policy.to_wire_value()
+            | (self.reserved & !Self::FAILURE_POLICY)
+    }
+}
+
+impl From<u8> for RwFlags {
+    fn from(bits: u8) -> Self {
+        Self::from_bits(bits)
+    }
+}
+
+/// Flags for an [`FwRegion`].
+///
+/// Any bits that Manticore does not understand are kept in `reserved`, so
+/// that converting to and from bits is lossless.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ImageFlags {
+    /// Whether this region must be validated on boot, rather than just when
+    /// loading a new firmware update.
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip_serializing_if = "crate::serde::is_default")
+    )]
+    pub must_validate_on_boot: bool,
+
+    /// Unrecognized flag bits.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            skip_serializing_if = "crate::serde::is_default"
+        )
+    )]
+    #[cfg_attr(
+        all(feature = "serde", feature = "std"),
+        serde(serialize_with = "crate::serde::se_bin")
+    )]
+    pub reserved: u8,
+}
+
+impl ImageFlags {
+    const MUST_VALIDATE_ON_BOOT: u8 = 0b1;
+
+    /// Decodes flags out of their wire representation.
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            must_validate_on_boot: bits & Self::MUST_VALIDATE_ON_BOOT != 0,
+            reserved: bits & !Self::MUST_VALIDATE_ON_BOOT,
+        }
+    }
+
+    /// Encodes these flags into their wire representation.
+    ///
+    /// Bits of `reserved` that overlap known flags are ignored.
+    pub fn to_bits(self) -> u8 {
+        let mut bits = self.reserved & !Self::MUST_VALIDATE_ON_BOOT;
+        if self.must_validate_on_boot {
+            bits |= Self::MUST_VALIDATE_ON_BOOT;
+        }
+        bits
+    }
+}
+
+impl From<u8> for ImageFlags {
+    fn from(bits: u8) -> Self {
+        Self::from_bits(bits)
+    }
+}
+
 /// A read-write region within a [`FwVersion`].
 ///
 /// This region is not hashed or protected in any way, and both reads and
@@ -736,6 +1001,11 @@ impl RwRegion {
         RwFailurePolicy::from_wire_value(self.flags & 0b11)
     }
 
+    /// Returns the flags for this element.
+    pub fn flags(&self) -> RwFlags {
+        RwFlags::from_bits(self.flags)
+    }
+
     /// Returns the raw encoded flags for this element.
     pub fn raw_flags(&self) -> u8 {
         self.flags
@@ -778,7 +1048,12 @@ impl FwRegion<'_> {
     /// Returns whether this region must be validated on boot, rather than just
     /// when loading a new firmware update.
     pub fn must_validate_on_boot(&self) -> bool {
-        (self.header.flags & 1) == 1
+        self.flags().must_validate_on_boot
+    }
+
+    /// Returns the flags for this element.
+    pub fn flags(&self) -> ImageFlags {
+        ImageFlags::from_bits(self.header.flags)
     }
 
     /// Returns the raw encoded flags for this element.
@@ -821,6 +1096,7 @@ mod test {
     use crate::crypto::ring;
     use crate::crypto::sha256::Builder as _;
     use crate::crypto::testdata::rsa as test_rsa;
+    use crate::hardware::fake;
     use crate::hardware::flash::Ram;
     use crate::io::Write as _;
     use crate::manifest::owned;
@@ -857,6 +1133,17 @@ mod test {
         assert!(pfm.platform_id(&sha, &OutOfMemory).unwrap().is_none());
         assert!(pfm.flash_device_info(&sha, &OutOfMemory).unwrap().is_none());
         assert_eq!(pfm.allowable_fws().count(), 0);
+
+        let identity =
+            fake::Identity::new(b"", &[], b"").with_platform_id(b"my pfm");
+        assert!(matches!(
+            <Pfm as Parse<'_, _, _>>::validate(
+                &pfm,
+                ValidationTime::Activation,
+                &identity
+            ),
+            Err(Error::PlatformIdMismatch)
+        ));
     }
 
     #[test]
@@ -885,6 +1172,66 @@ mod test {
         assert_eq!(id.id_string(), b"my pfm");
     }
 
+    #[test]
+    fn validate_platform_id() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "my pfm" }]
+        }"#).unwrap();
+        let bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+
+        let container = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        let pfm = ParsedPfm::new(container);
+        let validate = |when, identity: &fake::Identity| {
+            <Pfm as Parse<'_, _, _>>::validate(&pfm, when, identity)
+        };
+
+        let identity = fake::Identity::new(b"", &[], b"");
+        validate(ValidationTime::Activation, &identity).unwrap();
+
+        let identity = identity.with_platform_id(b"my pfm");
+        validate(ValidationTime::Activation, &identity).unwrap();
+
+        let identity = identity.with_platform_id(b"my other pfm");
+        assert!(matches!(
+            validate(ValidationTime::Activation, &identity),
+            Err(Error::PlatformIdMismatch)
+        ));
+        validate(ValidationTime::Startup, &identity).unwrap();
+
+        let identity = identity.with_platform_id(b"my pf");
+        assert!(matches!(
+            validate(ValidationTime::Activation, &identity),
+            Err(Error::PlatformIdMismatch)
+        ));
+
+        // An unsigned PFM's Platform ID is never trusted.
+        let identity = identity.with_platform_id(b"my pfm");
+        let adhoc = ParsedPfm::new(
+            Container::<_, _, provenance::Adhoc>::parse(&bytes, &OutOfMemory)
+                .unwrap(),
+        );
+        assert!(matches!(
+            <Pfm as Parse<'_, _, _>>::validate(
+                &adhoc,
+                ValidationTime::Activation,
+                &identity
+            ),
+            Err(Error::PlatformIdMismatch)
+        ));
+    }
+
     #[test]
     fn fw_versions() {
         let sha = ring::sha256::Builder::new();
@@ -0,0 +1,360 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Asynchronous networking interfaces.
+//!
+//! This module provides [`AsyncHostPort`] and [`AsyncDevicePort`], which are
+//! counterparts to [`HostPort`] and [`DevicePort`] for integrations that run
+//! on an async executor, and so cannot block while waiting for the transport.
+//!
+//! Rather than blocking, these traits split each transport operation into a
+//! `poll_*()` function, which is polled until the operation is complete, and
+//! a synchronous accessor for the result. Once a request has arrived, it is
+//! accessed through the same [`HostRequest`] and [`HostResponse`] traits used
+//! by [`HostPort`]; a reply is buffered by the port until it is flushed. This
+//! keeps the traits object-safe and free of allocation, so that they work
+//! without `std`.
+//!
+//! For convenience, `dyn AsyncHostPort` and `dyn AsyncDevicePort` have `async`
+//! methods that wrap the `poll_*()` functions:
+//! ```
+//! # use manticore::io::Write as _;
+//! # use manticore::net::*;
+//! # use manticore::net::asynch::*;
+//! async fn process_request(
+//!     port: &mut dyn AsyncHostPort,
+//! ) -> Result<(), Error> {
+//!     let req = port.receive().await?;
+//!     let header = req.header()?;
+//!
+//!     // ... do stuff with `req.payload()` ...
+//!
+//!     let resp = req.reply(header)?;
+//!     resp.sink()?.write_bytes(&[1, 2, 3]);
+//!     resp.finish()?;
+//!
+//!     // The reply is not necessarily sent until the port is flushed.
+//!     port.flush().await
+//! }
+//! ```
+//!
+//! Timeouts are not part of these interfaces; an integration that needs them
+//! should use whatever timer facility its executor provides. Long-running
+//! signature checks can likewise be awaited, by providing a
+//! [`sig::AsyncVerify`](crate::crypto::sig::AsyncVerify).
+//!
+//! [`HostPort`]: super::HostPort
+//! [`HostResponse`]: super::HostResponse
+//! [`DevicePort`]: super::DevicePort
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+
+use static_assertions::assert_obj_safe;
+
+use crate::net::DeviceResponse;
+use crate::net::Error;
+use crate::net::Header;
+use crate::net::HostRequest;
+use crate::net::InMemDevice;
+use crate::net::InMemHost;
+
+/// Represents a physical port that can be used to interact with host devices,
+/// without blocking.
+///
+/// This is the asynchronous counterpart to [`HostPort`](super::HostPort);
+/// see its documentation for more information.
+pub trait AsyncHostPort {
+    /// Polls for an incoming message from a connected host device.
+    ///
+    /// Once this function returns `Poll::Ready(Ok(()))`, `request()` returns
+    /// the received request.
+    fn poll_receive(&mut self, cx: &mut Context) -> Poll<Result<(), Error>>;
+
+    /// Returns the request most recently received by `poll_receive()`.
+    ///
+    /// This function should return [`Error::OutOfOrder`] if no request has
+    /// been received.
+    fn request(&mut self) -> Result<&mut dyn HostRequest, Error>;
+
+    /// Polls for completion of transmitting the reply to the current request.
+    ///
+    /// Implementations may buffer a reply until it is flushed. If there is no
+    /// reply to flush, this function should return `Poll::Ready(Ok(()))`.
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), Error>>;
+}
+assert_obj_safe!(AsyncHostPort);
+
+impl<P: AsyncHostPort + ?Sized> AsyncHostPort for &'_ mut P {
+    fn poll_receive(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        P::poll_receive(*self, cx)
+    }
+
+    fn request(&mut self) -> Result<&mut dyn HostRequest, Error> {
+        P::request(*self)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        P::poll_flush(*self, cx)
+    }
+}
+
+impl<'a> dyn AsyncHostPort + 'a {
+    /// Receives an incoming message from a connected host device.
+    ///
+    /// This is the asynchronous counterpart to
+    /// [`HostPort::receive()`](super::HostPort::receive).
+    pub async fn receive(&mut self) -> Result<&mut dyn HostRequest, Error> {
+        PollFn(|cx: &mut Context| self.poll_receive(cx)).await?;
+        self.request()
+    }
+
+    /// Transmits the reply to the current request.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        PollFn(|cx: &mut Context| self.poll_flush(cx)).await
+    }
+}
+
+/// Represents a physical port that can be used to interact with client
+/// devices, without blocking.
+///
+/// This is the asynchronous counterpart to
+/// [`DevicePort`](super::DevicePort); see its documentation for more
+/// information.
+pub trait AsyncDevicePort {
+    /// Queues a message to be sent to the device at `dest`.
+    ///
+    /// The message is not necessarily sent until `poll_flush()` completes.
+    fn send(
+        &mut self,
+        dest: u8,
+        header: Header,
+        msg: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Polls for completion of transmitting all queued messages.
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), Error>>;
+
+    /// Polls for a response to the most recently sent message.
+    ///
+    /// Once this function returns `Poll::Ready(Ok(()))`,
+    /// `receive_response()` returns the response.
+    fn poll_response(&mut self, cx: &mut Context) -> Poll<Result<(), Error>>;
+
+    /// Returns the response most recently received by `poll_response()`.
+    fn receive_response(&mut self) -> Result<&mut dyn DeviceResponse, Error>;
+}
+assert_obj_safe!(AsyncDevicePort);
+
+impl<'a> dyn AsyncDevicePort + 'a {
+    /// Sends a message to the device at `dest`, waiting for it to be
+    /// transmitted.
+    pub async fn send_all(
+        &mut self,
+        dest: u8,
+        header: Header,
+        msg: &[u8],
+    ) -> Result<(), Error> {
+        self.send(dest, header, msg)?;
+        PollFn(|cx: &mut Context| self.poll_flush(cx)).await
+    }
+
+    /// Waits for a response to the most recently sent message.
+    ///
+    /// This is the asynchronous counterpart to calling
+    /// `DevicePort::wait_for_response()` followed by
+    /// `DevicePort::receive_response()`.
+    pub async fn response(&mut self) -> Result<&mut dyn DeviceResponse, Error> {
+        PollFn(|cx: &mut Context| self.poll_response(cx)).await?;
+        self.receive_response()
+    }
+}
+
+/// A future that polls a function until it returns `Poll::Ready`.
+pub(crate) struct PollFn<F>(pub(crate) F);
+
+impl<F, T> Future for PollFn<F>
+where
+    F: FnMut(&mut Context) -> Poll<T> + Unpin,
+{
+    type Output = T;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        (self.0)(cx)
+    }
+}
+
+/// An `InMemHost` never waits: a request is available as soon as it is
+/// scheduled, and replies are "transmitted" immediately.
+impl AsyncHostPort for InMemHost<'_> {
+    fn poll_receive(&mut self, _: &mut Context) -> Poll<Result<(), Error>> {
+        if self.0.rx_header.is_none() {
+            return Poll::Ready(Err(Error::Disconnected));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn request(&mut self) -> Result<&mut dyn HostRequest, Error> {
+        Ok(&mut self.0)
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// An `InMemDevice` never waits, like `InMemHost`.
+impl AsyncDevicePort for InMemDevice<'_> {
+    fn send(
+        &mut self,
+        dest: u8,
+        header: Header,
+        msg: &[u8],
+    ) -> Result<(), Error> {
+        crate::net::DevicePort::send(self, dest, header, msg)
+    }
+
+    fn poll_flush(&mut self, _: &mut Context) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_response(&mut self, _: &mut Context) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn receive_response(&mut self) -> Result<&mut dyn DeviceResponse, Error> {
+        Ok(&mut self.0)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::task::Waker;
+
+    use crate::protocol::CommandType;
+
+    /// A waker that counts how many times it has been woken.
+    struct Counter(AtomicUsize);
+    impl Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Runs `fut` to completion, returning its output and the number of times
+    /// it returned `Poll::Pending`.
+    ///
+    /// Panics if `fut` returns `Poll::Pending` without waking its waker.
+    pub fn block_on<F: Future>(fut: F) -> (F::Output, usize) {
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut cx = Context::from_waker(&waker);
+
+        let mut fut = Box::pin(fut);
+        let mut pending = 0;
+        loop {
+            if let Poll::Ready(x) = fut.as_mut().poll(&mut cx) {
+                return (x, pending);
+            }
+            pending += 1;
+            assert_eq!(
+                counter.0.load(Ordering::SeqCst),
+                pending,
+                "future returned Pending without waking"
+            );
+        }
+    }
+
+    /// An `AsyncHostPort` that makes each operation wait once before
+    /// delegating to `InMemHost`.
+    pub struct Slow<'a> {
+        pub host: InMemHost<'a>,
+        pub waited: bool,
+    }
+
+    impl Slow<'_> {
+        fn wait(&mut self, cx: &mut Context) -> Poll<()> {
+            self.waited = !self.waited;
+            if self.waited {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(())
+        }
+    }
+
+    impl AsyncHostPort for Slow<'_> {
+        fn poll_receive(
+            &mut self,
+            cx: &mut Context,
+        ) -> Poll<Result<(), Error>> {
+            if self.wait(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.host.poll_receive(cx)
+        }
+
+        fn request(&mut self) -> Result<&mut dyn HostRequest, Error> {
+            AsyncHostPort::request(&mut self.host)
+        }
+
+        fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+            if self.wait(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.host.poll_flush(cx)
+        }
+    }
+
+    #[test]
+    fn receive_and_flush() {
+        let mut out = [0; 16];
+        let mut port = Slow {
+            host: InMemHost::new(&mut out),
+            waited: false,
+        };
+        let header = Header {
+            is_request: true,
+            command: CommandType::DeviceId.into(),
+        };
+        port.host.request(header, &[1, 2, 3]);
+
+        let (result, pending) = block_on(async {
+            let port: &mut dyn AsyncHostPort = &mut port;
+            let req = port.receive().await?;
+            assert_eq!(req.header()?, header);
+            let resp = req.reply(Header {
+                is_request: false,
+                ..header
+            })?;
+            resp.sink()?.write_bytes(&[4, 5]).unwrap();
+            resp.finish()?;
+            port.flush().await
+        });
+        result.unwrap();
+        assert_eq!(pending, 2);
+
+        let (header, resp) = port.host.response().unwrap();
+        assert!(!header.is_request);
+        assert_eq!(resp, &[4, 5]);
+    }
+
+    #[test]
+    fn disconnected() {
+        let mut out = [0; 16];
+        let mut port = InMemHost::new(&mut out);
+        let (result, _) = block_on(async {
+            let port: &mut dyn AsyncHostPort = &mut port;
+            port.receive().await.map(|_| ())
+        });
+        assert!(matches!(result, Err(Error::Disconnected)));
+    }
+}
@@ -25,6 +25,16 @@ use crate::io::Write;
 
 pub use crate::protocol::Header;
 
+pub mod asynch;
+pub mod packet;
+
+#[cfg(feature = "std")]
+pub mod sim;
+#[cfg(feature = "std")]
+pub mod socket;
+#[cfg(feature = "std")]
+pub mod transcript;
+
 /// A networking error.
 #[derive(Copy, Clone, Debug)]
 pub enum Error {
@@ -42,6 +52,11 @@ pub enum Error {
     OutOfOrder,
     /// The operation timed out.
     Timeout,
+    /// Indicates that a packet was malformed, such as by being too long or
+    /// arriving out of order.
+    BadPacket,
+    /// Indicates that a message exceeded the maximum message size.
+    MessageTooLong,
 }
 
 impl From<io::Error> for Error {
@@ -131,6 +146,13 @@ pub trait HostPort {
 }
 assert_obj_safe!(HostPort);
 
+impl<P: HostPort + ?Sized> HostPort for &'_ mut P {
+    #[inline]
+    fn receive(&mut self) -> Result<&mut dyn HostRequest, Error> {
+        P::receive(*self)
+    }
+}
+
 /// Provides the "request" half of a transaction with a host.
 ///
 /// See [`HostPort`](trait.HostPort.html) for more information.
@@ -140,6 +162,16 @@ pub trait HostRequest {
     /// This function should not be called after calling `reply()`.
     fn header(&self) -> Result<Header, Error>;
 
+    /// Returns an identifier for the host that sent this request, such as its
+    /// bus address.
+    ///
+    /// Servers may use this value to keep track of per-host state, such as
+    /// negotiated capabilities. Ports that only ever speak to a single host
+    /// may use the default implementation, which always returns zero.
+    fn host_id(&self) -> u8 {
+        0
+    }
+
     /// Returns the raw byte stream for the payload of the request.
     ///
     /// This function should not be called after calling `reply()`.
@@ -241,7 +273,7 @@ pub trait DeviceResponse {
 ///
 /// // Prepare a request to push into the host.
 /// let header = Header {
-///     command: CommandType::FirmwareVersion,
+///     command: CommandType::FirmwareVersion.into(),
 ///     is_request: true,
 /// };
 /// let req = [0];
@@ -264,7 +296,7 @@ pub trait DeviceResponse {
 ///
 /// // Prepare to reply to the message.
 /// let mut host_resp = host_req.reply(Header {
-///     command: CommandType::FirmwareVersion,
+///     command: CommandType::FirmwareVersion.into(),
 ///     is_request: false,
 /// })?;
 ///
@@ -300,6 +332,7 @@ pub struct InMemHost<'buf>(InMemInner<'buf>);
 /// Implementors of `HostPort` should take care that the same is not possible
 /// with their implementation.
 struct InMemInner<'buf> {
+    rx_host: u8,
     rx_header: Option<Header>,
     rx: &'buf [u8],
     tx_header: Option<Header>,
@@ -313,6 +346,7 @@ impl<'buf> InMemHost<'buf> {
     /// replies to this host.
     pub fn new(out: &'buf mut [u8]) -> Self {
         Self(InMemInner {
+            rx_host: 0,
             rx_header: None,
             rx: &[],
             tx_header: None,
@@ -326,6 +360,18 @@ impl<'buf> InMemHost<'buf> {
     /// Calling this function will make `recieve()` start working; otherwise,
     /// it will assert that the port is disconnected.
     pub fn request(&mut self, header: Header, message: &'buf [u8]) {
+        self.request_from(0, header, message)
+    }
+
+    /// Schedules a new request to be recieved, like `request()`, but as if it
+    /// came from the host with the given [`HostRequest::host_id()`].
+    pub fn request_from(
+        &mut self,
+        host_id: u8,
+        header: Header,
+        message: &'buf [u8],
+    ) {
+        self.0.rx_host = host_id;
         self.0.rx_header = Some(header);
         self.0.rx = message;
 
@@ -356,6 +402,10 @@ impl HostRequest for InMemInner<'_> {
         self.rx_header.ok_or(Error::OutOfOrder)
     }
 
+    fn host_id(&self) -> u8 {
+        self.rx_host
+    }
+
     fn payload(&mut self) -> Result<&mut dyn Read, Error> {
         if self.rx_header.is_none() {
             return Err(Error::OutOfOrder);
@@ -0,0 +1,766 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Packet segmentation and reassembly.
+//!
+//! Many physical transports, such as SPI or I2C, can only move a bounded
+//! number of bytes at a time, and Cerberus devices advertise these bounds as
+//! part of their [`Networking`] capabilities. This module provides
+//! [`SegmentedHost`], a [`HostPort`] that reassembles requests out of packets
+//! provided by a [`PacketPort`], and splits responses back into packets,
+//! while enforcing the negotiated message and packet sizes.
+//!
+//! Because `SegmentedHost` is itself a `HostPort`, anything that works with a
+//! `HostPort`, such as the request handlers in [`server`], will work
+//! unmodified on top of a packetized transport.
+//!
+//! `SegmentedHost` cannot sit on top of an arbitrary `HostPort`, since a
+//! `HostPort` only ever yields whole messages: the start-of-message and
+//! end-of-message bits and the sequence number of each packet, which are
+//! needed for reassembly, are not visible through it. Hence the separate
+//! [`PacketPort`] trait for the physical layer. A `HostPort` that assembles
+//! messages out of packets itself can expose those packets by implementing
+//! [`PacketHostPort`], and then be wrapped in a `SegmentedHost` by way of
+//! [`HostPackets`], so that the negotiated limits are enforced on it.
+//!
+//! # Packet Format
+//!
+//! A packet consists of a [`PacketHeader`] and at most `max_packet_size`
+//! bytes of data; how the packet header is encoded is left up to the
+//! [`PacketPort`]. The data of all packets in a message, concatenated
+//! together, consists of an encoded [`Header`] followed by the message
+//! payload; the total length of this must not exceed `max_message_size`.
+//!
+//! The first packet of a message has the "start of message" bit set, and the
+//! last packet of a message has the "end of message" bit set (a single-packet
+//! message sets both). Each packet carries a two-bit sequence number, which
+//! is incremented (modulo four) for each packet in a message.
+//!
+//! [`server`]: ../../server/index.html
+
+use crate::io;
+use crate::io::Read;
+use crate::io::Write;
+use crate::mem::OutOfMemory;
+use crate::net::Error;
+use crate::net::Header;
+use crate::net::HostPort;
+use crate::net::HostRequest;
+use crate::net::HostResponse;
+use crate::protocol;
+use crate::protocol::capabilities::Networking;
+use crate::protocol::wire::FromWire as _;
+use crate::protocol::wire::ToWire as _;
+use crate::protocol::CommandType;
+use crate::protocol::ErrorCode;
+use crate::protocol::HEADER_LEN;
+
+/// The number of distinct packet sequence numbers.
+const SEQ_MODULUS: u8 = 4;
+
+/// Transport-level information about a single packet.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct PacketHeader {
+    /// Whether this is the first packet of a message.
+    pub start_of_message: bool,
+    /// Whether this is the last packet of a message.
+    pub end_of_message: bool,
+    /// The packet's sequence number within its message, modulo four.
+    pub seq: u8,
+}
+
+/// A physical port that transacts individual packets with host devices.
+///
+/// This is the layer below a [`HostPort`]; see [`SegmentedHost`].
+pub trait PacketPort {
+    /// Receives a single packet from a connected host device, blocking until
+    /// one is available.
+    ///
+    /// The packet's data is written to `out`, and its header and actual length
+    /// are returned. If the packet is longer than `out`, only the first
+    /// `out.len()` bytes of data are written, but the full length is still
+    /// returned, so that the caller can detect the overflow.
+    fn receive_packet(
+        &mut self,
+        out: &mut [u8],
+    ) -> Result<(PacketHeader, usize), Error>;
+
+    /// Sends a single packet to the host device, blocking until the operation
+    /// is complete.
+    fn send_packet(
+        &mut self,
+        header: PacketHeader,
+        data: &[u8],
+    ) -> Result<(), Error>;
+}
+
+impl<P: PacketPort + ?Sized> PacketPort for &mut P {
+    fn receive_packet(
+        &mut self,
+        out: &mut [u8],
+    ) -> Result<(PacketHeader, usize), Error> {
+        P::receive_packet(*self, out)
+    }
+
+    fn send_packet(
+        &mut self,
+        header: PacketHeader,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        P::send_packet(*self, header, data)
+    }
+}
+
+/// A [`HostPort`] whose transport exposes packet boundaries.
+///
+/// A `HostPort` is responsible for assembling messages out of its physical
+/// layer's packets. An implementation that can also hand out those packets
+/// unassembled implements this trait, so that it can be wrapped in a
+/// [`SegmentedHost`] with [`HostPackets`].
+pub trait PacketHostPort: HostPort {
+    /// Receives a single packet, bypassing message assembly.
+    ///
+    /// This function has the same contract as
+    /// [`PacketPort::receive_packet()`].
+    fn receive_packet(
+        &mut self,
+        out: &mut [u8],
+    ) -> Result<(PacketHeader, usize), Error>;
+
+    /// Sends a single packet, bypassing message segmentation.
+    ///
+    /// This function has the same contract as [`PacketPort::send_packet()`].
+    fn send_packet(
+        &mut self,
+        header: PacketHeader,
+        data: &[u8],
+    ) -> Result<(), Error>;
+}
+
+/// Adapts a [`PacketHostPort`] into a [`PacketPort`], so that it can be
+/// wrapped in a [`SegmentedHost`].
+///
+/// While wrapped, the port is only used for its packets; its own message
+/// assembly is bypassed.
+pub struct HostPackets<H>(pub H);
+
+impl<H: PacketHostPort> PacketPort for HostPackets<H> {
+    fn receive_packet(
+        &mut self,
+        out: &mut [u8],
+    ) -> Result<(PacketHeader, usize), Error> {
+        self.0.receive_packet(out)
+    }
+
+    fn send_packet(
+        &mut self,
+        header: PacketHeader,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.0.send_packet(header, data)
+    }
+}
+
+/// A [`HostPort`] that performs segmentation and reassembly on top of a
+/// [`PacketPort`].
+///
+/// Messages, including their headers, are buffered in a caller-provided
+/// buffer, which should be at least as large as the maximum message size.
+///
+/// When a host sends a malformed or oversized message, `SegmentedHost` replies
+/// with an appropriate [`protocol::Error`] on its own, and returns an error
+/// from [`HostPort::receive()`]:
+/// - Packets that are longer than the maximum packet size result in
+///   [`ErrorCode::InvalidPacketLength`] and [`Error::BadPacket`].
+/// - Packets that are out of order result in
+///   [`ErrorCode::OutOfOrderMessage`] and [`Error::BadPacket`].
+/// - Messages that exceed the maximum message size result in
+///   [`ErrorCode::MessageOverflow`] and [`Error::MessageTooLong`].
+///
+/// Responses that exceed the maximum message size fail with an I/O error
+/// while being written, and are never sent.
+pub struct SegmentedHost<'buf, P>(Inner<'buf, P>);
+
+/// The actual guts of a `SegmentedHost`; see `InMemInner` for why this needs
+/// to be separate.
+struct Inner<'buf, P> {
+    port: P,
+    max_message_size: usize,
+    max_packet_size: usize,
+    buf: &'buf mut [u8],
+
+    /// Whether we are discarding packets until the start of a new message.
+    resync: bool,
+
+    rx_header: Option<Header>,
+    // Invariant: rx_cursor <= rx_len <= buf.len().
+    rx_cursor: usize,
+    rx_len: usize,
+
+    tx_header: Option<Header>,
+    // Invariant: tx_len <= buf.len().
+    tx_len: usize,
+    finished: bool,
+}
+
+impl<'buf, P: PacketPort> SegmentedHost<'buf, P> {
+    /// Creates a new `SegmentedHost` wrapping `port`, with limits taken from
+    /// `networking`.
+    ///
+    /// `buf` is used to assemble messages; it must have room for at least a
+    /// [`Header`]. Messages will not exceed either `buf.len()` or
+    /// `networking.max_message_size`.
+    pub fn new(
+        port: P,
+        networking: &Networking,
+        buf: &'buf mut [u8],
+    ) -> Result<Self, OutOfMemory> {
+        if buf.len() < HEADER_LEN {
+            return Err(OutOfMemory);
+        }
+
+        let mut host = Self(Inner {
+            port,
+            max_message_size: 0,
+            max_packet_size: 0,
+            buf,
+            resync: false,
+            rx_header: None,
+            rx_cursor: 0,
+            rx_len: 0,
+            tx_header: None,
+            tx_len: 0,
+            finished: false,
+        });
+        host.set_limits(networking);
+        Ok(host)
+    }
+
+    /// Updates the message and packet size limits, such as after
+    /// capabilities negotiation with a host.
+    pub fn set_limits(&mut self, networking: &Networking) {
+        self.0.max_message_size = (networking.max_message_size as usize)
+            .min(self.0.buf.len())
+            .max(HEADER_LEN);
+        self.0.max_packet_size = (networking.max_packet_size as usize).max(1);
+    }
+
+    /// Returns the maximum message size, including the header, that this port
+    /// will accept or send.
+    pub fn max_message_size(&self) -> usize {
+        self.0.max_message_size
+    }
+
+    /// Returns the maximum number of bytes of message data that this port will
+    /// accept or send in a single packet.
+    pub fn max_packet_size(&self) -> usize {
+        self.0.max_packet_size
+    }
+
+    /// Returns a reference to the underlying [`PacketPort`].
+    pub fn packet_port(&mut self) -> &mut P {
+        &mut self.0.port
+    }
+}
+
+impl<P: PacketPort> HostPort for SegmentedHost<'_, P> {
+    fn receive(&mut self) -> Result<&mut dyn HostRequest, Error> {
+        self.0.receive_message()?;
+        Ok(&mut self.0)
+    }
+}
+
+impl<P: PacketPort> Inner<'_, P> {
+    /// Reads packets until a complete message is assembled in `buf`.
+    fn receive_message(&mut self) -> Result<(), Error> {
+        self.rx_header = None;
+        self.tx_header = None;
+        self.rx_cursor = 0;
+        self.rx_len = 0;
+        self.finished = false;
+
+        let mut expected_seq = None;
+        let mut overflowed = false;
+        loop {
+            // Read directly into the message buffer. If the rest of the
+            // buffer is smaller than a packet, we can still detect
+            // oversized packets and messages, since the full length is
+            // reported.
+            let limit =
+                (self.rx_len + self.max_packet_size).min(self.max_message_size);
+            let (header, len) = self
+                .port
+                .receive_packet(&mut self.buf[self.rx_len..limit])?;
+
+            match expected_seq {
+                None if self.resync && !header.start_of_message => continue,
+                None if !header.start_of_message => {
+                    self.resync = true;
+                    self.reply_error(ErrorCode::OutOfOrderMessage)?;
+                    return Err(Error::BadPacket);
+                }
+                Some(_) if header.start_of_message => {
+                    // The host abandoned the message in progress and started
+                    // a new one, so start reassembly over from this packet.
+                    let received = len.min(limit - self.rx_len);
+                    let fresh_limit =
+                        self.max_packet_size.min(self.max_message_size);
+                    if received < len.min(fresh_limit) {
+                        // Part of the packet did not fit behind the abandoned
+                        // message, so it cannot be recovered.
+                        self.resync = !header.end_of_message;
+                        self.reply_error(ErrorCode::OutOfOrderMessage)?;
+                        return Err(Error::BadPacket);
+                    }
+                    self.buf
+                        .copy_within(self.rx_len..self.rx_len + received, 0);
+                    self.rx_len = 0;
+                    overflowed = false;
+                }
+                Some(seq) if header.seq != seq => {
+                    self.resync = !header.end_of_message;
+                    self.reply_error(ErrorCode::OutOfOrderMessage)?;
+                    return Err(Error::BadPacket);
+                }
+                _ => {}
+            }
+            self.resync = false;
+
+            if len > self.max_packet_size {
+                self.resync = !header.end_of_message;
+                self.reply_error(ErrorCode::InvalidPacketLength)?;
+                return Err(Error::BadPacket);
+            }
+
+            // Once a message has overflowed, we keep reading packets (but not
+            // their contents) so that the reply comes after the last one.
+            if !overflowed {
+                match self.rx_len.checked_add(len) {
+                    Some(end) if end <= self.max_message_size => {
+                        self.rx_len = end
+                    }
+                    _ => overflowed = true,
+                }
+            }
+
+            if header.end_of_message {
+                break;
+            }
+            expected_seq = Some((header.seq + 1) % SEQ_MODULUS);
+        }
+
+        if overflowed {
+            self.reply_error(ErrorCode::MessageOverflow)?;
+            return Err(Error::MessageTooLong);
+        }
+
+        let mut message = &self.buf[..self.rx_len];
+        let header = Header::from_wire(&mut message, &OutOfMemory)
+            .map_err(|_| Error::BadHeader)?;
+        self.rx_header = Some(header);
+        self.rx_cursor = HEADER_LEN;
+        Ok(())
+    }
+
+    /// Sends a message consisting of a response header and the given error
+    /// code.
+    fn reply_error(&mut self, code: ErrorCode) -> Result<(), Error> {
+        self.begin_reply(Header {
+            is_request: false,
+            command: CommandType::Error.into(),
+        })?;
+        protocol::Error::new(code)
+            .to_wire(&mut *self)
+            .map_err(|_| Error::Io(io::Error::BufferExhausted))?;
+        self.send_message()
+    }
+
+    /// Prepares `buf` for writing a response with the given header.
+    fn begin_reply(&mut self, header: Header) -> Result<(), Error> {
+        self.rx_header = None;
+        self.tx_header = Some(header);
+        self.tx_len = 0;
+        self.finished = false;
+        header
+            .to_wire(&mut *self)
+            .map_err(|_| Error::Io(io::Error::BufferExhausted))
+    }
+
+    /// Splits the response in `buf` into packets and sends them.
+    fn send_message(&mut self) -> Result<(), Error> {
+        self.finished = true;
+        let message = &self.buf[..self.tx_len];
+        let packet_count =
+            (message.len() + self.max_packet_size - 1) / self.max_packet_size;
+        for (i, data) in message.chunks(self.max_packet_size).enumerate() {
+            let header = PacketHeader {
+                start_of_message: i == 0,
+                end_of_message: i + 1 == packet_count,
+                seq: (i % SEQ_MODULUS as usize) as u8,
+            };
+            self.port.send_packet(header, data)?;
+        }
+        Ok(())
+    }
+}
+
+impl<P> Read for Inner<'_, P> {
+    fn read_bytes(&mut self, out: &mut [u8]) -> Result<(), io::Error> {
+        let end = self
+            .rx_cursor
+            .checked_add(out.len())
+            .filter(|&end| end <= self.rx_len)
+            .ok_or(io::Error::BufferExhausted)?;
+        out.copy_from_slice(&self.buf[self.rx_cursor..end]);
+        self.rx_cursor = end;
+        Ok(())
+    }
+
+    fn remaining_data(&self) -> usize {
+        self.rx_len - self.rx_cursor
+    }
+}
+
+impl<P> Write for Inner<'_, P> {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), io::Error> {
+        let end = self
+            .tx_len
+            .checked_add(buf.len())
+            .filter(|&end| end <= self.max_message_size)
+            .ok_or(io::Error::BufferExhausted)?;
+        self.buf[self.tx_len..end].copy_from_slice(buf);
+        self.tx_len = end;
+        Ok(())
+    }
+}
+
+impl<P: PacketPort> HostRequest for Inner<'_, P> {
+    fn header(&self) -> Result<Header, Error> {
+        self.rx_header.ok_or(Error::OutOfOrder)
+    }
+
+    fn payload(&mut self) -> Result<&mut dyn Read, Error> {
+        if self.rx_header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(self)
+    }
+
+    fn reply(
+        &mut self,
+        header: Header,
+    ) -> Result<&mut dyn HostResponse, Error> {
+        if self.rx_header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        self.begin_reply(header)?;
+        Ok(self)
+    }
+}
+
+impl<P: PacketPort> HostResponse for Inner<'_, P> {
+    fn sink(&mut self) -> Result<&mut dyn Write, Error> {
+        if self.tx_header.is_none() || self.finished {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(self)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.tx_header.is_none() || self.finished {
+            return Err(Error::OutOfOrder);
+        }
+        self.send_message()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+
+    use crate::io::Cursor;
+    use crate::net;
+    use crate::protocol::capabilities::BusRole;
+    use crate::protocol::capabilities::RotMode;
+    use crate::protocol::wire::FromWire;
+    use crate::protocol::wire::ToWire;
+    use crate::protocol::CommandByte;
+
+    const NETWORKING: Networking = Networking {
+        max_message_size: 32,
+        max_packet_size: 8,
+        mode: RotMode::Platform,
+        roles: BusRole::HOST,
+    };
+
+    /// A fake `PacketPort` that replays a fixed list of packets.
+    #[derive(Default)]
+    struct FakePackets {
+        rx: VecDeque<(PacketHeader, Vec<u8>)>,
+        tx: Vec<(PacketHeader, Vec<u8>)>,
+    }
+
+    impl FakePackets {
+        /// Splits `message` into packets of size `packet_size`.
+        fn push_message(&mut self, message: &[u8], packet_size: usize) {
+            let count = (message.len() + packet_size - 1) / packet_size;
+            for (i, data) in message.chunks(packet_size).enumerate() {
+                let header = PacketHeader {
+                    start_of_message: i == 0,
+                    end_of_message: i + 1 == count,
+                    seq: (i % 4) as u8,
+                };
+                self.rx.push_back((header, data.to_vec()));
+            }
+        }
+
+        /// Reassembles the sent packets, checking that they are well-formed.
+        fn sent_message(&self) -> Vec<u8> {
+            let mut message = Vec::new();
+            for (i, (header, data)) in self.tx.iter().enumerate() {
+                assert_eq!(header.start_of_message, i == 0);
+                assert_eq!(header.end_of_message, i + 1 == self.tx.len());
+                assert_eq!(header.seq as usize, i % 4);
+                message.extend_from_slice(data);
+            }
+            message
+        }
+    }
+
+    impl PacketPort for FakePackets {
+        fn receive_packet(
+            &mut self,
+            out: &mut [u8],
+        ) -> Result<(PacketHeader, usize), Error> {
+            let (header, data) =
+                self.rx.pop_front().ok_or(Error::Disconnected)?;
+            let len = data.len().min(out.len());
+            out[..len].copy_from_slice(&data[..len]);
+            Ok((header, data.len()))
+        }
+
+        fn send_packet(
+            &mut self,
+            header: PacketHeader,
+            data: &[u8],
+        ) -> Result<(), Error> {
+            self.tx.push((header, data.to_vec()));
+            Ok(())
+        }
+    }
+
+    /// A fake `HostPort` that assembles messages out of its packets itself,
+    /// without enforcing any limits.
+    struct FakeHost {
+        packets: FakePackets,
+        host: net::InMemHost<'static>,
+    }
+
+    impl FakeHost {
+        fn new(packets: FakePackets) -> Self {
+            let out = Box::leak(vec![0; 64].into_boxed_slice());
+            Self {
+                packets,
+                host: net::InMemHost::new(out),
+            }
+        }
+    }
+
+    impl HostPort for FakeHost {
+        fn receive(&mut self) -> Result<&mut dyn HostRequest, Error> {
+            let mut message = Vec::new();
+            loop {
+                let (header, data) =
+                    self.packets.rx.pop_front().ok_or(Error::Disconnected)?;
+                message.extend(data);
+                if header.end_of_message {
+                    break;
+                }
+            }
+
+            let mut message: &'static [u8] = Box::leak(message.into());
+            let header = Header::from_wire(&mut message, &OutOfMemory)
+                .map_err(|_| Error::BadHeader)?;
+            self.host.request(header, message);
+            self.host.receive()
+        }
+    }
+
+    impl PacketHostPort for FakeHost {
+        fn receive_packet(
+            &mut self,
+            out: &mut [u8],
+        ) -> Result<(PacketHeader, usize), Error> {
+            self.packets.receive_packet(out)
+        }
+
+        fn send_packet(
+            &mut self,
+            header: PacketHeader,
+            data: &[u8],
+        ) -> Result<(), Error> {
+            self.packets.send_packet(header, data)
+        }
+    }
+
+    fn encode(header: Header, payload: &[u8]) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let mut cursor = Cursor::new(&mut buf);
+        header.to_wire(&mut cursor).unwrap();
+        cursor.write_bytes(payload).unwrap();
+        cursor.consumed_bytes().to_vec()
+    }
+
+    fn sent_error(packets: &FakePackets) -> ErrorCode {
+        let message = packets.sent_message();
+        let mut r = &message[..];
+        let header = Header::from_wire(&mut r, &OutOfMemory).unwrap();
+        assert_eq!(header.command, CommandType::Error);
+        protocol::Error::from_wire(&mut r, &OutOfMemory)
+            .unwrap()
+            .code
+    }
+
+    const REQ_HEADER: Header = Header {
+        is_request: true,
+        command: CommandByte(0xc5),
+    };
+
+    #[test]
+    fn round_trip() {
+        let payload = (0..20).collect::<Vec<u8>>();
+        let mut packets = FakePackets::default();
+        packets.push_message(&encode(REQ_HEADER, &payload), 8);
+
+        let mut buf = [0; 64];
+        let mut host =
+            SegmentedHost::new(&mut packets, &NETWORKING, &mut buf).unwrap();
+        let req = host.receive().unwrap();
+        assert_eq!(req.header().unwrap(), REQ_HEADER);
+
+        let mut received = [0; 20];
+        let r = req.payload().unwrap();
+        assert_eq!(r.remaining_data(), 20);
+        r.read_bytes(&mut received).unwrap();
+        assert_eq!(&received[..], &payload[..]);
+
+        let resp_header = Header {
+            is_request: false,
+            ..REQ_HEADER
+        };
+        let resp = req.reply(resp_header).unwrap();
+        resp.sink().unwrap().write_bytes(&[0xaa; 25]).unwrap();
+        resp.finish().unwrap();
+
+        assert_eq!(packets.tx.len(), 4);
+        assert_eq!(packets.sent_message(), encode(resp_header, &[0xaa; 25]));
+    }
+
+    #[test]
+    fn host_packets() {
+        let mut packets = FakePackets::default();
+        packets.push_message(&encode(REQ_HEADER, &[0; 10]), 12);
+        packets.push_message(&encode(REQ_HEADER, &[0; 10]), 12);
+        let mut fake = FakeHost::new(packets);
+
+        // On its own, the port accepts packets of any size...
+        let req = fake.receive().unwrap();
+        assert_eq!(req.payload().unwrap().remaining_data(), 10);
+
+        // ...but not once it is wrapped.
+        let mut buf = [0; 64];
+        let mut host =
+            SegmentedHost::new(HostPackets(fake), &NETWORKING, &mut buf)
+                .unwrap();
+        assert!(matches!(host.receive(), Err(Error::BadPacket)));
+
+        let fake = &host.packet_port().0;
+        assert_eq!(sent_error(&fake.packets), ErrorCode::InvalidPacketLength);
+    }
+
+    #[test]
+    fn oversized_message() {
+        let mut packets = FakePackets::default();
+        packets.push_message(&encode(REQ_HEADER, &[0; 40]), 8);
+        packets.push_message(&encode(REQ_HEADER, &[1, 2, 3]), 8);
+
+        let mut buf = [0; 64];
+        let mut host =
+            SegmentedHost::new(&mut packets, &NETWORKING, &mut buf).unwrap();
+        assert!(matches!(host.receive(), Err(Error::MessageTooLong)));
+
+        // The next message should be processed normally.
+        let req = host.receive().unwrap();
+        assert_eq!(req.payload().unwrap().remaining_data(), 3);
+
+        drop(host);
+        assert_eq!(sent_error(&packets), ErrorCode::MessageOverflow);
+    }
+
+    #[test]
+    fn oversized_response() {
+        let mut packets = FakePackets::default();
+        packets.push_message(&encode(REQ_HEADER, &[]), 8);
+
+        let mut buf = [0; 64];
+        let mut host =
+            SegmentedHost::new(&mut packets, &NETWORKING, &mut buf).unwrap();
+        let req = host.receive().unwrap();
+        let resp = req.reply(REQ_HEADER).unwrap();
+        assert!(resp.sink().unwrap().write_bytes(&[0; 28]).is_err());
+    }
+
+    #[test]
+    fn oversized_packet() {
+        let mut packets = FakePackets::default();
+        packets.push_message(&encode(REQ_HEADER, &[0; 10]), 12);
+
+        let mut buf = [0; 64];
+        let mut host =
+            SegmentedHost::new(&mut packets, &NETWORKING, &mut buf).unwrap();
+        assert!(matches!(host.receive(), Err(Error::BadPacket)));
+
+        drop(host);
+        assert_eq!(sent_error(&packets), ErrorCode::InvalidPacketLength);
+    }
+
+    #[test]
+    fn restart_mid_message() {
+        let mut packets = FakePackets::default();
+        packets.push_message(&encode(REQ_HEADER, &[0; 10]), 8);
+        packets.rx.pop_back();
+        packets.push_message(&encode(REQ_HEADER, &[1, 2, 3, 4, 5]), 4);
+
+        let mut buf = [0; 64];
+        let mut host =
+            SegmentedHost::new(&mut packets, &NETWORKING, &mut buf).unwrap();
+        let req = host.receive().unwrap();
+        assert_eq!(req.header().unwrap(), REQ_HEADER);
+
+        let mut received = [0; 5];
+        let r = req.payload().unwrap();
+        assert_eq!(r.remaining_data(), 5);
+        r.read_bytes(&mut received).unwrap();
+        assert_eq!(received, [1, 2, 3, 4, 5]);
+
+        drop(host);
+        assert!(packets.tx.is_empty());
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut packets = FakePackets::default();
+        packets.push_message(&encode(REQ_HEADER, &[0; 10]), 8);
+        packets.rx.swap(0, 1);
+
+        let mut buf = [0; 64];
+        let mut host =
+            SegmentedHost::new(&mut packets, &NETWORKING, &mut buf).unwrap();
+        assert!(matches!(host.receive(), Err(Error::BadPacket)));
+
+        drop(host);
+        assert_eq!(sent_error(&packets), ErrorCode::OutOfOrderMessage);
+    }
+}
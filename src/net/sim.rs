@@ -0,0 +1,771 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A simulated multi-device bus.
+//!
+//! This module provides [`Bus`], an in-memory model of a bus shared by many
+//! addressable devices, for end-to-end tests of topologies such as a PA-RoT
+//! attesting several AC-RoTs. Each device attaches to the bus at an address,
+//! as a [`SimHost`] (a [`HostPort`] which serves requests sent to that
+//! address) and/or as a [`SimDevice`] (a [`DevicePort`] which sends requests
+//! from that address to other devices).
+//!
+//! The bus runs on a simulated clock, rather than on wall-clock time, so that
+//! tests are fast and deterministic. Messages may be delayed, dropped or
+//! corrupted according to the [`Faults`] configured for their destination;
+//! randomness is drawn from a seeded generator, so a given seed always
+//! produces the same faults.
+//!
+//! # Timing
+//!
+//! Every message is delivered at the time it was sent plus the latency of its
+//! destination. [`HostPort::receive()`] advances the clock to the delivery
+//! time of the next request, returning [`Error::Timeout`] if none are in
+//! flight. [`DevicePort::wait_for_response()`] advances the clock to the
+//! delivery time of the response, or, if it would not arrive within the
+//! requested duration, by that duration, returning [`Error::Timeout`].
+
+use core::cell::RefCell;
+use core::time::Duration;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use crate::io::Read;
+use crate::io::Write as _;
+use crate::net::socket::RxFrame;
+use crate::net::socket::TxFrame;
+use crate::net::DevicePort;
+use crate::net::DeviceResponse;
+use crate::net::Error;
+use crate::net::Header;
+use crate::net::HostPort;
+use crate::net::HostRequest;
+use crate::net::HostResponse;
+
+/// Faults to inject into messages delivered to some address.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Faults {
+    /// How long a message takes to be delivered.
+    pub latency: Duration,
+    /// The probability, as a percentage, that a message is silently dropped.
+    pub drop_percent: u8,
+    /// The probability, as a percentage, that a single bit of a message is
+    /// flipped.
+    pub corrupt_percent: u8,
+}
+
+/// A message in flight on the bus.
+struct Message {
+    src: u8,
+    dest: u8,
+    is_request: bool,
+    deliver_at: Duration,
+    frame: Vec<u8>,
+}
+
+/// The shared state of a [`Bus`].
+struct State {
+    now: Duration,
+    rng: u64,
+    default_faults: Faults,
+    faults: Vec<(u8, Faults)>,
+    in_flight: Vec<Message>,
+    attached: Vec<(u8, bool)>,
+}
+
+impl State {
+    /// Returns a pseudorandom number in `0..100`.
+    fn percentile(&mut self) -> u8 {
+        // xorshift64; see Marsaglia, "Xorshift RNGs".
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % 100) as u8
+    }
+
+    fn faults(&self, dest: u8) -> Faults {
+        self.faults
+            .iter()
+            .find(|&&(addr, _)| addr == dest)
+            .map(|&(_, f)| f)
+            .unwrap_or(self.default_faults)
+    }
+
+    /// Puts a message on the bus, subject to the destination's faults.
+    fn send(&mut self, src: u8, dest: u8, is_request: bool, frame: &[u8]) {
+        let faults = self.faults(dest);
+        if self.percentile() < faults.drop_percent {
+            return;
+        }
+
+        let mut frame = frame.to_vec();
+        if !frame.is_empty() && self.percentile() < faults.corrupt_percent {
+            let bit = self.rng as usize % (frame.len() * 8);
+            frame[bit / 8] ^= 1 << (bit % 8);
+        }
+
+        self.in_flight.push(Message {
+            src,
+            dest,
+            is_request,
+            deliver_at: self.now + faults.latency,
+            frame,
+        });
+    }
+
+    /// Removes and returns the first message to be delivered that matches
+    /// `pred`, so long as it is delivered no later than `deadline`.
+    ///
+    /// The clock is advanced to the message's delivery time.
+    fn take(
+        &mut self,
+        deadline: Option<Duration>,
+        pred: impl Fn(&Message) -> bool,
+    ) -> Option<Message> {
+        let (idx, msg) = self
+            .in_flight
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| pred(m))
+            .min_by_key(|(_, m)| m.deliver_at)?;
+        if deadline.map(|d| msg.deliver_at > d).unwrap_or(false) {
+            return None;
+        }
+
+        let msg = self.in_flight.remove(idx);
+        self.now = self.now.max(msg.deliver_at);
+        Some(msg)
+    }
+
+    fn attach(&mut self, addr: u8, is_host: bool) {
+        assert!(
+            !self.attached.contains(&(addr, is_host)),
+            "address {:#04x} is already attached to the bus",
+            addr
+        );
+        self.attached.push((addr, is_host));
+    }
+}
+
+/// A simulated bus, connecting many [`SimHost`]s and [`SimDevice`]s.
+///
+/// See the [module documentation](index.html) for details.
+///
+/// Cloning a `Bus` produces another handle to the same bus.
+#[derive(Clone)]
+pub struct Bus(Rc<RefCell<State>>);
+
+impl Bus {
+    /// Creates a new bus, whose faults are determined by `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self(Rc::new(RefCell::new(State {
+            now: Duration::default(),
+            // xorshift is stuck at zero, so avoid it.
+            rng: seed | 1,
+            default_faults: Faults::default(),
+            faults: Vec::new(),
+            in_flight: Vec::new(),
+            attached: Vec::new(),
+        })))
+    }
+
+    /// Returns the current time on the bus's simulated clock.
+    pub fn now(&self) -> Duration {
+        self.0.borrow().now
+    }
+
+    /// Sets the faults for messages delivered to `addr`.
+    pub fn set_faults(&self, addr: u8, faults: Faults) {
+        let mut state = self.0.borrow_mut();
+        state.faults.retain(|&(a, _)| a != addr);
+        state.faults.push((addr, faults));
+    }
+
+    /// Sets the faults for messages delivered to addresses that have not had
+    /// faults set with [`Bus::set_faults()`].
+    pub fn set_default_faults(&self, faults: Faults) {
+        self.0.borrow_mut().default_faults = faults;
+    }
+
+    /// Attaches a [`SimHost`] to the bus, which serves requests sent to
+    /// `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a `SimHost` is already attached at `addr`.
+    pub fn host(&self, addr: u8) -> SimHost {
+        self.0.borrow_mut().attach(addr, true);
+        SimHost(SimHostInner {
+            bus: self.clone(),
+            addr,
+            src: 0,
+            rx: RxFrame::default(),
+            tx: TxFrame::default(),
+            replying: false,
+        })
+    }
+
+    /// Attaches a [`SimDevice`] to the bus, which sends requests from
+    /// `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a `SimDevice` is already attached at `addr`.
+    pub fn device(&self, addr: u8) -> SimDevice {
+        self.0.borrow_mut().attach(addr, false);
+        SimDevice(SimDeviceInner {
+            bus: self.clone(),
+            addr,
+            dest: None,
+            rx: RxFrame::default(),
+            tx: TxFrame::default(),
+        })
+    }
+}
+
+/// A [`HostPort`] attached to a [`Bus`].
+///
+/// [`HostRequest::host_id()`] reports the address of the device that sent
+/// the request.
+pub struct SimHost(SimHostInner);
+
+/// The connection state of a `SimHost`.
+struct SimHostInner {
+    bus: Bus,
+    addr: u8,
+    src: u8,
+    rx: RxFrame,
+    tx: TxFrame,
+    replying: bool,
+}
+
+impl SimHost {
+    /// Returns the address this host is attached at.
+    pub fn addr(&self) -> u8 {
+        self.0.addr
+    }
+}
+
+impl HostPort for SimHost {
+    fn receive(&mut self) -> Result<&mut dyn HostRequest, Error> {
+        let inner = &mut self.0;
+        inner.replying = false;
+        inner.rx.header = None;
+
+        let addr = inner.addr;
+        let msg = inner
+            .bus
+            .0
+            .borrow_mut()
+            .take(None, |m| m.is_request && m.dest == addr)
+            .ok_or(Error::Timeout)?;
+
+        inner.src = msg.src;
+        inner.rx.buf = msg.frame;
+        inner.rx.parse()?;
+        Ok(inner)
+    }
+}
+
+impl HostRequest for SimHostInner {
+    fn header(&self) -> Result<Header, Error> {
+        self.rx.header.ok_or(Error::OutOfOrder)
+    }
+
+    fn host_id(&self) -> u8 {
+        self.src
+    }
+
+    fn payload(&mut self) -> Result<&mut dyn Read, Error> {
+        if self.rx.header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(&mut self.rx)
+    }
+
+    fn reply(
+        &mut self,
+        header: Header,
+    ) -> Result<&mut dyn HostResponse, Error> {
+        if self.rx.header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        self.rx.header = None;
+
+        self.tx.start(header)?;
+        self.replying = true;
+        Ok(self)
+    }
+}
+
+impl HostResponse for SimHostInner {
+    fn sink(&mut self) -> Result<&mut dyn crate::io::Write, Error> {
+        if !self.replying {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(&mut self.tx)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if !self.replying {
+            return Err(Error::OutOfOrder);
+        }
+        self.replying = false;
+        self.bus
+            .0
+            .borrow_mut()
+            .send(self.addr, self.src, false, &self.tx.0);
+        Ok(())
+    }
+}
+
+/// A [`DevicePort`] attached to a [`Bus`].
+///
+/// Sending a new request discards any responses to previous requests that
+/// have not been received yet, such as responses that arrived too late.
+pub struct SimDevice(SimDeviceInner);
+
+/// The connection state of a `SimDevice`.
+struct SimDeviceInner {
+    bus: Bus,
+    addr: u8,
+    /// The destination of the request awaiting a response, if any.
+    dest: Option<u8>,
+    rx: RxFrame,
+    tx: TxFrame,
+}
+
+impl SimDevice {
+    /// Returns the address this device is attached at.
+    pub fn addr(&self) -> u8 {
+        self.0.addr
+    }
+}
+
+impl DevicePort for SimDevice {
+    fn send(
+        &mut self,
+        dest: u8,
+        header: Header,
+        msg: &[u8],
+    ) -> Result<(), Error> {
+        let inner = &mut self.0;
+        inner.rx.header = None;
+
+        inner.tx.start(header)?;
+        inner
+            .tx
+            .write_bytes(msg)
+            .map_err(|_| Error::MessageTooLong)?;
+
+        let addr = inner.addr;
+        let mut state = inner.bus.0.borrow_mut();
+        state.in_flight.retain(|m| m.is_request || m.dest != addr);
+        state.send(addr, dest, true, &inner.tx.0);
+        inner.dest = Some(dest);
+        Ok(())
+    }
+
+    fn wait_for_response(&mut self, duration: usize) -> Result<(), Error> {
+        let inner = &mut self.0;
+        let dest = inner.dest.ok_or(Error::OutOfOrder)?;
+        let addr = inner.addr;
+
+        let mut state = inner.bus.0.borrow_mut();
+        let deadline = state.now + Duration::from_millis(duration as u64);
+        let msg = match state.take(Some(deadline), |m| {
+            !m.is_request && m.dest == addr && m.src == dest
+        }) {
+            Some(msg) => msg,
+            None => {
+                state.now = deadline;
+                return Err(Error::Timeout);
+            }
+        };
+
+        inner.dest = None;
+        inner.rx.buf = msg.frame;
+        inner.rx.parse()
+    }
+
+    fn receive_response(&mut self) -> Result<&mut dyn DeviceResponse, Error> {
+        if self.0.rx.header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(&mut self.0)
+    }
+}
+
+impl DeviceResponse for SimDeviceInner {
+    fn header(&self) -> Result<Header, Error> {
+        self.rx.header.ok_or(Error::OutOfOrder)
+    }
+
+    fn payload(&mut self) -> Result<&mut dyn Read, Error> {
+        if self.rx.header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(&mut self.rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring;
+    use crate::hardware::fake;
+    use crate::hardware::Identity as _;
+    use crate::io::Cursor;
+    use crate::mem::Arena;
+    use crate::mem::BumpArena;
+    use crate::protocol;
+    use crate::protocol::capabilities::*;
+    use crate::protocol::device_id::DeviceIdentifier;
+    use crate::protocol::firmware_version::FirmwareVersionRequest;
+    use crate::protocol::wire::FromWire;
+    use crate::protocol::wire::ToWire;
+    use crate::protocol::Command;
+    use crate::protocol::Request as _;
+    use crate::protocol::Response as _;
+    use crate::server;
+    use crate::server::pa_rot;
+    use crate::server::pa_rot::PaRot;
+
+    const PA_ROT: u8 = 0x01;
+    const AC_ROT: u8 = 0x10;
+
+    /// Sends `req` from `device` to `dest`.
+    fn send<'a, C: Command<'a>>(device: &mut SimDevice, dest: u8, req: C::Req) {
+        let mut buf = [0; 64];
+        let mut cursor = Cursor::new(&mut buf);
+        req.to_wire(&mut cursor).unwrap();
+        let header = Header {
+            command: C::Req::TYPE,
+            is_request: true,
+        };
+        device.send(dest, header, cursor.consumed_bytes()).unwrap();
+    }
+
+    /// Waits `timeout` milliseconds for a response to a request sent with
+    /// `send()`.
+    fn receive<'a, C: Command<'a>, A: Arena>(
+        device: &mut SimDevice,
+        timeout: usize,
+        arena: &'a A,
+    ) -> Result<C::Resp, Error> {
+        device.wait_for_response(timeout)?;
+        let resp = device.receive_response()?;
+        if resp.header()?.command != C::Resp::TYPE {
+            return Err(Error::BadHeader);
+        }
+        C::Resp::from_wire(resp.payload()?, arena).map_err(|_| Error::BadPacket)
+    }
+
+    type Server<'a> =
+        PaRot<'a, fake::Identity, fake::Reset, ring::rsa::Builder>;
+
+    /// Returns the options for a RoT server with the given identity and
+    /// device ID.
+    fn options<'a>(
+        identity: &'a fake::Identity,
+        reset: &'a fake::Reset,
+        rsa: &'a ring::rsa::Builder,
+        device_id: u16,
+    ) -> pa_rot::Options<'a, fake::Identity, fake::Reset, ring::rsa::Builder>
+    {
+        pa_rot::Options {
+            device_id: DeviceIdentifier {
+                device_id,
+                ..pa_rot::test::DEVICE_ID
+            },
+            networking: Networking {
+                mode: RotMode::Active,
+                roles: BusRole::TARGET,
+                ..pa_rot::test::NETWORKING
+            },
+            ..pa_rot::test::options(identity, reset, rsa)
+        }
+    }
+
+    /// Has `server` process requests sent to `host` until there are none
+    /// left, returning how many it processed.
+    fn serve(
+        server: &mut Server,
+        host: &mut SimHost,
+        arena: &mut BumpArena,
+    ) -> usize {
+        let mut count = 0;
+        loop {
+            let result = server.process_request(host, &*arena);
+            arena.reset();
+            match result {
+                Err(server::Error::Network(Error::Timeout)) => return count,
+                _ => count += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn attestation() {
+        const GOOD_FW: &[u8] = b"good firmware";
+        let ac_rots: [(u8, &[u8]); 3] = [
+            (0x10, GOOD_FW),
+            (0x11, b"evil firmware"),
+            // This AC-RoT is on a bad link, and never hears from the PA-RoT.
+            (0x12, GOOD_FW),
+        ];
+
+        let bus = Bus::new(0xc0ffee);
+        bus.set_faults(
+            0x12,
+            Faults {
+                drop_percent: 100,
+                ..Faults::default()
+            },
+        );
+
+        let reset = fake::Reset::new(0, Duration::from_millis(1));
+        let rsa = ring::rsa::Builder::new();
+        let pa_identity = fake::Identity::new(b"pa-rot", &[], b"pa-rot bits");
+        let mut pa_server = PaRot::new(options(&pa_identity, &reset, &rsa, 0));
+        let mut pa_host = bus.host(PA_ROT);
+        let mut pa_device = bus.device(PA_ROT);
+
+        // Every AC-RoT is up at once, both serving the PA-RoT and making
+        // requests of it.
+        let identities = ac_rots
+            .iter()
+            .map(|&(_, version)| fake::Identity::new(version, &[], b"bits"))
+            .collect::<Vec<_>>();
+        let mut nodes = ac_rots
+            .iter()
+            .zip(&identities)
+            .map(|(&(addr, _), identity)| {
+                let server =
+                    PaRot::new(options(identity, &reset, &rsa, addr as u16));
+                (addr, server, bus.host(addr), bus.device(addr))
+            })
+            .collect::<Vec<_>>();
+
+        // The firmware version the PA-RoT expects to measure.
+        let good = fake::Identity::new(GOOD_FW, &[], b"bits");
+
+        let mut arena = [0; 128];
+        let mut arena = BumpArena::new(&mut arena);
+        let mut verdicts = Vec::new();
+        for &(addr, _) in &ac_rots {
+            for step in 0..2 {
+                if step == 0 {
+                    send::<protocol::DeviceId>(
+                        &mut pa_device,
+                        addr,
+                        protocol::device_id::DeviceIdRequest,
+                    );
+                } else {
+                    send::<protocol::FirmwareVersion>(
+                        &mut pa_device,
+                        addr,
+                        FirmwareVersionRequest { index: 0 },
+                    );
+                }
+
+                // Meanwhile, every AC-RoT negotiates with the PA-RoT, each
+                // asking for a different maximum message size.
+                for (addr, server, _, device) in &mut nodes {
+                    send::<protocol::DeviceCapabilities>(
+                        device,
+                        PA_ROT,
+                        DeviceCapabilitiesRequest {
+                            capabilities: Capabilities {
+                                networking: Networking {
+                                    max_message_size: *addr as u16 + step,
+                                    ..server.capabilities().networking
+                                },
+                                ..server.capabilities()
+                            },
+                        },
+                    );
+                }
+
+                // The PA-RoT serves all of the AC-RoTs' requests, while one
+                // of them serves the PA-RoT's.
+                let mut served = 0;
+                for (_, server, host, _) in &mut nodes {
+                    served += serve(server, host, &mut arena);
+                }
+                assert_eq!(served, (addr != 0x12) as usize);
+                assert_eq!(serve(&mut pa_server, &mut pa_host, &mut arena), 3);
+
+                for (addr, _, _, device) in &mut nodes {
+                    let resp = receive::<protocol::DeviceCapabilities, _>(
+                        device, 0, &arena,
+                    );
+                    match resp {
+                        Ok(resp) => {
+                            assert_eq!(
+                                resp.capabilities,
+                                pa_server.capabilities()
+                            )
+                        }
+                        Err(e) => {
+                            assert_eq!(*addr, 0x12);
+                            assert!(matches!(e, Error::Timeout));
+                        }
+                    }
+                    arena.reset();
+                }
+
+                if step == 0 {
+                    let resp = receive::<protocol::DeviceId, _>(
+                        &mut pa_device,
+                        100,
+                        &arena,
+                    );
+                    match resp {
+                        Ok(resp) => {
+                            assert_ne!(addr, 0x12);
+                            assert_eq!(resp.id.device_id, addr as u16);
+                        }
+                        Err(e) => {
+                            assert_eq!(addr, 0x12);
+                            assert!(matches!(e, Error::Timeout));
+                        }
+                    }
+                } else {
+                    let verdict = receive::<protocol::FirmwareVersion, _>(
+                        &mut pa_device,
+                        100,
+                        &arena,
+                    )
+                    .map(|resp| resp.version == good.firmware_version());
+                    verdicts.push(verdict);
+                }
+                arena.reset();
+            }
+        }
+
+        assert!(matches!(
+            &verdicts[..],
+            [Ok(true), Ok(false), Err(Error::Timeout)]
+        ));
+        // The PA-RoT kept each AC-RoT's negotiation apart.
+        for &(addr, _) in &ac_rots {
+            let caps = pa_server.host_capabilities(addr).unwrap();
+            assert_eq!(caps.networking.max_message_size, addr as u16 + 1);
+        }
+        // Two requests to the unreachable AC-RoT timed out.
+        assert_eq!(bus.now(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn latency() {
+        let bus = Bus::new(0);
+        let mut pa_rot = bus.device(PA_ROT);
+        let mut host = bus.host(AC_ROT);
+
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let identity = fake::Identity::new(b"firmware", &[], b"bits");
+        let reset = fake::Reset::new(0, Duration::from_millis(1));
+        let rsa = ring::rsa::Builder::new();
+        let mut server = PaRot::new(options(&identity, &reset, &rsa, 0));
+
+        // A response 50 ms away fits in a 50 ms timeout...
+        bus.set_default_faults(Faults {
+            latency: Duration::from_millis(50),
+            ..Faults::default()
+        });
+        send::<protocol::DeviceId>(
+            &mut pa_rot,
+            AC_ROT,
+            protocol::device_id::DeviceIdRequest,
+        );
+        server.process_request(&mut host, &arena).unwrap();
+        assert_eq!(bus.now(), Duration::from_millis(50));
+        arena.reset();
+        assert!(
+            receive::<protocol::DeviceId, _>(&mut pa_rot, 50, &arena).is_ok()
+        );
+        assert_eq!(bus.now(), Duration::from_millis(100));
+
+        // ...but one 70 ms away does not.
+        bus.set_faults(
+            PA_ROT,
+            Faults {
+                latency: Duration::from_millis(70),
+                ..Faults::default()
+            },
+        );
+        send::<protocol::DeviceId>(
+            &mut pa_rot,
+            AC_ROT,
+            protocol::device_id::DeviceIdRequest,
+        );
+        server.process_request(&mut host, &arena).unwrap();
+        arena.reset();
+        assert!(matches!(
+            receive::<protocol::DeviceId, _>(&mut pa_rot, 50, &arena),
+            Err(Error::Timeout)
+        ));
+        assert_eq!(bus.now(), Duration::from_millis(200));
+
+        // The late response is discarded once a new request is sent.
+        send::<protocol::DeviceId>(
+            &mut pa_rot,
+            AC_ROT,
+            protocol::device_id::DeviceIdRequest,
+        );
+        assert!(matches!(
+            receive::<protocol::DeviceId, _>(&mut pa_rot, 1000, &arena),
+            Err(Error::Timeout)
+        ));
+
+        // The last request is still waiting for the AC-RoT.
+        assert!(matches!(host.receive(), Ok(_)));
+        assert!(matches!(host.receive(), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn corruption() {
+        let bus = Bus::new(42);
+        bus.set_faults(
+            AC_ROT,
+            Faults {
+                corrupt_percent: 100,
+                ..Faults::default()
+            },
+        );
+        let mut pa_rot = bus.device(PA_ROT);
+        let mut host = bus.host(AC_ROT);
+
+        let header = Header {
+            command: protocol::CommandType::FirmwareVersion.into(),
+            is_request: true,
+        };
+        for _ in 0..32 {
+            pa_rot.send(AC_ROT, header, b"payload").unwrap();
+            match host.receive() {
+                Err(e) => assert!(matches!(e, Error::BadHeader)),
+                Ok(req) => {
+                    let mut payload = [0; 7];
+                    req.payload().unwrap().read_bytes(&mut payload).unwrap();
+                    assert!(
+                        req.header().unwrap() != header
+                            || &payload != b"payload"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn double_attach() {
+        let bus = Bus::new(0);
+        let _host = bus.host(AC_ROT);
+        let _device = bus.device(AC_ROT);
+        let _host = bus.host(AC_ROT);
+    }
+}
@@ -0,0 +1,565 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stream socket transports.
+//!
+//! This module provides [`SocketHost`], a [`HostPort`] that speaks to a host
+//! over any [`std::io::Read`] + [`std::io::Write`] byte stream, and
+//! [`SocketDevice`], a [`DevicePort`] that speaks to a device over a
+//! [`Socket`], such as a [`TcpStream`] or a [`UnixStream`]. They are intended
+//! for simulating devices on a development machine, rather than for use in
+//! firmware.
+//!
+//! # Frame Format
+//!
+//! Because a byte stream has no message boundaries, each message is sent as a
+//! frame: a little-endian `u16` length, followed by that many bytes. These
+//! bytes consist of an encoded [`Header`] followed by the message payload.
+//!
+//! # Errors
+//!
+//! Errors from the underlying stream which indicate that the peer went away,
+//! including reaching the end of the stream, are reported as
+//! [`Error::Disconnected`]. Errors indicating that a read timeout expired are
+//! reported as [`Error::Timeout`]; after a timeout, a partially-read frame is
+//! lost, so the connection should be dropped.
+
+use std::io::ErrorKind;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use std::vec::Vec;
+
+use crate::io;
+use crate::io::Read;
+use crate::io::Write;
+use crate::mem::OutOfMemory;
+use crate::net::DevicePort;
+use crate::net::DeviceResponse;
+use crate::net::Error;
+use crate::net::Header;
+use crate::net::HostPort;
+use crate::net::HostRequest;
+use crate::net::HostResponse;
+use crate::protocol::wire::FromWire as _;
+use crate::protocol::wire::ToWire as _;
+use crate::protocol::HEADER_LEN;
+
+/// The maximum length of a frame, excluding its length prefix.
+pub const MAX_FRAME_LEN: usize = u16::MAX as usize;
+
+/// A byte stream with a configurable read timeout.
+///
+/// This trait is implemented for [`TcpStream`] and, on Unix platforms,
+/// [`UnixStream`].
+pub trait Socket: std::io::Read + std::io::Write {
+    /// Sets the read timeout for this socket; `None` means reads block
+    /// indefinitely.
+    ///
+    /// A zero timeout is rounded up to the smallest timeout the platform
+    /// accepts.
+    fn set_read_timeout(
+        &self,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn set_read_timeout(
+        &self,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout.map(nonzero))
+    }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn set_read_timeout(
+        &self,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout.map(nonzero))
+    }
+}
+
+/// Rounds a zero duration up, since `std` rejects zero socket timeouts.
+fn nonzero(d: Duration) -> Duration {
+    d.max(Duration::from_millis(1))
+}
+
+/// Converts a [`std::io::Error`] into a networking [`Error`].
+fn from_std(e: std::io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::UnexpectedEof
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::BrokenPipe => Error::Disconnected,
+        // Unix platforms report an expired read timeout as `WouldBlock`.
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::Timeout,
+        _ => Error::Io(io::Error::Internal),
+    }
+}
+
+/// Reads a single frame from `stream` into `buf`, replacing its contents.
+///
+/// This is the receiving half of the frame format, for speaking it over
+/// streams other than a [`SocketHost`] or [`SocketDevice`].
+pub fn read_frame<S>(stream: &mut S, buf: &mut Vec<u8>) -> Result<(), Error>
+where
+    S: std::io::Read + ?Sized,
+{
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).map_err(from_std)?;
+    let len = u16::from_le_bytes(len) as usize;
+
+    buf.clear();
+    buf.resize(len, 0);
+    stream.read_exact(buf).map_err(from_std)
+}
+
+/// Writes `frame` to `stream`, prefixed with its length.
+///
+/// This is the sending half of the frame format, for speaking it over streams
+/// other than a [`SocketHost`] or [`SocketDevice`].
+pub fn write_frame<S>(stream: &mut S, frame: &[u8]) -> Result<(), Error>
+where
+    S: std::io::Write + ?Sized,
+{
+    if frame.len() > MAX_FRAME_LEN {
+        return Err(Error::MessageTooLong);
+    }
+    let len = (frame.len() as u16).to_le_bytes();
+    stream.write_all(&len).map_err(from_std)?;
+    stream.write_all(frame).map_err(from_std)?;
+    stream.flush().map_err(from_std)
+}
+
+/// A received frame, which is read from as a [`manticore::io::Read`].
+///
+/// This type is shared with [`net::sim`](../sim/index.html), which uses the
+/// same frame contents but no length prefix.
+///
+/// [`manticore::io::Read`]: ../../io/trait.Read.html
+#[derive(Default)]
+pub(super) struct RxFrame {
+    pub buf: Vec<u8>,
+    cursor: usize,
+    pub header: Option<Header>,
+}
+
+impl RxFrame {
+    /// Receives a new frame from `stream`, replacing the current one.
+    fn receive(
+        &mut self,
+        stream: &mut impl std::io::Read,
+    ) -> Result<(), Error> {
+        self.header = None;
+        read_frame(stream, &mut self.buf)?;
+        self.parse()
+    }
+
+    /// Parses the header out of the frame in `buf`.
+    pub fn parse(&mut self) -> Result<(), Error> {
+        self.header = None;
+        let mut frame = self.buf.as_slice();
+        let header = Header::from_wire(&mut frame, &OutOfMemory)
+            .map_err(|_| Error::BadHeader)?;
+        self.header = Some(header);
+        self.cursor = HEADER_LEN;
+        Ok(())
+    }
+}
+
+impl Read for RxFrame {
+    fn read_bytes(&mut self, out: &mut [u8]) -> Result<(), io::Error> {
+        let end = self.cursor + out.len();
+        if end > self.buf.len() {
+            return Err(io::Error::BufferExhausted);
+        }
+        out.copy_from_slice(&self.buf[self.cursor..end]);
+        self.cursor = end;
+        Ok(())
+    }
+
+    fn remaining_data(&self) -> usize {
+        self.buf.len() - self.cursor
+    }
+}
+
+/// A frame being built for transmission, which is written to as a
+/// [`manticore::io::Write`].
+///
+/// [`manticore::io::Write`]: ../../io/trait.Write.html
+#[derive(Default)]
+pub(super) struct TxFrame(pub Vec<u8>);
+
+impl TxFrame {
+    /// Clears this frame and writes `header` to it.
+    pub fn start(&mut self, header: Header) -> Result<(), Error> {
+        self.0.clear();
+        header
+            .to_wire(&mut *self)
+            .map_err(|_| Error::Io(io::Error::Internal))
+    }
+}
+
+impl Write for TxFrame {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), io::Error> {
+        if self.0.len() + buf.len() > MAX_FRAME_LEN {
+            return Err(io::Error::BufferExhausted);
+        }
+        self.0.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// A [`HostPort`] over a byte stream.
+///
+/// See the [module documentation](index.html) for the frame format.
+pub struct SocketHost<S>(SocketInner<S>);
+
+/// The connection state of a `SocketHost`; see [`InMemHost`] for why this is
+/// a separate type.
+///
+/// [`InMemHost`]: ../struct.InMemHost.html
+struct SocketInner<S> {
+    stream: S,
+    rx: RxFrame,
+    tx: TxFrame,
+    replying: bool,
+}
+
+impl<S> SocketHost<S>
+where
+    S: std::io::Read + std::io::Write,
+{
+    /// Creates a new `SocketHost` that speaks over `stream`.
+    pub fn new(stream: S) -> Self {
+        Self(SocketInner {
+            stream,
+            rx: RxFrame::default(),
+            tx: TxFrame::default(),
+            replying: false,
+        })
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn stream(&self) -> &S {
+        &self.0.stream
+    }
+
+    /// Consumes this `SocketHost`, returning the underlying stream.
+    pub fn into_stream(self) -> S {
+        self.0.stream
+    }
+}
+
+impl<S: Socket> SocketHost<S> {
+    /// Sets how long [`HostPort::receive()`] will wait for a request before
+    /// returning [`Error::Timeout`]; `None` means it waits indefinitely.
+    pub fn set_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<(), Error> {
+        self.0.stream.set_read_timeout(timeout).map_err(from_std)
+    }
+}
+
+impl<S> HostPort for SocketHost<S>
+where
+    S: std::io::Read + std::io::Write,
+{
+    fn receive(&mut self) -> Result<&mut dyn HostRequest, Error> {
+        let inner = &mut self.0;
+        inner.replying = false;
+        inner.rx.receive(&mut inner.stream)?;
+        Ok(inner)
+    }
+}
+
+impl<S> HostRequest for SocketInner<S>
+where
+    S: std::io::Read + std::io::Write,
+{
+    fn header(&self) -> Result<Header, Error> {
+        self.rx.header.ok_or(Error::OutOfOrder)
+    }
+
+    fn payload(&mut self) -> Result<&mut dyn Read, Error> {
+        if self.rx.header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(&mut self.rx)
+    }
+
+    fn reply(
+        &mut self,
+        header: Header,
+    ) -> Result<&mut dyn HostResponse, Error> {
+        if self.rx.header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        self.rx.header = None;
+
+        self.tx.start(header)?;
+        self.replying = true;
+        Ok(self)
+    }
+}
+
+impl<S> HostResponse for SocketInner<S>
+where
+    S: std::io::Read + std::io::Write,
+{
+    fn sink(&mut self) -> Result<&mut dyn Write, Error> {
+        if !self.replying {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(&mut self.tx)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if !self.replying {
+            return Err(Error::OutOfOrder);
+        }
+        self.replying = false;
+        write_frame(&mut self.stream, &self.tx.0)
+    }
+}
+
+/// A [`DevicePort`] over a [`Socket`].
+///
+/// A socket connects to exactly one device, so the `dest` argument of
+/// [`DevicePort::send()`] is ignored.
+///
+/// See the [module documentation](index.html) for the frame format.
+pub struct SocketDevice<S>(SocketDeviceInner<S>);
+
+/// The connection state of a `SocketDevice`.
+struct SocketDeviceInner<S> {
+    stream: S,
+    rx: RxFrame,
+    tx: TxFrame,
+    /// Whether a request has been sent that has not yet been responded to.
+    waiting: bool,
+}
+
+impl<S: Socket> SocketDevice<S> {
+    /// Creates a new `SocketDevice` that speaks over `stream`.
+    pub fn new(stream: S) -> Self {
+        Self(SocketDeviceInner {
+            stream,
+            rx: RxFrame::default(),
+            tx: TxFrame::default(),
+            waiting: false,
+        })
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn stream(&self) -> &S {
+        &self.0.stream
+    }
+
+    /// Consumes this `SocketDevice`, returning the underlying stream.
+    pub fn into_stream(self) -> S {
+        self.0.stream
+    }
+}
+
+impl<S: Socket> DevicePort for SocketDevice<S> {
+    fn send(
+        &mut self,
+        _dest: u8,
+        header: Header,
+        msg: &[u8],
+    ) -> Result<(), Error> {
+        let inner = &mut self.0;
+        inner.rx.header = None;
+
+        inner.tx.start(header)?;
+        inner
+            .tx
+            .write_bytes(msg)
+            .map_err(|_| Error::MessageTooLong)?;
+        write_frame(&mut inner.stream, &inner.tx.0)?;
+        inner.waiting = true;
+        Ok(())
+    }
+
+    fn wait_for_response(&mut self, duration: usize) -> Result<(), Error> {
+        let inner = &mut self.0;
+        if !inner.waiting {
+            return Err(Error::OutOfOrder);
+        }
+
+        let timeout = Duration::from_millis(duration as u64);
+        inner
+            .stream
+            .set_read_timeout(Some(timeout))
+            .map_err(from_std)?;
+        let result = inner.rx.receive(&mut inner.stream);
+        inner.stream.set_read_timeout(None).map_err(from_std)?;
+
+        result?;
+        inner.waiting = false;
+        Ok(())
+    }
+
+    fn receive_response(&mut self) -> Result<&mut dyn DeviceResponse, Error> {
+        if self.0.rx.header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(&mut self.0)
+    }
+}
+
+impl<S> DeviceResponse for SocketDeviceInner<S> {
+    fn header(&self) -> Result<Header, Error> {
+        self.rx.header.ok_or(Error::OutOfOrder)
+    }
+
+    fn payload(&mut self) -> Result<&mut dyn Read, Error> {
+        if self.rx.header.is_none() {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(&mut self.rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::protocol::CommandType;
+
+    /// A fake stream that reads from a fixed buffer and records writes.
+    struct Duplex {
+        rx: std::io::Cursor<Vec<u8>>,
+        tx: Vec<u8>,
+    }
+
+    impl Duplex {
+        fn new(rx: Vec<u8>) -> Self {
+            Self {
+                rx: std::io::Cursor::new(rx),
+                tx: Vec::new(),
+            }
+        }
+    }
+
+    impl std::io::Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.rx.read(buf)
+        }
+    }
+
+    impl std::io::Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.tx.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(header: Header, payload: &[u8]) -> Vec<u8> {
+        let mut msg = Vec::new();
+        header.to_wire(io::write::StdWrite(&mut msg)).unwrap();
+        msg.extend_from_slice(payload);
+
+        let mut out = Vec::new();
+        write_frame(&mut out, &msg).unwrap();
+        out
+    }
+
+    const REQ: Header = Header {
+        command: CommandType::FirmwareVersion.to_byte(),
+        is_request: true,
+    };
+    const RESP: Header = Header {
+        command: CommandType::FirmwareVersion.to_byte(),
+        is_request: false,
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut rx = frame(REQ, b"\x00");
+        rx.extend(frame(REQ, b"\x01"));
+        let mut host = SocketHost::new(Duplex::new(rx));
+
+        for &index in &[0u8, 1] {
+            let host_req = host.receive().unwrap();
+            assert_eq!(host_req.header().unwrap(), REQ);
+            let mut payload = host_req.payload().unwrap();
+            assert_eq!((&mut payload).read_le::<u8>().unwrap(), index);
+            assert_eq!(payload.remaining_data(), 0);
+
+            let host_resp = host_req.reply(RESP).unwrap();
+            host_resp.sink().unwrap().write_bytes(b"ok").unwrap();
+            host_resp.finish().unwrap();
+            assert!(host_resp.finish().is_err());
+        }
+
+        let mut expected = frame(RESP, b"ok");
+        expected.extend(frame(RESP, b"ok"));
+        assert_eq!(host.stream().tx, expected);
+
+        assert!(matches!(host.receive(), Err(Error::Disconnected)));
+    }
+
+    #[test]
+    fn bad_frames() {
+        let mut host = SocketHost::new(Duplex::new(b"\x03\x00abc".to_vec()));
+        assert!(matches!(host.receive(), Err(Error::BadHeader)));
+
+        let mut host = SocketHost::new(Duplex::new(b"\x08\x00abc".to_vec()));
+        assert!(matches!(host.receive(), Err(Error::Disconnected)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn host_and_device() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut device = SocketDevice::new(a);
+        let mut host = SocketHost::new(b);
+
+        assert!(matches!(
+            device.wait_for_response(10),
+            Err(Error::OutOfOrder)
+        ));
+        device.send(0, REQ, b"\x07").unwrap();
+
+        let host_req = host.receive().unwrap();
+        assert_eq!(host_req.header().unwrap(), REQ);
+        let mut payload = host_req.payload().unwrap();
+        assert_eq!((&mut payload).read_le::<u8>().unwrap(), 7);
+        let host_resp = host_req.reply(RESP).unwrap();
+        host_resp.sink().unwrap().write_bytes(b"ok").unwrap();
+        host_resp.finish().unwrap();
+
+        device.wait_for_response(1000).unwrap();
+        let resp = device.receive_response().unwrap();
+        assert_eq!(resp.header().unwrap(), RESP);
+        let payload = resp.payload().unwrap();
+        let mut ok = [0; 2];
+        payload.read_bytes(&mut ok).unwrap();
+        assert_eq!(&ok, b"ok");
+
+        // No response is coming, so waiting should time out.
+        device.send(0, REQ, b"\x07").unwrap();
+        assert!(matches!(device.wait_for_response(10), Err(Error::Timeout)));
+
+        host.set_timeout(Some(Duration::from_millis(10))).unwrap();
+        host.receive().unwrap();
+        assert!(matches!(host.receive(), Err(Error::Timeout)));
+
+        drop(host);
+        assert!(matches!(
+            device.wait_for_response(1000),
+            Err(Error::Disconnected)
+        ));
+    }
+}
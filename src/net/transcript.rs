@@ -0,0 +1,506 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transcript recording and replay.
+//!
+//! This module provides tools for capturing the traffic of a misbehaving
+//! device and reproducing it in a test: a [`Recorder`] wraps a [`HostPort`]
+//! and records each request it receives, along with the reply, into a
+//! [`Transcript`], which can be serialized. A [`Replay`] is a [`HostPort`]
+//! that feeds the requests in a `Transcript` to a server, recording the
+//! server's replies into a new `Transcript`; [`Transcript::diff()`] then
+//! finds where the two disagree.
+//!
+//! ```
+//! # use manticore::net::*;
+//! # use manticore::net::transcript::*;
+//! # fn process_request(port: &mut dyn HostPort) {
+//! #     if let Ok(req) = port.receive() {
+//! #         let h = req.header().unwrap();
+//! #         req.reply(h).unwrap().finish().unwrap();
+//! #     }
+//! # }
+//! # let recorded = Transcript::default();
+//! let mut replay = Replay::new(recorded.clone());
+//! for _ in &recorded.exchanges {
+//!     process_request(&mut replay);
+//! }
+//! assert!(recorded.diff(&replay.into_transcript()).is_empty());
+//! ```
+
+use std::vec::Vec;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::io;
+use crate::io::Read;
+use crate::io::Write;
+use crate::net::Error;
+use crate::net::Header;
+use crate::net::HostPort;
+use crate::net::HostRequest;
+use crate::net::HostResponse;
+
+/// A message, consisting of a header and a payload.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Message {
+    /// The message's header.
+    pub header: Header,
+    /// The message's encoded payload.
+    pub payload: Vec<u8>,
+}
+
+/// A request, and the reply to it, if there was one.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Exchange {
+    /// The [`HostRequest::host_id()`] of the host that sent the request.
+    pub host_id: u8,
+    /// The request.
+    pub request: Message,
+    /// The reply, if one was finished.
+    pub response: Option<Message>,
+}
+
+/// A recorded sequence of [`Exchange`]s.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Transcript {
+    /// The exchanges, in the order they occurred.
+    pub exchanges: Vec<Exchange>,
+}
+
+/// A disagreement between two [`Transcript`]s, found by
+/// [`Transcript::diff()`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Mismatch {
+    /// The index of the exchange which differs.
+    pub index: usize,
+    /// The exchange in the expected transcript, if it has one at `index`.
+    pub expected: Option<Exchange>,
+    /// The exchange in the actual transcript, if it has one at `index`.
+    pub actual: Option<Exchange>,
+}
+
+impl Transcript {
+    /// Compares this transcript, the expected one, with `actual`, returning
+    /// every exchange that differs between them.
+    pub fn diff(&self, actual: &Transcript) -> Vec<Mismatch> {
+        let len = self.exchanges.len().max(actual.exchanges.len());
+        (0..len)
+            .filter_map(|index| {
+                let expected = self.exchanges.get(index);
+                let actual = actual.exchanges.get(index);
+                if expected == actual {
+                    return None;
+                }
+                Some(Mismatch {
+                    index,
+                    expected: expected.cloned(),
+                    actual: actual.cloned(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A wrapper over a [`HostPort`] that records its traffic into a
+/// [`Transcript`].
+///
+/// Because a [`HostRequest`] borrows from the port that produced it, a
+/// wrapper cannot hand out the wrapped port's requests while also observing
+/// them. Instead, [`Recorder::record()`] receives a request from the wrapped
+/// port and hands it to a callback through a [`Replay`], forwarding whatever
+/// reply the callback makes back to the wrapped port.
+pub struct Recorder<P> {
+    port: P,
+    transcript: Transcript,
+}
+
+impl<P: HostPort> Recorder<P> {
+    /// Creates a new `Recorder` wrapping `port`.
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            transcript: Transcript::default(),
+        }
+    }
+
+    /// Receives a request from the wrapped port and calls `f` with a
+    /// [`HostPort`] that will yield it; typically, `f` will process it with
+    /// a server.
+    ///
+    /// If `f` replies to the request and finishes the reply, the reply is
+    /// forwarded to the wrapped port. Either way, the exchange is recorded;
+    /// unfinished replies are recorded as no reply at all.
+    ///
+    /// Errors from the wrapped port are returned without calling `f`.
+    pub fn record<R>(
+        &mut self,
+        f: impl FnOnce(&mut dyn HostPort) -> R,
+    ) -> Result<R, Error> {
+        let req = self.port.receive()?;
+        let header = req.header()?;
+        let host_id = req.host_id();
+        let payload = req.payload()?;
+        let mut bytes = vec![0; payload.remaining_data()];
+        payload.read_bytes(&mut bytes)?;
+
+        let mut replay = Replay::new(Transcript {
+            exchanges: vec![Exchange {
+                host_id,
+                request: Message {
+                    header,
+                    payload: bytes,
+                },
+                response: None,
+            }],
+        });
+        let result = f(&mut replay);
+
+        let exchange = replay
+            .into_transcript()
+            .exchanges
+            .pop()
+            .expect("a Replay records each request it receives");
+        if let Some(response) = &exchange.response {
+            let resp = req.reply(response.header)?;
+            resp.sink()?.write_bytes(&response.payload)?;
+            resp.finish()?;
+        }
+        self.transcript.exchanges.push(exchange);
+
+        Ok(result)
+    }
+
+    /// Returns the transcript recorded so far.
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    /// Consumes this `Recorder`, returning the wrapped port and the recorded
+    /// transcript.
+    pub fn into_parts(self) -> (P, Transcript) {
+        (self.port, self.transcript)
+    }
+}
+
+/// A [`HostPort`] that yields the requests in a [`Transcript`], in order,
+/// recording the replies to them.
+///
+/// Once every request has been received, [`HostPort::receive()`] returns
+/// [`Error::Disconnected`].
+pub struct Replay(ReplayInner);
+
+/// The connection state of a `Replay`.
+struct ReplayInner {
+    recorded: Vec<Exchange>,
+    actual: Transcript,
+    /// The read position in the current request's payload.
+    cursor: usize,
+    /// The reply being written, which is only recorded once finished.
+    pending: Option<Message>,
+    state: State,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    Received,
+    Replying,
+}
+
+impl Replay {
+    /// Creates a new `Replay` which will yield the requests in `transcript`.
+    ///
+    /// The responses in `transcript` are ignored.
+    pub fn new(transcript: Transcript) -> Self {
+        let mut recorded = transcript.exchanges;
+        // Requests are popped off the end.
+        recorded.reverse();
+        Self(ReplayInner {
+            recorded,
+            actual: Transcript::default(),
+            cursor: 0,
+            pending: None,
+            state: State::Idle,
+        })
+    }
+
+    /// Returns the number of requests which have not yet been received.
+    pub fn remaining(&self) -> usize {
+        self.0.recorded.len()
+    }
+
+    /// Consumes this `Replay`, returning the transcript of the requests
+    /// received so far and the replies to them.
+    pub fn into_transcript(self) -> Transcript {
+        self.0.actual
+    }
+}
+
+impl ReplayInner {
+    fn current(&mut self) -> &mut Exchange {
+        self.actual
+            .exchanges
+            .last_mut()
+            .expect("called only after a request was received")
+    }
+}
+
+impl HostPort for Replay {
+    fn receive(&mut self) -> Result<&mut dyn HostRequest, Error> {
+        let inner = &mut self.0;
+        let exchange = inner.recorded.pop().ok_or(Error::Disconnected)?;
+        inner.actual.exchanges.push(Exchange {
+            response: None,
+            ..exchange
+        });
+        inner.cursor = 0;
+        inner.pending = None;
+        inner.state = State::Received;
+        Ok(inner)
+    }
+}
+
+impl HostRequest for ReplayInner {
+    fn header(&self) -> Result<Header, Error> {
+        if self.state != State::Received {
+            return Err(Error::OutOfOrder);
+        }
+        let exchange = self.actual.exchanges.last().ok_or(Error::OutOfOrder)?;
+        Ok(exchange.request.header)
+    }
+
+    fn host_id(&self) -> u8 {
+        self.actual.exchanges.last().map(|e| e.host_id).unwrap_or(0)
+    }
+
+    fn payload(&mut self) -> Result<&mut dyn Read, Error> {
+        if self.state != State::Received {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(self)
+    }
+
+    fn reply(
+        &mut self,
+        header: Header,
+    ) -> Result<&mut dyn HostResponse, Error> {
+        if self.state != State::Received {
+            return Err(Error::OutOfOrder);
+        }
+        self.state = State::Replying;
+        self.pending = Some(Message {
+            header,
+            payload: Vec::new(),
+        });
+        Ok(self)
+    }
+}
+
+impl HostResponse for ReplayInner {
+    fn sink(&mut self) -> Result<&mut dyn Write, Error> {
+        if self.state != State::Replying {
+            return Err(Error::OutOfOrder);
+        }
+        Ok(self)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.state != State::Replying {
+            return Err(Error::OutOfOrder);
+        }
+        self.state = State::Idle;
+        self.current().response = self.pending.take();
+        Ok(())
+    }
+}
+
+impl Read for ReplayInner {
+    fn read_bytes(&mut self, out: &mut [u8]) -> Result<(), io::Error> {
+        let cursor = self.cursor;
+        let payload = &self.current().request.payload;
+        let bytes = payload
+            .get(cursor..cursor + out.len())
+            .ok_or(io::Error::BufferExhausted)?;
+        out.copy_from_slice(bytes);
+        self.cursor += out.len();
+        Ok(())
+    }
+
+    fn remaining_data(&self) -> usize {
+        self.actual
+            .exchanges
+            .last()
+            .map(|e| e.request.payload.len() - self.cursor)
+            .unwrap_or(0)
+    }
+}
+
+impl Write for ReplayInner {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), io::Error> {
+        match &mut self.pending {
+            Some(response) => {
+                response.payload.extend_from_slice(buf);
+                Ok(())
+            }
+            None => Err(io::Error::Internal),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::time::Duration;
+
+    use crate::crypto::ring;
+    use crate::hardware::fake;
+    use crate::mem::Arena as _;
+    use crate::mem::BumpArena;
+    use crate::net::InMemHost;
+    use crate::protocol::CommandType;
+    use crate::server::pa_rot;
+    use crate::server::pa_rot::PaRot;
+
+    fn request(command: CommandType, payload: &[u8]) -> Message {
+        Message {
+            header: Header {
+                command: command.into(),
+                is_request: true,
+            },
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn with_server(
+        version: &[u8],
+        f: impl FnOnce(&mut PaRot<fake::Identity, fake::Reset, ring::rsa::Builder>),
+    ) {
+        let identity = fake::Identity::new(version, &[], b"random bits");
+        let reset = fake::Reset::new(0, Duration::from_millis(1));
+        let rsa = ring::rsa::Builder::new();
+        let mut server =
+            PaRot::new(pa_rot::test::options(&identity, &reset, &rsa));
+        f(&mut server)
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let requests = [
+            request(CommandType::FirmwareVersion, &[0]),
+            request(CommandType::DeviceId, &[]),
+            request(CommandType::FirmwareVersion, &[42]),
+        ];
+
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+
+        let mut recorded = Transcript::default();
+        with_server(b"field firmware", |server| {
+            let mut out = [0; 1024];
+            let mut host = InMemHost::new(&mut out);
+            for req in &requests {
+                host.request_from(7, req.header, &req.payload);
+                let mut recorder = Recorder::new(&mut host);
+                recorder
+                    .record(|port| server.process_request(port, &arena))
+                    .unwrap()
+                    .unwrap();
+                arena.reset();
+
+                let (host, transcript) = recorder.into_parts();
+                let exchange = &transcript.exchanges[0];
+                assert_eq!(exchange.host_id, 7);
+                assert_eq!(&exchange.request, req);
+
+                // The reply made it back to the wrapped port.
+                let response = exchange.response.as_ref().unwrap();
+                let (header, payload) = host.response().unwrap();
+                assert_eq!(header, response.header);
+                assert_eq!(payload, &response.payload[..]);
+
+                recorded.exchanges.extend(transcript.exchanges);
+            }
+        });
+
+        // Transcripts survive serialization.
+        let json = serde_json::to_string(&recorded).unwrap();
+        let recorded: Transcript = serde_json::from_str(&json).unwrap();
+
+        // The same firmware behaves the same...
+        with_server(b"field firmware", |server| {
+            let mut replay = Replay::new(recorded.clone());
+            while replay.remaining() > 0 {
+                server.process_request(&mut replay, &arena).unwrap();
+                arena.reset();
+            }
+            assert!(recorded.diff(&replay.into_transcript()).is_empty());
+        });
+
+        // ...while different firmware does not.
+        with_server(b"fixed firmware", |server| {
+            let mut replay = Replay::new(recorded.clone());
+            while replay.remaining() > 0 {
+                server.process_request(&mut replay, &arena).unwrap();
+                arena.reset();
+            }
+            let diff = recorded.diff(&replay.into_transcript());
+            assert_eq!(diff.len(), 1);
+            assert_eq!(diff[0].index, 0);
+        });
+    }
+
+    #[test]
+    fn unfinished_reply() {
+        let req = request(CommandType::DeviceId, &[]);
+        let mut out = [0; 64];
+        let mut host = InMemHost::new(&mut out);
+        host.request(req.header, &req.payload);
+
+        let mut recorder = Recorder::new(&mut host);
+        recorder
+            .record(|port| {
+                let req = port.receive().unwrap();
+                let header = req.header().unwrap();
+                let resp = req.reply(header).unwrap();
+                resp.sink().unwrap().write_bytes(b"partial").unwrap();
+            })
+            .unwrap();
+
+        let (host, transcript) = recorder.into_parts();
+        assert_eq!(transcript.exchanges[0].response, None);
+        assert!(host.response().is_none());
+    }
+
+    #[test]
+    fn diff_lengths() {
+        let exchange = Exchange {
+            host_id: 0,
+            request: request(CommandType::DeviceId, &[]),
+            response: None,
+        };
+        let short = Transcript {
+            exchanges: vec![exchange.clone()],
+        };
+        let long = Transcript {
+            exchanges: vec![exchange.clone(), exchange.clone()],
+        };
+
+        assert!(short.diff(&short).is_empty());
+        assert_eq!(
+            short.diff(&long),
+            vec![Mismatch {
+                index: 1,
+                expected: None,
+                actual: Some(exchange),
+            }]
+        );
+    }
+}
@@ -14,19 +14,21 @@ use core::{u32, u8};
 use bitflags::bitflags;
 
 use crate::crypto::rsa;
-use crate::io::bit_buf::BitBuf;
 use crate::io::Read;
 use crate::io::Write;
 use crate::mem::Arena;
+use crate::protocol::wire::BitField;
 use crate::protocol::wire::FromWire;
 use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire;
 use crate::protocol::wire::ToWireError;
-use crate::protocol::wire::WireEnum;
 use crate::protocol::Command;
+use crate::protocol::CommandByte;
 use crate::protocol::CommandType;
+use crate::protocol::FuzzSafe;
 use crate::protocol::Request;
 use crate::protocol::Response;
+use crate::protocol::HEADER_LEN;
 
 #[cfg(feature = "arbitrary-derive")]
 use libfuzzer_sys::arbitrary::{self, Arbitrary};
@@ -47,89 +49,31 @@ impl Command<'_> for DeviceCapabilities {
 }
 
 /// The [`DeviceCapabilities`] request.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceCapabilitiesRequest {
     /// The advertised capabilities of the client.
     pub capabilities: Capabilities,
 }
-make_fuzz_safe!(DeviceCapabilitiesRequest);
 
 impl Request<'_> for DeviceCapabilitiesRequest {
-    const TYPE: CommandType = CommandType::DeviceCapabilities;
-}
-
-impl<'a> FromWire<'a> for DeviceCapabilitiesRequest {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        a: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let capabilities = Capabilities::from_wire(&mut r, a)?;
-        Ok(Self { capabilities })
-    }
-}
-
-impl ToWire for DeviceCapabilitiesRequest {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        self.capabilities.to_wire(&mut w)
-    }
+    const TYPE: CommandByte = CommandType::DeviceCapabilities.to_byte();
 }
 
 /// The [`DeviceCapabilities`] response.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceCapabilitiesResponse {
-    /// Capabilities negotiated based on the request.
+    /// The responding device's own capabilities.
     pub capabilities: Capabilities,
     /// Timeouts that this device expects the client to observe.
     pub timeouts: Timeouts,
 }
-make_fuzz_safe!(DeviceCapabilitiesResponse);
 
 impl Response<'_> for DeviceCapabilitiesResponse {
-    const TYPE: CommandType = CommandType::DeviceCapabilities;
-}
-
-impl<'a> FromWire<'a> for DeviceCapabilitiesResponse {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        a: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let capabilities = Capabilities::from_wire(&mut r, a)?;
-        let response_timeout =
-            Duration::from_millis((10 * (r.read_le::<u8>()? as u32)) as _);
-        let crypto_timeout =
-            Duration::from_millis((100 * (r.read_le::<u8>()? as u32)) as _);
-        Ok(Self {
-            capabilities,
-            timeouts: Timeouts {
-                regular: response_timeout,
-                crypto: crypto_timeout,
-            },
-        })
-    }
-}
-
-impl ToWire for DeviceCapabilitiesResponse {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        self.capabilities.to_wire(&mut w)?;
-        // Carefully compress the millisecond cound (which is a u128!) down
-        // to a byte, saturating when possible, and avoiding expensive
-        // division operations.
-        let response_time =
-            self.timeouts.regular.as_millis().min(u32::MAX as _) as u32;
-        let crypto_time =
-            self.timeouts.crypto.as_millis().min(u32::MAX as _) as u32;
-
-        let response_byte = (response_time / 10).min(u8::MAX as _) as u8;
-        let crypto_byte = (crypto_time / 100).min(u8::MAX as _) as u8;
-        w.write_le(response_byte)?;
-        w.write_le(crypto_byte)?;
-
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::DeviceCapabilities.to_byte();
 }
 
 wire_enum! {
@@ -236,6 +180,27 @@ bitflags! {
     }
 }
 
+/// Implements [`BitField`] for `bitflags!` types, rejecting unknown bits.
+macro_rules! bitflags_bit_field {
+    ($($ty:ident),* $(,)?) => {$(
+        impl BitField for $ty {
+            fn from_bits(bits: u8) -> Option<Self> {
+                $ty::from_bits(bits)
+            }
+            fn to_bits(self) -> u8 {
+                self.bits()
+            }
+        }
+    )*}
+}
+bitflags_bit_field!(
+    BusRole,
+    Security,
+    EccKeyStrength,
+    RsaKeyStrength,
+    AesKeyStrength,
+);
+
 /// Network-related capabilities for a device.
 ///
 /// A value of this type needs to be provided to `manticore` by an integration,
@@ -257,8 +222,10 @@ pub struct Networking {
     /// be packetized). It is typically derived from the size of underlying
     /// buffers in, say, a SPI or I2C hardware IP.
     ///
-    /// (Note: the packetization strategy is not provided by `manticore`
-    /// itself.)
+    /// See [`net::packet`] for a packetization strategy that makes use of
+    /// this value.
+    ///
+    /// [`net::packet`]: ../../net/packet/index.html
     pub max_packet_size: u16,
 
     /// The type of RoT this device is.
@@ -267,6 +234,30 @@ pub struct Networking {
     pub roles: BusRole,
 }
 
+/// The smallest maximum message size a device may advertise.
+///
+/// This leaves room for exactly a header and a [`protocol::Error`], so that a
+/// device can always at least report failures.
+///
+/// [`protocol::Error`]: ../struct.Error.html
+pub const MIN_MESSAGE_SIZE: u16 = HEADER_LEN as u16 + 5;
+
+impl Networking {
+    /// Negotiates networking parameters between this device and a peer with
+    /// the networking capabilities `peer`.
+    ///
+    /// The resulting message and packet sizes are the smaller of the two
+    /// devices'; the mode and roles are this device's.
+    pub fn negotiate(&self, peer: &Networking) -> Networking {
+        Networking {
+            max_message_size: self.max_message_size.min(peer.max_message_size),
+            max_packet_size: self.max_packet_size.min(peer.max_packet_size),
+            mode: self.mode,
+            roles: self.roles,
+        }
+    }
+}
+
 /// A description of device capabilities.
 ///
 /// This struct describes all of the device capabilities used in capability
@@ -314,135 +305,138 @@ pub struct Capabilities {
     pub aes_strength: AesKeyStrength,
 }
 
-/// Constants relevant to parsing `Capabilities`.
-mod consts {
-    pub const MODE_SIZE: usize = 2;
-    pub const BUS_SIZE: usize = 2;
-    pub const SEC_SIZE: usize = 3;
+impl Capabilities {
+    /// Negotiates capabilities between this device and a peer with the
+    /// capabilities `peer`.
+    ///
+    /// The result consists of those capabilities supported by both devices;
+    /// see [`Networking::negotiate()`] for how networking parameters are
+    /// chosen.
+    pub fn negotiate(&self, peer: &Capabilities) -> Capabilities {
+        Capabilities {
+            networking: self.networking.negotiate(&peer.networking),
+
+            security: self.security & peer.security,
+            has_pfm_support: self.has_pfm_support && peer.has_pfm_support,
+            has_policy_support: self.has_policy_support
+                && peer.has_policy_support,
+            has_firmware_protection: self.has_firmware_protection
+                && peer.has_firmware_protection,
+
+            has_ecdsa: self.has_ecdsa && peer.has_ecdsa,
+            has_ecc: self.has_ecc && peer.has_ecc,
+            has_rsa: self.has_rsa && peer.has_rsa,
+            has_aes: self.has_aes && peer.has_aes,
+
+            ecc_strength: self.ecc_strength & peer.ecc_strength,
+            rsa_strength: self.rsa_strength & peer.rsa_strength,
+            aes_strength: self.aes_strength & peer.aes_strength,
+        }
+    }
+}
 
-    pub const RSA_SIZE: usize = 3;
-    pub const ECC_SIZE: usize = 3;
-    pub const AES_SIZE: usize = 3;
+/// The wire format of [`Capabilities`], which packs fields from different
+/// parts of it into the same byte.
+///
+/// Reserved bits are ignored when parsing, rather than rejected, so that they
+/// may be put to use by newer devices.
+#[derive(FromWire, ToWire)]
+struct CapabilitiesWire {
+    max_message_size: u16,
+    max_packet_size: u16,
+
+    #[wire(bits = 2)]
+    mode: RotMode,
+    #[wire(bits = 2)]
+    roles: BusRole,
+    #[wire(bits = 1)]
+    reserved5: u8,
+    #[wire(bits = 3)]
+    security: Security,
+
+    #[wire(bits = 1)]
+    has_pfm_support: bool,
+    #[wire(bits = 1)]
+    has_policy_support: bool,
+    #[wire(bits = 1)]
+    has_firmware_protection: bool,
+    #[wire(bits = 5)]
+    reserved6: u8,
+
+    #[wire(bits = 1)]
+    has_rsa: bool,
+    #[wire(bits = 1)]
+    has_ecdsa: bool,
+    #[wire(bits = 3)]
+    ecc_strength: EccKeyStrength,
+    #[wire(bits = 3)]
+    rsa_strength: RsaKeyStrength,
+
+    #[wire(bits = 1)]
+    has_ecc: bool,
+    #[wire(bits = 4)]
+    reserved8: u8,
+    #[wire(bits = 3)]
+    aes_strength: AesKeyStrength,
 }
 
 impl<'a> FromWire<'a> for Capabilities {
     fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        _: &'a A,
+        r: R,
+        a: &'a A,
     ) -> Result<Capabilities, FromWireError> {
-        use consts::*;
-        let max_message_size = r.read_le::<u16>()?;
-        let max_packet_size = r.read_le::<u16>()?;
-
-        // The fifth byte contains the security capabilities, a reserved bit,
-        // the bus role, and the RoT mode, in that order.
-        let mut byte_five = BitBuf::from_bits(r.read_le::<u8>()?);
-        let mode_bits = byte_five.read_bits(MODE_SIZE)?;
-        let bus_bits = byte_five.read_bits(BUS_SIZE)?;
-        let _ = byte_five.read_bits(1)?;
-        let security_bits = byte_five.read_bits(SEC_SIZE)?;
-
-        let mode = RotMode::from_wire_value(mode_bits)
-            .ok_or(FromWireError::OutOfRange)?;
-        let roles =
-            BusRole::from_bits(bus_bits).ok_or(FromWireError::OutOfRange)?;
-        let networking = Networking {
-            max_message_size,
-            max_packet_size,
-            mode,
-            roles,
-        };
-
-        let security = Security::from_bits(security_bits)
-            .ok_or(FromWireError::OutOfRange)?;
-
-        // The sixth byte consists of five reserved bits, and the PFM, policy,
-        // and firmware protection bits.
-        let mut byte_six = BitBuf::from_bits(r.read_le::<u8>()?);
-        let has_pfm_support = byte_six.read_bit()?;
-        let has_policy_support = byte_six.read_bit()?;
-        let has_firmware_protection = byte_six.read_bit()?;
-        let _ = byte_six.read_bits(5)?;
-
-        // The seventh byte consists of the rsa strength, the ecc strength, and
-        // the ecdsa and rsa bits.
-        let mut byte_seven = BitBuf::from_bits(r.read_le::<u8>()?);
-        let has_rsa = byte_seven.read_bit()?;
-        let has_ecdsa = byte_seven.read_bit()?;
-        let ecc_bits = byte_seven.read_bits(ECC_SIZE)?;
-        let rsa_bits = byte_seven.read_bits(RSA_SIZE)?;
-
-        let rsa_strength = RsaKeyStrength::from_bits(rsa_bits)
-            .ok_or(FromWireError::OutOfRange)?;
-        let ecc_strength = EccKeyStrength::from_bits(ecc_bits)
-            .ok_or(FromWireError::OutOfRange)?;
-
-        // The eighth byte consists of the aes strength, four reserved bits,
-        // and the ecc bit.
-        let mut byte_eight = BitBuf::from_bits(r.read_le::<u8>()?);
-        let has_ecc = byte_eight.read_bit()?;
-        let _ = byte_eight.read_bits(4)?;
-        let aes_bits = byte_eight.read_bits(AES_SIZE)?;
-
-        let aes_strength = AesKeyStrength::from_bits(aes_bits)
-            .ok_or(FromWireError::OutOfRange)?;
-
+        let wire = CapabilitiesWire::from_wire(r, a)?;
         Ok(Capabilities {
-            networking,
+            networking: Networking {
+                max_message_size: wire.max_message_size,
+                max_packet_size: wire.max_packet_size,
+                mode: wire.mode,
+                roles: wire.roles,
+            },
 
-            security,
-            has_pfm_support,
-            has_policy_support,
-            has_firmware_protection,
+            security: wire.security,
+            has_pfm_support: wire.has_pfm_support,
+            has_policy_support: wire.has_policy_support,
+            has_firmware_protection: wire.has_firmware_protection,
 
-            has_ecdsa,
-            has_ecc,
-            has_rsa,
+            has_ecdsa: wire.has_ecdsa,
+            has_ecc: wire.has_ecc,
+            has_rsa: wire.has_rsa,
             has_aes: false,
 
-            ecc_strength,
-            rsa_strength,
-            aes_strength,
+            ecc_strength: wire.ecc_strength,
+            rsa_strength: wire.rsa_strength,
+            aes_strength: wire.aes_strength,
         })
     }
 }
 
 impl ToWire for Capabilities {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        use consts::*;
-        w.write_le(self.networking.max_message_size)?;
-        w.write_le(self.networking.max_packet_size)?;
-
-        // See `deserialize_capabilities` for the description of each byte.
-        let mut fifth_byte = BitBuf::new();
-        fifth_byte
-            .write_bits(MODE_SIZE, self.networking.mode.to_wire_value())?;
-        fifth_byte.write_bits(BUS_SIZE, self.networking.roles.bits())?;
-        fifth_byte.write_zero_bits(1)?;
-        fifth_byte.write_bits(SEC_SIZE, self.security.bits())?;
-        w.write_le(fifth_byte.bits())?;
-
-        let mut sixth_byte = BitBuf::new();
-        sixth_byte.write_bit(self.has_pfm_support)?;
-        sixth_byte.write_bit(self.has_policy_support)?;
-        sixth_byte.write_bit(self.has_firmware_protection)?;
-        sixth_byte.write_zero_bits(5)?;
-        w.write_le(sixth_byte.bits())?;
-
-        let mut seventh_byte = BitBuf::new();
-        seventh_byte.write_bit(self.has_rsa)?;
-        seventh_byte.write_bit(self.has_ecdsa)?;
-        seventh_byte.write_bits(ECC_SIZE, self.ecc_strength.bits())?;
-        seventh_byte.write_bits(RSA_SIZE, self.rsa_strength.bits())?;
-        w.write_le(seventh_byte.bits())?;
-
-        let mut eighth_byte = BitBuf::new();
-        eighth_byte.write_bit(self.has_ecc)?;
-        eighth_byte.write_zero_bits(4)?;
-        eighth_byte.write_bits(AES_SIZE, self.aes_strength.bits())?;
-        w.write_le(eighth_byte.bits())?;
-
-        Ok(())
+    fn to_wire<W: Write>(&self, w: W) -> Result<(), ToWireError> {
+        CapabilitiesWire {
+            max_message_size: self.networking.max_message_size,
+            max_packet_size: self.networking.max_packet_size,
+
+            mode: self.networking.mode,
+            roles: self.networking.roles,
+            reserved5: 0,
+            security: self.security,
+
+            has_pfm_support: self.has_pfm_support,
+            has_policy_support: self.has_policy_support,
+            has_firmware_protection: self.has_firmware_protection,
+            reserved6: 0,
+
+            has_rsa: self.has_rsa,
+            has_ecdsa: self.has_ecdsa,
+            ecc_strength: self.ecc_strength,
+            rsa_strength: self.rsa_strength,
+
+            has_ecc: self.has_ecc,
+            reserved8: 0,
+            aes_strength: self.aes_strength,
+        }
+        .to_wire(w)
     }
 }
 
@@ -469,10 +463,42 @@ pub struct Timeouts {
     pub crypto: Duration,
 }
 
+impl<'a> FromWire<'a> for Timeouts {
+    fn from_wire<R: Read, A: Arena>(
+        mut r: R,
+        _: &'a A,
+    ) -> Result<Self, FromWireError> {
+        let regular =
+            Duration::from_millis((10 * (r.read_le::<u8>()? as u32)) as _);
+        let crypto =
+            Duration::from_millis((100 * (r.read_le::<u8>()? as u32)) as _);
+        Ok(Self { regular, crypto })
+    }
+}
+
+impl ToWire for Timeouts {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        // Carefully compress the millisecond cound (which is a u128!) down
+        // to a byte, saturating when possible, and avoiding expensive
+        // division operations.
+        let regular_time = self.regular.as_millis().min(u32::MAX as _) as u32;
+        let crypto_time = self.crypto.as_millis().min(u32::MAX as _) as u32;
+
+        let regular_byte = (regular_time / 10).min(u8::MAX as _) as u8;
+        let crypto_byte = (crypto_time / 100).min(u8::MAX as _) as u8;
+        w.write_le(regular_byte)?;
+        w.write_le(crypto_byte)?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    use crate::mem::BumpArena;
+
     round_trip_test! {
         request_round_trip: {
             bytes: &[
@@ -543,4 +569,80 @@ mod test {
             },
         },
     }
+
+    #[test]
+    fn reserved_bits_ignored() {
+        #[rustfmt::skip]
+        let bytes = [
+            0x00, 0x01,  // Message size.
+            0x80, 0x00,  // Packet size.
+            0b01_11_1_011,
+            0b1_0_0_10101,
+            0b1_0_000_001,
+            0b0_1001_011,
+        ];
+        let mut buf = [0; 16];
+        let arena = BumpArena::new(&mut buf);
+        let caps = Capabilities::from_wire(&mut &bytes[..], &arena).unwrap();
+        assert_eq!(caps.networking.roles, BusRole::HOST | BusRole::TARGET);
+        assert!(caps.has_pfm_support);
+        assert!(!caps.has_ecc);
+
+        // Reserved bits are always written as zero.
+        let mut out = [0; 8];
+        caps.to_wire(&mut &mut out[..]).unwrap();
+        assert_eq!(
+            out[4..],
+            [0b01_11_0_011, 0b1_0_0_00000, 0b1_0_000_001, 0b0_0000_011]
+        );
+    }
+
+    #[test]
+    fn negotiate() {
+        let ours = Capabilities {
+            networking: Networking {
+                max_message_size: 0x100,
+                max_packet_size: 0x80,
+                mode: RotMode::Platform,
+                roles: BusRole::HOST,
+            },
+            security: Security::HASH_AND_KDF | Security::AUTHENTICATION,
+            has_pfm_support: true,
+            has_policy_support: false,
+            has_firmware_protection: false,
+            has_ecdsa: false,
+            has_ecc: false,
+            has_rsa: true,
+            has_aes: false,
+            ecc_strength: EccKeyStrength::empty(),
+            rsa_strength: RsaKeyStrength::BITS_2048 | RsaKeyStrength::BITS_3072,
+            aes_strength: AesKeyStrength::empty(),
+        };
+        let theirs = Capabilities {
+            networking: Networking {
+                max_message_size: 0x400,
+                max_packet_size: 0x40,
+                mode: RotMode::Active,
+                roles: BusRole::TARGET,
+            },
+            security: Security::AUTHENTICATION,
+            rsa_strength: RsaKeyStrength::BITS_3072,
+            ..ours
+        };
+
+        let negotiated = ours.negotiate(&theirs);
+        assert_eq!(
+            negotiated.networking,
+            Networking {
+                max_message_size: 0x100,
+                max_packet_size: 0x40,
+                mode: RotMode::Platform,
+                roles: BusRole::HOST,
+            }
+        );
+        assert_eq!(negotiated.security, Security::AUTHENTICATION);
+        assert!(negotiated.has_pfm_support);
+        assert!(negotiated.has_rsa);
+        assert_eq!(negotiated.rsa_strength, RsaKeyStrength::BITS_3072);
+    }
 }
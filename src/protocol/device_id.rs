@@ -7,15 +7,12 @@
 //! This module provides a Cerberus command that allows requesting a unique
 //! "device ID" from an RoT.
 
-use crate::io::Read;
-use crate::io::Write;
-use crate::mem::Arena;
 use crate::protocol::wire::FromWire;
-use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire;
-use crate::protocol::wire::ToWireError;
 use crate::protocol::Command;
+use crate::protocol::CommandByte;
 use crate::protocol::CommandType;
+use crate::protocol::FuzzSafe;
 use crate::protocol::Request;
 use crate::protocol::Response;
 
@@ -42,60 +39,26 @@ impl Command<'_> for DeviceId {
 }
 
 /// The [`DeviceId`] request.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceIdRequest;
-make_fuzz_safe!(DeviceIdRequest);
 
 impl Request<'_> for DeviceIdRequest {
-    const TYPE: CommandType = CommandType::DeviceId;
-}
-
-impl<'a> FromWire<'a> for DeviceIdRequest {
-    fn from_wire<R: Read, A: Arena>(
-        _: R,
-        _: &'a A,
-    ) -> Result<Self, FromWireError> {
-        Ok(DeviceIdRequest)
-    }
-}
-
-impl ToWire for DeviceIdRequest {
-    fn to_wire<W: Write>(&self, _: W) -> Result<(), ToWireError> {
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::DeviceId.to_byte();
 }
 
 /// The [`DeviceId`] response.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceIdResponse {
     /// A device identifier that uniquely identifies this device's silicon.
     pub id: DeviceIdentifier,
 }
-make_fuzz_safe!(DeviceIdResponse);
 
 impl Response<'_> for DeviceIdResponse {
-    const TYPE: CommandType = CommandType::DeviceId;
-}
-
-impl<'a> FromWire<'a> for DeviceIdResponse {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        a: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let id = DeviceIdentifier::from_wire(&mut r, a)?;
-        Ok(Self { id })
-    }
-}
-
-impl ToWire for DeviceIdResponse {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        self.id.to_wire(&mut w)?;
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::DeviceId.to_byte();
 }
 
 /// An identifier for a physical device.
@@ -105,7 +68,7 @@ impl ToWire for DeviceIdResponse {
 ///
 /// The meaning of the fields below is currently unspecified by Cerberus
 /// beyond their names.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 // TODO: Remove this once we have a better idea of what Cerberus expects of
@@ -118,34 +81,6 @@ pub struct DeviceIdentifier {
     pub subsys_id: u16,
 }
 
-impl<'a> FromWire<'a> for DeviceIdentifier {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        _: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let vendor_id = r.read_le::<u16>()?;
-        let device_id = r.read_le::<u16>()?;
-        let subsys_vendor_id = r.read_le::<u16>()?;
-        let subsys_id = r.read_le::<u16>()?;
-        Ok(Self {
-            vendor_id,
-            device_id,
-            subsys_vendor_id,
-            subsys_id,
-        })
-    }
-}
-
-impl ToWire for DeviceIdentifier {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        w.write_le(self.vendor_id)?;
-        w.write_le(self.device_id)?;
-        w.write_le(self.subsys_vendor_id)?;
-        w.write_le(self.subsys_id)?;
-        Ok(())
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -7,16 +7,12 @@
 //! This module provides a Cerberus command that allows the querying of
 //! Cerberus and vendor-specified information about the device.
 
-use crate::io::Read;
-use crate::io::Write;
-use crate::mem::Arena;
-use crate::mem::ArenaExt as _;
 use crate::protocol::wire::FromWire;
-use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire;
-use crate::protocol::wire::ToWireError;
 use crate::protocol::Command;
+use crate::protocol::CommandByte;
 use crate::protocol::CommandType;
+use crate::protocol::FuzzSafe;
 use crate::protocol::Request;
 use crate::protocol::Response;
 
@@ -49,72 +45,34 @@ wire_enum! {
 }
 
 /// The [`DeviceInfo`] request.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceInfoRequest {
     /// Which device information to look up.
     pub index: InfoIndex,
 }
-make_fuzz_safe!(DeviceInfoRequest);
 
 impl Request<'_> for DeviceInfoRequest {
-    const TYPE: CommandType = CommandType::DeviceInfo;
+    const TYPE: CommandByte = CommandType::DeviceInfo.to_byte();
 }
 
-impl<'a> FromWire<'a> for DeviceInfoRequest {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        a: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let index = InfoIndex::from_wire(&mut r, a)?;
-        Ok(Self { index })
-    }
-}
-
-impl<'a> ToWire for DeviceInfoRequest {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        self.index.to_wire(&mut w)?;
-        Ok(())
-    }
-}
-
-make_fuzz_safe! {
-    /// The [`DeviceInfo`] response.
-    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// The [`DeviceInfo`] response.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-    pub struct DeviceInfoResponse<'a> as DIRWrap {
-        /// The requested information, in some binary format.
-        ///
-        /// The format of the response depends on which information index was sent.
-        /// Only `0x00` is specified by Cerberus, which is reqired to produce the
-        /// "Unique Chip Identifier".
-        #[cfg_attr(feature = "serde", serde(borrow))]
-        pub info: (&'a [u8]),
-    }
+pub struct DeviceInfoResponse<'a> {
+    /// The requested information, in some binary format.
+    ///
+    /// The format of the response depends on which information index was sent.
+    /// Only `0x00` is specified by Cerberus, which is reqired to produce the
+    /// "Unique Chip Identifier".
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    #[wire(remaining)]
+    pub info: &'a [u8],
 }
 
 impl<'a> Response<'a> for DeviceInfoResponse<'a> {
-    const TYPE: CommandType = CommandType::DeviceInfo;
-}
-
-impl<'a> FromWire<'a> for DeviceInfoResponse<'a> {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        arena: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let len = r.remaining_data();
-        let buf = arena.alloc_slice::<u8>(len)?;
-        r.read_bytes(buf)?;
-        Ok(Self { info: buf })
-    }
-}
-
-impl ToWire for DeviceInfoResponse<'_> {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        w.write_bytes(self.info)?;
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::DeviceInfo.to_byte();
 }
 
 #[cfg(test)]
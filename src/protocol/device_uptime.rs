@@ -19,7 +19,9 @@ use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire;
 use crate::protocol::wire::ToWireError;
 use crate::protocol::Command;
+use crate::protocol::CommandByte;
 use crate::protocol::CommandType;
+use crate::protocol::FuzzSafe;
 use crate::protocol::Request;
 use crate::protocol::Response;
 
@@ -46,38 +48,20 @@ impl<'a> Command<'a> for DeviceUptime {
 }
 
 /// The [`DeviceUptime`] request.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceUptimeRequest {
     /// The port that the device whose uptime is being looked up.
     pub port_id: u8,
 }
-make_fuzz_safe!(DeviceUptimeRequest);
 
 impl Request<'_> for DeviceUptimeRequest {
-    const TYPE: CommandType = CommandType::DeviceUptime;
-}
-
-impl<'a> FromWire<'a> for DeviceUptimeRequest {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        _: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let port_id = r.read_le::<u8>()?;
-        Ok(Self { port_id })
-    }
-}
-
-impl<'a> ToWire for DeviceUptimeRequest {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        w.write_le(self.port_id)?;
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::DeviceUptime.to_byte();
 }
 
 /// The [`DeviceUptime`] response.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DeviceUptimeResponse {
@@ -87,10 +71,9 @@ pub struct DeviceUptimeResponse {
     /// seconds.
     pub uptime: Duration,
 }
-make_fuzz_safe!(DeviceUptimeResponse);
 
 impl Response<'_> for DeviceUptimeResponse {
-    const TYPE: CommandType = CommandType::DeviceUptime;
+    const TYPE: CommandByte = CommandType::DeviceUptime.to_byte();
 }
 
 impl<'a> FromWire<'a> for DeviceUptimeResponse {
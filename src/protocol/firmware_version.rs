@@ -7,16 +7,12 @@
 //! This module provides a Cerberus command allowing the versions of various
 //! on-device firmware to be queried.
 
-use crate::io::Read;
-use crate::io::Write;
-use crate::mem::Arena;
-use crate::mem::ArenaExt as _;
 use crate::protocol::wire::FromWire;
-use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire;
-use crate::protocol::wire::ToWireError;
 use crate::protocol::Command;
+use crate::protocol::CommandByte;
 use crate::protocol::CommandType;
+use crate::protocol::FuzzSafe;
 use crate::protocol::Request;
 use crate::protocol::Response;
 
@@ -43,7 +39,7 @@ impl<'a> Command<'a> for FirmwareVersion {
 }
 
 /// The [`FirmwareVersion`] request.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FirmwareVersionRequest {
@@ -52,40 +48,22 @@ pub struct FirmwareVersionRequest {
     /// integration.
     pub index: u8,
 }
-make_fuzz_safe!(FirmwareVersionRequest);
 
 impl Request<'_> for FirmwareVersionRequest {
-    const TYPE: CommandType = CommandType::FirmwareVersion;
+    const TYPE: CommandByte = CommandType::FirmwareVersion.to_byte();
 }
 
-impl<'a> FromWire<'a> for FirmwareVersionRequest {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        _: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let index = r.read_le()?;
-        Ok(Self { index })
-    }
-}
-
-impl<'a> ToWire for FirmwareVersionRequest {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        w.write_le(self.index)?;
-        Ok(())
-    }
-}
-
-make_fuzz_safe! {
-    /// The [`FirmwareVersion`] response.
-    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-    pub struct FirmwareVersionResponse<'a> as FVRWrap {
-        /// The firmware version. In practice, this is usually an ASCII string.
-        #[cfg_attr(feature = "serde",
-                   serde(deserialize_with = "deserialize_u8x32"))]
-        #[cfg_attr(feature = "serde", serde(borrow))]
-        pub version: (&'a [u8; 32]),
-    }
+/// The [`FirmwareVersion`] response.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FirmwareVersionResponse<'a> {
+    /// The firmware version. In practice, this is usually an ASCII string.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "deserialize_u8x32")
+    )]
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub version: &'a [u8; 32],
 }
 
 // NOTE: This function exists to work around the fact that serde does not
@@ -104,25 +82,7 @@ fn deserialize_u8x32<'de: 'a, 'a, D: Deserializer<'de>>(
 }
 
 impl<'a> Response<'a> for FirmwareVersionResponse<'a> {
-    const TYPE: CommandType = CommandType::FirmwareVersion;
-}
-
-impl<'a> FromWire<'a> for FirmwareVersionResponse<'a> {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        arena: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let version: &mut [u8; 32] = arena.alloc::<[u8; 32]>()?;
-        r.read_bytes(version)?;
-        Ok(Self { version })
-    }
-}
-
-impl ToWire for FirmwareVersionResponse<'_> {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        w.write_bytes(self.version)?;
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::FirmwareVersion.to_byte();
 }
 
 #[cfg(test)]
@@ -0,0 +1,117 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `KeySetUpdate` request.
+//!
+//! This module provides a `manticore`-specific command allowing the host to
+//! add or revoke keys in the set of keys the RoT trusts to sign manifests.
+//!
+//! An update must be signed by a key that is already in the set, so that a
+//! fleet can rotate its signing keys without needing to trust the host. The
+//! signature is a signature of type [`SigType`] over the wire encoding of the
+//! request, up to but not including the `signature` field. See
+//! [`KeyRing::apply()`] for an implementation.
+//!
+//! [`SigType`]: ../../manifest/enum.SigType.html
+//! [`KeyRing::apply()`]:
+//!     ../../manifest/key_set/struct.KeyRing.html#method.apply
+
+use crate::protocol::wire::FromWire;
+use crate::protocol::wire::ToWire;
+use crate::protocol::Command;
+use crate::protocol::CommandByte;
+use crate::protocol::CommandType;
+use crate::protocol::FuzzSafe;
+use crate::protocol::Request;
+
+#[cfg(feature = "arbitrary-derive")]
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A command for updating the set of trusted manifest-signing keys.
+///
+/// Corresponds to [`CommandType::KeySetUpdate`].
+///
+/// [`CommandType::KeySetUpdate`]:
+///     ../enum.CommandType.html#variant.KeySetUpdate
+pub enum KeySetUpdate {}
+
+impl<'a> Command<'a> for KeySetUpdate {
+    type Req = KeySetUpdateRequest<'a>;
+    type Resp = crate::protocol::Error;
+}
+
+wire_enum! {
+    /// An operation on a key set.
+    #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum KeyOp: u8 {
+        /// Adds a new key to the set.
+        ///
+        /// Key IDs cannot be reused, so this fails if the ID is already in
+        /// use or has been revoked.
+        Add = 0x00,
+        /// Permanently revokes a key.
+        Revoke = 0x01,
+    }
+}
+
+/// The [`KeySetUpdate`] request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KeySetUpdateRequest<'a> {
+    /// The operation to perform.
+    pub op: KeyOp,
+    /// The ID of the key being added or revoked.
+    pub key_id: u8,
+    /// The ID of the key that signed this request.
+    pub signer_id: u8,
+    /// The key being added, as a DER-encoded `SubjectPublicKeyInfo`.
+    ///
+    /// This is empty for [`KeyOp::Revoke`].
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    #[wire(len_prefix = "u16")]
+    pub key: &'a [u8],
+    /// A signature over all of the preceding fields.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    #[wire(remaining)]
+    pub signature: &'a [u8],
+}
+
+impl<'a> Request<'a> for KeySetUpdateRequest<'a> {
+    const TYPE: CommandByte = CommandType::KeySetUpdate.to_byte();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    round_trip_test! {
+        request_round_trip: {
+            bytes: &[
+                0x00, 0x02, 0x01,
+                0x03, 0x00, b'k', b'e', b'y',
+                b's', b'i', b'g',
+            ],
+            value: KeySetUpdateRequest {
+                op: KeyOp::Add,
+                key_id: 2,
+                signer_id: 1,
+                key: b"key",
+                signature: b"sig",
+            },
+        },
+        revoke_round_trip: {
+            bytes: &[0x01, 0x01, 0x02, 0x00, 0x00, b's', b'i', b'g'],
+            value: KeySetUpdateRequest {
+                op: KeyOp::Revoke,
+                key_id: 1,
+                signer_id: 2,
+                key: b"",
+                signature: b"sig",
+            },
+        },
+    }
+}
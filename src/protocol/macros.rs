@@ -52,70 +52,14 @@ macro_rules! round_trip_test {
     )+}
 }
 
-/// Convenience trait for use with `make_fuzz_safe`.
+/// A message type that can be generated by a fuzzer.
+///
+/// `#[derive(FuzzSafe)]` implements this trait for protocol messages.
+///
+/// Messages defined outside of `manticore` should implement this trait
+/// directly to be usable with the generated fuzz targets.
 #[doc(hidden)]
 #[cfg(feature = "arbitrary-derive")]
 pub trait FuzzSafe {
     type Safe: libfuzzer_sys::arbitrary::Arbitrary;
 }
-
-/// Convenience macro for generating a "fuzz-safe" version of the struct that
-/// can be cheaply converted into the original struct.
-macro_rules! make_fuzz_safe {
-    ($name:ident$(<$lt:lifetime>)?) => {
-        #[cfg(feature = "arbitrary-derive")]
-        impl<$($lt)?> $crate::protocol::macros::FuzzSafe for $name<$($lt)?> {
-            type Safe = $name<$($lt)?>;
-        }
-    };
-    (
-        $(#[$meta:meta])*
-        $vis:vis struct $name:ident $(<$lt:lifetime>)? as $wrapper_name:ident {$(
-            $(#[$field_meta:meta])*
-            $field_vis:vis $field:ident:
-                // Because we need to destructure on this, this *cannot* be a
-                // type. Hence, we accept a token-tree instead, with the
-                // understanding that callers will wrap complex types in
-                // parentheses.
-                //
-                // This is a limitation of the macro engine. See
-                // https://danielkeep.github.io/tlborm/book/mbe-min-captures-and-expansion-redux.html
-            $field_ty:tt,
-        )*}
-    ) => {
-        $(#[$meta])*
-        $vis struct $name $(<$lt>)? {$(
-            $(#[$field_meta])*
-            $field_vis $field: $field_ty,
-        )*}
-
-        #[cfg(feature = "arbitrary-derive")]
-        #[derive(Clone, Debug, Arbitrary)]
-        #[allow(unused_parens)]
-        #[doc(hidden)]
-        $vis struct $wrapper_name {$(
-            $field: make_fuzz_safe!(@convert_ty, $field_ty),
-        )*}
-        #[cfg(feature = "arbitrary-derive")]
-        impl $wrapper_name {
-            /// Borrow this value into a protocol struct.
-            pub fn as_ref<$($lt)?>(&$($lt)? self)
-            -> $name<$($lt)?> {
-                $name {$(
-                    $field: make_fuzz_safe!(@extract_ty,
-                                            self.$field: $field_ty),
-                )*}
-            }
-        }
-        #[cfg(feature = "arbitrary-derive")]
-        impl<$($lt)?> $crate::protocol::macros::FuzzSafe for $name<$($lt)?> {
-            type Safe = $wrapper_name;
-        }
-    };
-    (@convert_ty, (&$lt:tt [$ty:ty])) => {std::boxed::Box<[$ty]>};
-    (@convert_ty, (&$lt:tt str)) => {std::boxed::Box<str>};
-    (@convert_ty, (&$lt:tt $ty:ty)) => {$ty};
-    (@convert_ty, $ty:ty) => {$ty};
-    (@extract_ty, $s:tt.$f:tt: (&$lt:lifetime $ty:ty)) => {&$s.$f};
-    (@extract_ty, $s:tt.$f:tt: $ty:ty) => {$s.$f};
-}
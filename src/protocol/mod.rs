@@ -26,7 +26,8 @@
 //! In addition to providing (de)serialization to and from the Cerberus wire
 //! format (via the [`wire` module]), the `serde` feature will provide relevant
 //! implementations of [`serde`] traits, for (de)serialization to and from
-//! human-readable formats, like JSON.
+//! human-readable formats, like JSON. The [`schema` module] describes the
+//! wire layout of every message, for use by external tools.
 //!
 //! ---
 //!
@@ -41,9 +42,7 @@
 //! spoken over MCTP, and, as such, does not use the same header as Cerberus.
 //!
 //! [`wire` module]: wire/index.html
-
-// This is required due to the make_fuzz_safe! macro.
-#![allow(unused_parens)]
+//! [`schema` module]: schema/index.html
 
 use crate::io::Read;
 use crate::io::Write;
@@ -52,6 +51,7 @@ use crate::protocol::wire::FromWire;
 use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire;
 use crate::protocol::wire::ToWireError;
+use crate::protocol::wire::WireEnum as _;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -60,10 +60,14 @@ use serde::{Deserialize, Serialize};
 mod macros;
 #[cfg(feature = "arbitrary-derive")]
 pub use macros::FuzzSafe;
+#[doc(hidden)]
+pub use manticore_derive::FuzzSafe;
 
 #[macro_use]
 pub mod wire;
 
+pub mod schema;
+
 pub mod device_id;
 pub use device_id::DeviceId;
 
@@ -79,6 +83,9 @@ pub use capabilities::DeviceCapabilities;
 pub mod firmware_version;
 pub use firmware_version::FirmwareVersion;
 
+pub mod key_set_update;
+pub use key_set_update::KeySetUpdate;
+
 pub mod reset_counter;
 pub use reset_counter::ResetCounter;
 
@@ -95,6 +102,54 @@ pub use request_counter::RequestCounter;
 /// This trait is not implemented by any of the request or response types, but
 /// is intead implemented by uninhabited types that represent pairs of requests
 /// and responses, for use in generic programming.
+///
+/// # Vendor-defined commands
+///
+/// Commands need not be defined by `manticore`: an integration may define its
+/// own commands, such as debug or lifecycle commands specific to a device, by
+/// implementing these traits with a command byte that `manticore` does not
+/// allocate (i.e., one for which [`CommandByte::command_type()`] returns
+/// `None`). Such commands can then be used with the same generic machinery as
+/// built-in commands, such as the `server` handler framework.
+/// ```
+/// # use manticore::io::{Read, Write};
+/// # use manticore::mem::Arena;
+/// # use manticore::protocol::*;
+/// # use manticore::protocol::wire::*;
+/// /// A vendor-specific command for unlocking a debug port.
+/// pub enum DebugUnlock {}
+///
+/// impl Command<'_> for DebugUnlock {
+///     type Req = DebugUnlockRequest;
+///     type Resp = Error;
+/// }
+///
+/// pub struct DebugUnlockRequest {
+///     pub token: u32,
+/// }
+///
+/// impl Request<'_> for DebugUnlockRequest {
+///     const TYPE: CommandByte = CommandByte(0xf5);
+/// }
+///
+/// impl<'a> FromWire<'a> for DebugUnlockRequest {
+///     fn from_wire<R: Read, A: Arena>(
+///         mut r: R,
+///         _: &'a A,
+///     ) -> Result<Self, FromWireError> {
+///         let token = r.read_le()?;
+///         Ok(Self { token })
+///     }
+/// }
+///
+/// impl ToWire for DebugUnlockRequest {
+///     fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+///         w.write_le(self.token)?;
+///         Ok(())
+///     }
+/// }
+/// # assert!(DebugUnlockRequest::TYPE.command_type().is_none());
+/// ```
 pub trait Command<'req> {
     /// The unique request type for this `Command`.
     type Req: Request<'req>;
@@ -107,16 +162,22 @@ pub trait Command<'req> {
 ///
 /// See [`Command`](trait.Command.html).
 pub trait Request<'req>: FromWire<'req> + ToWire {
-    /// The unique [`CommandType`] for this `Request`.
-    const TYPE: CommandType;
+    /// The unique command byte for this `Request`.
+    ///
+    /// For commands known to `manticore`, this will be the byte for some
+    /// [`CommandType`].
+    const TYPE: CommandByte;
 }
 
 /// A Cerberus response.
 ///
 /// See [`Command`](trait.Command.html).
 pub trait Response<'req>: FromWire<'req> + ToWire {
-    /// The unique [`CommandType`] for this `Response`.
-    const TYPE: CommandType;
+    /// The unique command byte for this `Response`.
+    ///
+    /// For commands known to `manticore`, this will be the byte for some
+    /// [`CommandType`].
+    const TYPE: CommandByte;
 }
 
 wire_enum! {
@@ -185,6 +246,15 @@ wire_enum! {
         /// [`RequestCounter`]:
         ///     device_uptime/struct.RequestCounter.html
         RequestCounter = 0xa1,
+        /// A request to update the set of keys trusted to sign manifests.
+        ///
+        /// Note that this command is a `manticore` extension.
+        ///
+        /// See [`KeySetUpdate`].
+        ///
+        /// [`KeySetUpdate`]:
+        ///     key_set_update/enum.KeySetUpdate.html
+        KeySetUpdate = 0xa2,
     }
 }
 
@@ -192,25 +262,113 @@ impl CommandType {
     /// Returns `true` when `self` represents a `manticore` extension to the
     /// protocol.
     pub fn is_manticore_extension(self) -> bool {
-        matches!(self, Self::DeviceUptime)
+        matches!(self, Self::DeviceUptime | Self::KeySetUpdate)
+    }
+
+    /// Returns the [`CommandByte`] for this `CommandType`.
+    ///
+    /// This is equivalent to `CommandByte::from(self)`, but can be used in
+    /// `const` contexts, such as [`Request::TYPE`].
+    pub const fn to_byte(self) -> CommandByte {
+        CommandByte(self as u8)
+    }
+}
+
+/// A raw command type byte, as it appears in a `manticore` header.
+///
+/// Unlike [`CommandType`], a `CommandByte` can represent any value, including
+/// command types that `manticore` does not know about, such as
+/// vendor-specific commands, or commands from a newer version of Cerberus.
+/// This ensures that such commands can be passed through to an integration,
+/// rather than being confused with some other command.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CommandByte(pub u8);
+
+impl CommandByte {
+    /// Returns the [`CommandType`] this byte represents, if it is one known
+    /// to `manticore`.
+    pub fn command_type(self) -> Option<CommandType> {
+        CommandType::from_wire_value(self.0)
+    }
+}
+
+impl From<CommandType> for CommandByte {
+    fn from(ty: CommandType) -> Self {
+        Self(ty.to_wire_value())
+    }
+}
+
+impl From<u8> for CommandByte {
+    fn from(byte: u8) -> Self {
+        Self(byte)
     }
 }
 
-impl From<u8> for CommandType {
-    fn from(num: u8) -> CommandType {
-        match num {
-            0x01 => CommandType::FirmwareVersion,
-            0x02 => CommandType::DeviceCapabilities,
-            0x03 => CommandType::DeviceId,
-            0x04 => CommandType::DeviceInfo,
-            0x87 => CommandType::ResetCounter,
-            0xa0 => CommandType::DeviceUptime,
-            0xa1 => CommandType::RequestCounter,
-            _ => CommandType::Error,
+impl From<CommandByte> for u8 {
+    fn from(byte: CommandByte) -> Self {
+        byte.0
+    }
+}
+
+impl PartialEq<CommandType> for CommandByte {
+    fn eq(&self, other: &CommandType) -> bool {
+        self.0 == other.to_wire_value()
+    }
+}
+
+impl PartialEq<CommandByte> for CommandType {
+    fn eq(&self, other: &CommandByte) -> bool {
+        other == self
+    }
+}
+
+impl core::fmt::Display for CommandByte {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.command_type() {
+            Some(ty) => write!(f, "{}", ty),
+            None => write!(f, "{:#04x}", self.0),
         }
     }
 }
 
+/// Parses a `CommandByte` either from the name of a [`CommandType`] or from
+/// a numeric literal, such as `0xf5`.
+impl core::str::FromStr for CommandByte {
+    type Err = wire::WireEnumFromStrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ty) = s.parse::<CommandType>() {
+            return Ok(ty.into());
+        }
+
+        let (digits, radix) = if let Some(hex) = s.strip_prefix("0x") {
+            (hex, 16)
+        } else {
+            (s, 10)
+        };
+        u8::from_str_radix(digits, radix)
+            .map(Self)
+            .map_err(|_| wire::WireEnumFromStrError)
+    }
+}
+
+impl<'a> FromWire<'a> for CommandByte {
+    fn from_wire<R: Read, A: Arena>(
+        mut r: R,
+        _: &'a A,
+    ) -> Result<Self, FromWireError> {
+        Ok(Self(r.read_le::<u8>()?))
+    }
+}
+
+impl ToWire for CommandByte {
+    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
+        w.write_le(self.0)?;
+        Ok(())
+    }
+}
+
 /// A parsed `manticore` header.
 ///
 /// This struct represents all of the meaningful fields from a `manticore`
@@ -219,8 +377,11 @@ impl From<u8> for CommandType {
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Header {
-    /// The [`CommandType`] for a request.
-    pub command: CommandType,
+    /// The command type for a request.
+    ///
+    /// This is a [`CommandByte`] rather than a [`CommandType`], so that
+    /// headers for commands unknown to `manticore` can still be represented.
+    pub command: CommandByte,
     /// The "request bit", for interpreting whether the body is the
     /// request or response variant of a command.
     pub is_request: bool,
@@ -250,7 +411,7 @@ impl<'a> FromWire<'a> for Header {
             _ => return Err(FromWireError::OutOfRange),
         };
 
-        let command = CommandType::from_wire(r, a)?;
+        let command = CommandByte::from_wire(r, a)?;
         Ok(Self {
             command,
             is_request,
@@ -270,20 +431,39 @@ impl ToWire for Header {
 wire_enum! {
     /// A Cerberus error.
     ///
-    /// This enum represents all error types implemented by `manticore`.
-    /// Because `manticore` does not mandate running the connection over MCTP,
-    /// the MCTP-specific error codes are omited.
+    /// This enum represents all error codes specified by Cerberus. Some of
+    /// these, such as [`ErrorCode::InvalidChecksum`], relate to the MCTP
+    /// transport; because `manticore` does not mandate running the connection
+    /// over MCTP, they are only meaningful to integrations that do.
     #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
     pub enum ErrorCode: u8 {
         /// Represents a successful operation; this "error" code is used to
         /// turn an [`Error`] into an ACK.
         Ok = 0x00,
+        /// Indicates that a request was invalid, such as being malformed or
+        /// being for a command that the device does not support.
+        InvalidRequest = 0x01,
         /// Indicates that the device is "busy", usually meaning that other
         /// commands are being serviced.
         Busy = 0x03,
         /// Indicates an unspecified, vendor-defined error, which may include
         /// extra data in an [`Error`].
         Unspecified = 0x04,
+        /// Indicates that a message failed its integrity check.
+        InvalidChecksum = 0xf0,
+        /// Indicates that a message was received out of the expected order,
+        /// such as a packet in the middle of a message arriving without a
+        /// first packet.
+        OutOfOrderMessage = 0xf1,
+        /// Indicates that a request could not be authenticated.
+        AuthenticationFailure = 0xf2,
+        /// Indicates that a message was received outside of the expected
+        /// sequence number window.
+        OutOfSequenceWindow = 0xf3,
+        /// Indicates that a packet had an invalid length.
+        InvalidPacketLength = 0xf4,
+        /// Indicates that a message exceeded the maximum message size.
+        MessageOverflow = 0xf5,
     }
 }
 
@@ -312,10 +492,15 @@ impl Error {
             data: [0; 4],
         }
     }
+
+    /// Creates an `Error` with the given code and no extra data.
+    pub fn new(code: ErrorCode) -> Self {
+        Self { code, data: [0; 4] }
+    }
 }
 
 impl Response<'_> for Error {
-    const TYPE: CommandType = CommandType::Error;
+    const TYPE: CommandByte = CommandType::Error.to_byte();
 }
 
 impl<'a> FromWire<'a> for Error {
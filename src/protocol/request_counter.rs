@@ -9,15 +9,12 @@
 //!
 //! Note that the command exposed by this module is a `manticore` extension.
 
-use crate::io::Read;
-use crate::io::Write;
-use crate::mem::Arena;
 use crate::protocol::wire::FromWire;
-use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire;
-use crate::protocol::wire::ToWireError;
 use crate::protocol::Command;
+use crate::protocol::CommandByte;
 use crate::protocol::CommandType;
+use crate::protocol::FuzzSafe;
 use crate::protocol::Request;
 use crate::protocol::Response;
 
@@ -40,33 +37,17 @@ impl<'a> Command<'a> for RequestCounter {
 }
 
 /// The [`RequestCounter`] request.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RequestCounterRequest;
-make_fuzz_safe!(RequestCounterRequest);
 
 impl Request<'_> for RequestCounterRequest {
-    const TYPE: CommandType = CommandType::RequestCounter;
-}
-
-impl<'a> FromWire<'a> for RequestCounterRequest {
-    fn from_wire<R: Read, A: Arena>(
-        _: R,
-        _: &'a A,
-    ) -> Result<Self, FromWireError> {
-        Ok(RequestCounterRequest)
-    }
-}
-
-impl<'a> ToWire for RequestCounterRequest {
-    fn to_wire<W: Write>(&self, _: W) -> Result<(), ToWireError> {
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::RequestCounter.to_byte();
 }
 
 /// The [`RequestCounter`] response.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RequestCounterResponse {
@@ -75,32 +56,9 @@ pub struct RequestCounterResponse {
     /// The number of failed requests since reset.
     pub err_count: u16,
 }
-make_fuzz_safe!(RequestCounterResponse);
 
 impl Response<'_> for RequestCounterResponse {
-    const TYPE: CommandType = CommandType::RequestCounter;
-}
-
-impl<'a> FromWire<'a> for RequestCounterResponse {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        _: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let ok_count = r.read_le::<u16>()?;
-        let err_count = r.read_le::<u16>()?;
-        Ok(Self {
-            ok_count,
-            err_count,
-        })
-    }
-}
-
-impl ToWire for RequestCounterResponse {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        w.write_le(self.ok_count)?;
-        w.write_le(self.err_count)?;
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::RequestCounter.to_byte();
 }
 
 #[cfg(test)]
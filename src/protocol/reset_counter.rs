@@ -8,15 +8,12 @@
 //! number of resets a device the RoT is connected to has undergone since it
 //! powered on.
 
-use crate::io::Read;
-use crate::io::Write;
-use crate::mem::Arena;
 use crate::protocol::wire::FromWire;
-use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire;
-use crate::protocol::wire::ToWireError;
 use crate::protocol::Command;
+use crate::protocol::CommandByte;
 use crate::protocol::CommandType;
+use crate::protocol::FuzzSafe;
 use crate::protocol::Request;
 use crate::protocol::Response;
 
@@ -58,7 +55,7 @@ wire_enum! {
 }
 
 /// The [`ResetCounter`] request.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ResetCounterRequest {
@@ -67,63 +64,22 @@ pub struct ResetCounterRequest {
     /// The port that the device whose reset counter is being looked up.
     pub port_id: u8,
 }
-make_fuzz_safe!(ResetCounterRequest);
 
 impl Request<'_> for ResetCounterRequest {
-    const TYPE: CommandType = CommandType::ResetCounter;
-}
-
-impl<'a> FromWire<'a> for ResetCounterRequest {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        a: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let reset_type = ResetType::from_wire(&mut r, a)?;
-        let port_id = r.read_le::<u8>()?;
-        Ok(Self {
-            reset_type,
-            port_id,
-        })
-    }
-}
-
-impl<'a> ToWire for ResetCounterRequest {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        self.reset_type.to_wire(&mut w)?;
-        w.write_le(self.port_id)?;
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::ResetCounter.to_byte();
 }
 
 /// The [`ResetCounter`] response.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FromWire, ToWire, FuzzSafe)]
 #[cfg_attr(feature = "arbitrary-derive", derive(Arbitrary))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ResetCounterResponse {
     /// The number of resets since POR, for the requested device.
     pub count: u16,
 }
-make_fuzz_safe!(ResetCounterResponse);
 
 impl Response<'_> for ResetCounterResponse {
-    const TYPE: CommandType = CommandType::ResetCounter;
-}
-
-impl<'a> FromWire<'a> for ResetCounterResponse {
-    fn from_wire<R: Read, A: Arena>(
-        mut r: R,
-        _: &'a A,
-    ) -> Result<Self, FromWireError> {
-        let count = r.read_le::<u16>()?;
-        Ok(Self { count })
-    }
-}
-
-impl ToWire for ResetCounterResponse {
-    fn to_wire<W: Write>(&self, mut w: W) -> Result<(), ToWireError> {
-        w.write_le(self.count)?;
-        Ok(())
-    }
+    const TYPE: CommandByte = CommandType::ResetCounter.to_byte();
 }
 
 #[cfg(test)]
@@ -0,0 +1,828 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Machine-readable descriptions of protocol messages.
+//!
+//! This module provides a static [`CommandSchema`] for every [`CommandType`],
+//! describing the layout of its request and response messages: the name,
+//! offset and width of each field, and the values of enumerated fields.
+//!
+//! These descriptions are intended for generating external tooling, such as
+//! packet decoders, without needing to re-encode the knowledge in each
+//! message's `FromWire` and `ToWire` implementations.
+//!
+//! Fields of nested structs are flattened, and named by their path from the
+//! message, such as `capabilities.networking.max_message_size`. All integers
+//! are unsigned and little-endian.
+
+use crate::protocol::capabilities::AesKeyStrength;
+use crate::protocol::capabilities::BusRole;
+use crate::protocol::capabilities::EccKeyStrength;
+use crate::protocol::capabilities::RotMode;
+use crate::protocol::capabilities::RsaKeyStrength;
+use crate::protocol::capabilities::Security;
+use crate::protocol::device_info::InfoIndex;
+use crate::protocol::key_set_update::KeyOp;
+use crate::protocol::reset_counter::ResetType;
+use crate::protocol::CommandType;
+use crate::protocol::ErrorCode;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// A description of the messages of a command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CommandSchema {
+    /// The command being described.
+    pub command: CommandType,
+    /// The request message, if this command has one.
+    ///
+    /// Only [`CommandType::Error`] has no request.
+    pub request: Option<MessageSchema>,
+    /// The response message.
+    pub response: MessageSchema,
+}
+
+/// A description of a single message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct MessageSchema {
+    /// The name of the Rust type for this message.
+    pub name: &'static str,
+    /// This message's fields, in wire order.
+    pub fields: &'static [Field],
+}
+
+/// A description of a field within a message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Field {
+    /// The name of this field; nested fields are separated by `.`.
+    pub name: &'static str,
+    /// Where this field begins within the message.
+    pub offset: Offset,
+    /// The width of this field.
+    pub width: Width,
+    /// How this field should be interpreted.
+    pub kind: Kind,
+}
+
+/// The position of a [`Field`] within its message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Offset {
+    /// A fixed offset from the start of the message, in bytes.
+    ///
+    /// For bit fields, this is the offset of the byte containing them.
+    Fixed(usize),
+    /// Immediately after the preceding field, which has no fixed offset or
+    /// a variable width, such as [`Width::Prefixed`].
+    AfterPrevious,
+}
+
+/// The width of a [`Field`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Width {
+    /// A fixed number of bytes.
+    Bytes(usize),
+    /// A bit field within a single byte.
+    Bits {
+        /// The number of bits the field is shifted left by within the byte.
+        shift: u8,
+        /// The number of bits in the field.
+        len: u8,
+    },
+    /// A little-endian length of the given number of bytes, followed by that
+    /// many bytes.
+    Prefixed(usize),
+    /// All remaining bytes of the message.
+    Remaining,
+}
+
+/// The interpretation of a [`Field`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Kind {
+    /// Reserved bits or bytes, which should be zero.
+    Reserved,
+    /// An unsigned, little-endian integer.
+    Int,
+    /// A single-bit boolean.
+    Bool,
+    /// Uninterpreted bytes.
+    Bytes,
+    /// An integer which takes on one of the given values.
+    Enum(&'static [Value]),
+    /// An integer consisting of some combination of the given bits.
+    Flags(&'static [Value]),
+    /// An integer duration, in units of the given number of microseconds.
+    Duration {
+        /// The number of microseconds in one unit of this field.
+        unit_micros: u64,
+    },
+}
+
+/// A named value of an [`Kind::Enum`] or [`Kind::Flags`] field.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Value {
+    /// The name of this value.
+    pub name: &'static str,
+    /// The value itself.
+    pub value: u64,
+}
+
+/// Returns the schema for `command`.
+pub fn command(command: CommandType) -> &'static CommandSchema {
+    COMMANDS
+        .iter()
+        .find(|c| c.command == command)
+        .expect("every command type should have a schema")
+}
+
+/// Builds a list of [`Value`]s out of the variants of a `wire_enum!`.
+macro_rules! enum_values {
+    ($ty:ident { $($variant:ident),* $(,)? }) => {
+        &[$(Value {
+            name: stringify!($variant),
+            value: $ty::$variant as u64,
+        },)*]
+    };
+}
+
+/// Builds a list of [`Value`]s out of the flags of a `bitflags!`.
+macro_rules! flag_values {
+    ($ty:ident { $($flag:ident),* $(,)? }) => {
+        &[$(Value {
+            name: stringify!($flag),
+            value: $ty::$flag.bits() as u64,
+        },)*]
+    };
+}
+
+/// Builds the fields of a [`Capabilities`], which begins both the
+/// `DeviceCapabilities` request and response, followed by `$extra`.
+///
+/// [`Capabilities`]: crate::protocol::capabilities::Capabilities
+macro_rules! capabilities_fields {
+    ($($extra:expr),* $(,)?) => {
+        &[
+            field(
+                "capabilities.networking.max_message_size",
+                0,
+                Width::Bytes(2),
+                Kind::Int,
+            ),
+            field(
+                "capabilities.networking.max_packet_size",
+                2,
+                Width::Bytes(2),
+                Kind::Int,
+            ),
+            field(
+                "capabilities.networking.mode",
+                4,
+                bits(6, 2),
+                Kind::Enum(ROT_MODE),
+            ),
+            field(
+                "capabilities.networking.roles",
+                4,
+                bits(4, 2),
+                Kind::Flags(BUS_ROLE),
+            ),
+            field("reserved", 4, bits(3, 1), Kind::Reserved),
+            field(
+                "capabilities.security",
+                4,
+                bits(0, 3),
+                Kind::Flags(SECURITY),
+            ),
+            field("capabilities.has_pfm_support", 5, bits(7, 1), Kind::Bool),
+            field("capabilities.has_policy_support", 5, bits(6, 1), Kind::Bool),
+            field(
+                "capabilities.has_firmware_protection",
+                5,
+                bits(5, 1),
+                Kind::Bool,
+            ),
+            field("reserved", 5, bits(0, 5), Kind::Reserved),
+            field("capabilities.has_rsa", 6, bits(7, 1), Kind::Bool),
+            field("capabilities.has_ecdsa", 6, bits(6, 1), Kind::Bool),
+            field(
+                "capabilities.ecc_strength",
+                6,
+                bits(3, 3),
+                Kind::Flags(ECC_STRENGTH),
+            ),
+            field(
+                "capabilities.rsa_strength",
+                6,
+                bits(0, 3),
+                Kind::Flags(RSA_STRENGTH),
+            ),
+            field("capabilities.has_ecc", 7, bits(7, 1), Kind::Bool),
+            field("reserved", 7, bits(3, 4), Kind::Reserved),
+            field(
+                "capabilities.aes_strength",
+                7,
+                bits(0, 3),
+                Kind::Flags(AES_STRENGTH),
+            ),
+            $($extra,)*
+        ]
+    };
+}
+
+/// Schemas for all commands known to `manticore`.
+pub static COMMANDS: &[CommandSchema] = &[
+    CommandSchema {
+        command: CommandType::Error,
+        request: None,
+        response: ERROR,
+    },
+    CommandSchema {
+        command: CommandType::FirmwareVersion,
+        request: Some(MessageSchema {
+            name: "FirmwareVersionRequest",
+            fields: &[field("index", 0, Width::Bytes(1), Kind::Int)],
+        }),
+        response: MessageSchema {
+            name: "FirmwareVersionResponse",
+            fields: &[field("version", 0, Width::Bytes(32), Kind::Bytes)],
+        },
+    },
+    CommandSchema {
+        command: CommandType::DeviceCapabilities,
+        request: Some(MessageSchema {
+            name: "DeviceCapabilitiesRequest",
+            fields: capabilities_fields!(),
+        }),
+        response: MessageSchema {
+            name: "DeviceCapabilitiesResponse",
+            fields: capabilities_fields!(
+                field(
+                    "timeouts.regular",
+                    8,
+                    Width::Bytes(1),
+                    Kind::Duration {
+                        unit_micros: 10_000
+                    },
+                ),
+                field(
+                    "timeouts.crypto",
+                    9,
+                    Width::Bytes(1),
+                    Kind::Duration {
+                        unit_micros: 100_000,
+                    },
+                ),
+            ),
+        },
+    },
+    CommandSchema {
+        command: CommandType::DeviceId,
+        request: Some(MessageSchema {
+            name: "DeviceIdRequest",
+            fields: &[],
+        }),
+        response: MessageSchema {
+            name: "DeviceIdResponse",
+            fields: &[
+                field("id.vendor_id", 0, Width::Bytes(2), Kind::Int),
+                field("id.device_id", 2, Width::Bytes(2), Kind::Int),
+                field("id.subsys_vendor_id", 4, Width::Bytes(2), Kind::Int),
+                field("id.subsys_id", 6, Width::Bytes(2), Kind::Int),
+            ],
+        },
+    },
+    CommandSchema {
+        command: CommandType::DeviceInfo,
+        request: Some(MessageSchema {
+            name: "DeviceInfoRequest",
+            fields: &[field(
+                "index",
+                0,
+                Width::Bytes(1),
+                Kind::Enum(INFO_INDEX),
+            )],
+        }),
+        response: MessageSchema {
+            name: "DeviceInfoResponse",
+            fields: &[field("info", 0, Width::Remaining, Kind::Bytes)],
+        },
+    },
+    CommandSchema {
+        command: CommandType::ResetCounter,
+        request: Some(MessageSchema {
+            name: "ResetCounterRequest",
+            fields: &[
+                field("reset_type", 0, Width::Bytes(1), Kind::Enum(RESET_TYPE)),
+                field("port_id", 1, Width::Bytes(1), Kind::Int),
+            ],
+        }),
+        response: MessageSchema {
+            name: "ResetCounterResponse",
+            fields: &[field("count", 0, Width::Bytes(2), Kind::Int)],
+        },
+    },
+    CommandSchema {
+        command: CommandType::DeviceUptime,
+        request: Some(MessageSchema {
+            name: "DeviceUptimeRequest",
+            fields: &[field("port_id", 0, Width::Bytes(1), Kind::Int)],
+        }),
+        response: MessageSchema {
+            name: "DeviceUptimeResponse",
+            fields: &[field(
+                "uptime",
+                0,
+                Width::Bytes(4),
+                Kind::Duration { unit_micros: 1 },
+            )],
+        },
+    },
+    CommandSchema {
+        command: CommandType::RequestCounter,
+        request: Some(MessageSchema {
+            name: "RequestCounterRequest",
+            fields: &[],
+        }),
+        response: MessageSchema {
+            name: "RequestCounterResponse",
+            fields: &[
+                field("ok_count", 0, Width::Bytes(2), Kind::Int),
+                field("err_count", 2, Width::Bytes(2), Kind::Int),
+            ],
+        },
+    },
+    CommandSchema {
+        command: CommandType::KeySetUpdate,
+        request: Some(MessageSchema {
+            name: "KeySetUpdateRequest",
+            fields: &[
+                field("op", 0, Width::Bytes(1), Kind::Enum(KEY_OP)),
+                field("key_id", 1, Width::Bytes(1), Kind::Int),
+                field("signer_id", 2, Width::Bytes(1), Kind::Int),
+                field("key", 3, Width::Prefixed(2), Kind::Bytes),
+                field_after("signature", Width::Remaining, Kind::Bytes),
+            ],
+        }),
+        response: ERROR,
+    },
+];
+
+/// The schema for [`protocol::Error`], which also serves as the response to
+/// some commands.
+///
+/// [`protocol::Error`]: crate::protocol::Error
+const ERROR: MessageSchema = MessageSchema {
+    name: "Error",
+    fields: &[
+        field("code", 0, Width::Bytes(1), Kind::Enum(ERROR_CODE)),
+        field("data", 1, Width::Bytes(4), Kind::Bytes),
+    ],
+};
+
+/// Shorthand for building a [`Field`] at a fixed offset.
+const fn field(
+    name: &'static str,
+    offset: usize,
+    width: Width,
+    kind: Kind,
+) -> Field {
+    Field {
+        name,
+        offset: Offset::Fixed(offset),
+        width,
+        kind,
+    }
+}
+
+/// Shorthand for building a [`Field`] that follows a variable-width one.
+const fn field_after(name: &'static str, width: Width, kind: Kind) -> Field {
+    Field {
+        name,
+        offset: Offset::AfterPrevious,
+        width,
+        kind,
+    }
+}
+
+/// Shorthand for a bit field.
+const fn bits(shift: u8, len: u8) -> Width {
+    Width::Bits { shift, len }
+}
+
+const ERROR_CODE: &[Value] = enum_values!(ErrorCode {
+    Ok,
+    InvalidRequest,
+    Busy,
+    Unspecified,
+    InvalidChecksum,
+    OutOfOrderMessage,
+    AuthenticationFailure,
+    OutOfSequenceWindow,
+    InvalidPacketLength,
+    MessageOverflow,
+});
+const INFO_INDEX: &[Value] = enum_values!(InfoIndex { UniqueChipIndex });
+const KEY_OP: &[Value] = enum_values!(KeyOp { Add, Revoke });
+const RESET_TYPE: &[Value] = enum_values!(ResetType { Local, External });
+const ROT_MODE: &[Value] = enum_values!(RotMode { Active, Platform });
+
+const BUS_ROLE: &[Value] = flag_values!(BusRole { HOST, TARGET });
+const SECURITY: &[Value] = flag_values!(Security {
+    HASH_AND_KDF,
+    AUTHENTICATION,
+    CONFIDENTIALITY,
+});
+const ECC_STRENGTH: &[Value] =
+    flag_values!(EccKeyStrength { BITS_160, BITS_256 });
+const RSA_STRENGTH: &[Value] = flag_values!(RsaKeyStrength {
+    BITS_2048,
+    BITS_3072,
+    BITS_4096
+});
+const AES_STRENGTH: &[Value] =
+    flag_values!(AesKeyStrength { BITS_128, BITS_256 });
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::time::Duration;
+
+    use crate::io::Cursor;
+    use crate::protocol::capabilities::*;
+    use crate::protocol::device_id::*;
+    use crate::protocol::device_info::*;
+    use crate::protocol::device_uptime::*;
+    use crate::protocol::firmware_version::*;
+    use crate::protocol::key_set_update::*;
+    use crate::protocol::request_counter::*;
+    use crate::protocol::reset_counter::*;
+    use crate::protocol::wire::ToWire;
+    use crate::protocol::wire::WireEnum;
+    use crate::protocol::Error;
+
+    /// Checks that `values` lists exactly the variants of `E`.
+    fn check_enum<E: WireEnum<Wire = u8>>(values: &[Value]) {
+        let mut expected = Vec::new();
+        for wire in 0..=u8::MAX {
+            if let Some(e) = E::from_wire_value(wire) {
+                expected.push((e.name(), wire as u64));
+            }
+        }
+        let actual =
+            values.iter().map(|v| (v.name, v.value)).collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn enums_are_complete() {
+        check_enum::<CommandType>(enum_values!(CommandType {
+            FirmwareVersion,
+            DeviceCapabilities,
+            DeviceId,
+            DeviceInfo,
+            Error,
+            ResetCounter,
+            DeviceUptime,
+            RequestCounter,
+            KeySetUpdate,
+        }));
+        check_enum::<ErrorCode>(ERROR_CODE);
+        check_enum::<InfoIndex>(INFO_INDEX);
+        check_enum::<KeyOp>(KEY_OP);
+        check_enum::<ResetType>(RESET_TYPE);
+        check_enum::<RotMode>(ROT_MODE);
+    }
+
+    #[test]
+    fn flags_are_complete() {
+        fn all(values: &[Value]) -> u64 {
+            values.iter().fold(0, |acc, v| acc | v.value)
+        }
+        assert_eq!(all(BUS_ROLE), BusRole::all().bits() as u64);
+        assert_eq!(all(SECURITY), Security::all().bits() as u64);
+        assert_eq!(all(ECC_STRENGTH), EccKeyStrength::all().bits() as u64);
+        assert_eq!(all(RSA_STRENGTH), RsaKeyStrength::all().bits() as u64);
+        assert_eq!(all(AES_STRENGTH), AesKeyStrength::all().bits() as u64);
+    }
+
+    #[test]
+    fn every_command_has_a_schema() {
+        for wire in 0..=u8::MAX {
+            if let Some(c) = CommandType::from_wire_value(wire) {
+                assert_eq!(command(c).command, c);
+            }
+        }
+    }
+
+    #[test]
+    fn fields_are_contiguous() {
+        let messages = COMMANDS
+            .iter()
+            .flat_map(|c| c.request.iter().chain(Some(&c.response)));
+        for message in messages {
+            // `None` once the offset is no longer fixed.
+            let mut offset = Some(0);
+            let mut bits_left = 8;
+            let mut saw_remaining = false;
+            for field in message.fields {
+                assert!(
+                    !saw_remaining,
+                    "{}: field after remainder",
+                    field.name
+                );
+                let expected = match offset {
+                    Some(offset) => Offset::Fixed(offset),
+                    None => Offset::AfterPrevious,
+                };
+                assert_eq!(
+                    field.offset, expected,
+                    "{}: bad offset",
+                    field.name
+                );
+                match field.width {
+                    Width::Bytes(n) => offset = offset.map(|o| o + n),
+                    Width::Bits { shift, len } => {
+                        assert_eq!(
+                            shift + len,
+                            bits_left,
+                            "{}: bad bit field",
+                            field.name
+                        );
+                        bits_left = shift;
+                        if bits_left == 0 {
+                            bits_left = 8;
+                            offset = offset.map(|o| o + 1);
+                        }
+                    }
+                    Width::Prefixed(_) => offset = None,
+                    Width::Remaining => saw_remaining = true,
+                }
+            }
+            assert_eq!(bits_left, 8, "{}: partial byte", message.name);
+        }
+    }
+
+    /// The value of a field in an encoded message.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Decoded<'a> {
+        Int(u64),
+        Bytes(&'a [u8]),
+    }
+
+    /// Encodes `message`, and checks that reading each field of `schema` out
+    /// of the encoding yields exactly `expected`, with no bytes left over.
+    ///
+    /// Reserved fields are checked to be zero, and are not listed in
+    /// `expected`.
+    fn check_encoding(
+        schema: &MessageSchema,
+        message: impl ToWire,
+        expected: &[(&str, Decoded)],
+    ) {
+        fn le(bytes: &[u8]) -> u64 {
+            bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64)
+        }
+
+        let mut buf = [0; 256];
+        let mut cursor = Cursor::new(&mut buf);
+        message.to_wire(&mut cursor).unwrap();
+        let bytes = cursor.consumed_bytes();
+
+        let mut pos = 0;
+        let mut actual = Vec::new();
+        for field in schema.fields {
+            let start = match field.offset {
+                Offset::Fixed(offset) => offset,
+                Offset::AfterPrevious => pos,
+            };
+            let value = match field.width {
+                Width::Bytes(n) => {
+                    pos = start + n;
+                    match field.kind {
+                        Kind::Bytes => Decoded::Bytes(&bytes[start..pos]),
+                        _ => Decoded::Int(le(&bytes[start..pos])),
+                    }
+                }
+                Width::Bits { shift, len } => {
+                    if shift == 0 {
+                        pos = start + 1;
+                    }
+                    let mask = (1u64 << len) - 1;
+                    Decoded::Int((bytes[start] as u64 >> shift) & mask)
+                }
+                Width::Prefixed(n) => {
+                    let len = le(&bytes[start..start + n]) as usize;
+                    pos = start + n + len;
+                    Decoded::Bytes(&bytes[start + n..pos])
+                }
+                Width::Remaining => {
+                    pos = bytes.len();
+                    Decoded::Bytes(&bytes[start..])
+                }
+            };
+
+            if field.kind == Kind::Reserved {
+                assert_eq!(value, Decoded::Int(0), "{}: not zero", field.name);
+                continue;
+            }
+            actual.push((field.name, value));
+        }
+
+        assert_eq!(pos, bytes.len(), "{}: trailing bytes", schema.name);
+        assert_eq!(actual, expected, "{}: wrong fields", schema.name);
+    }
+
+    /// Returns the request schema for `c`.
+    fn request(c: CommandType) -> &'static MessageSchema {
+        command(c).request.as_ref().unwrap()
+    }
+
+    /// Returns the response schema for `c`.
+    fn response(c: CommandType) -> &'static MessageSchema {
+        &command(c).response
+    }
+
+    #[test]
+    fn fields_match_encodings() {
+        use self::Decoded::*;
+
+        check_encoding(
+            response(CommandType::Error),
+            Error {
+                code: ErrorCode::Busy,
+                data: [1, 2, 3, 4],
+            },
+            &[("code", Int(0x03)), ("data", Bytes(&[1, 2, 3, 4]))],
+        );
+
+        check_encoding(
+            request(CommandType::FirmwareVersion),
+            FirmwareVersionRequest { index: 7 },
+            &[("index", Int(7))],
+        );
+        check_encoding(
+            response(CommandType::FirmwareVersion),
+            FirmwareVersionResponse {
+                version: &[0x5a; 32],
+            },
+            &[("version", Bytes(&[0x5a; 32]))],
+        );
+
+        let capabilities = Capabilities {
+            networking: Networking {
+                max_message_size: 0x0102,
+                max_packet_size: 0x0304,
+                mode: RotMode::Platform,
+                roles: BusRole::HOST | BusRole::TARGET,
+            },
+            security: Security::AUTHENTICATION,
+            has_pfm_support: true,
+            has_policy_support: false,
+            has_firmware_protection: true,
+            has_ecdsa: false,
+            has_ecc: true,
+            has_rsa: true,
+            has_aes: false,
+            ecc_strength: EccKeyStrength::BITS_256,
+            rsa_strength: RsaKeyStrength::BITS_2048 | RsaKeyStrength::BITS_4096,
+            aes_strength: AesKeyStrength::BITS_128,
+        };
+        let capabilities_fields = [
+            ("capabilities.networking.max_message_size", Int(0x0102)),
+            ("capabilities.networking.max_packet_size", Int(0x0304)),
+            ("capabilities.networking.mode", Int(0b01)),
+            ("capabilities.networking.roles", Int(0b11)),
+            ("capabilities.security", Int(0b010)),
+            ("capabilities.has_pfm_support", Int(1)),
+            ("capabilities.has_policy_support", Int(0)),
+            ("capabilities.has_firmware_protection", Int(1)),
+            ("capabilities.has_rsa", Int(1)),
+            ("capabilities.has_ecdsa", Int(0)),
+            ("capabilities.ecc_strength", Int(0b010)),
+            ("capabilities.rsa_strength", Int(0b101)),
+            ("capabilities.has_ecc", Int(1)),
+            ("capabilities.aes_strength", Int(0b001)),
+        ];
+        check_encoding(
+            request(CommandType::DeviceCapabilities),
+            DeviceCapabilitiesRequest { capabilities },
+            &capabilities_fields,
+        );
+        let mut response_fields = capabilities_fields.to_vec();
+        response_fields.extend_from_slice(&[
+            ("timeouts.regular", Int(3)),
+            ("timeouts.crypto", Int(2)),
+        ]);
+        check_encoding(
+            response(CommandType::DeviceCapabilities),
+            DeviceCapabilitiesResponse {
+                capabilities,
+                timeouts: Timeouts {
+                    regular: Duration::from_millis(30),
+                    crypto: Duration::from_millis(200),
+                },
+            },
+            &response_fields,
+        );
+
+        check_encoding(request(CommandType::DeviceId), DeviceIdRequest, &[]);
+        check_encoding(
+            response(CommandType::DeviceId),
+            DeviceIdResponse {
+                id: DeviceIdentifier {
+                    vendor_id: 0x0102,
+                    device_id: 0x0304,
+                    subsys_vendor_id: 0x0506,
+                    subsys_id: 0x0708,
+                },
+            },
+            &[
+                ("id.vendor_id", Int(0x0102)),
+                ("id.device_id", Int(0x0304)),
+                ("id.subsys_vendor_id", Int(0x0506)),
+                ("id.subsys_id", Int(0x0708)),
+            ],
+        );
+
+        check_encoding(
+            request(CommandType::DeviceInfo),
+            DeviceInfoRequest {
+                index: InfoIndex::UniqueChipIndex,
+            },
+            &[("index", Int(0))],
+        );
+        check_encoding(
+            response(CommandType::DeviceInfo),
+            DeviceInfoResponse { info: b"chip" },
+            &[("info", Bytes(b"chip"))],
+        );
+
+        check_encoding(
+            request(CommandType::ResetCounter),
+            ResetCounterRequest {
+                reset_type: ResetType::External,
+                port_id: 9,
+            },
+            &[("reset_type", Int(1)), ("port_id", Int(9))],
+        );
+        check_encoding(
+            response(CommandType::ResetCounter),
+            ResetCounterResponse { count: 0x0102 },
+            &[("count", Int(0x0102))],
+        );
+
+        check_encoding(
+            request(CommandType::DeviceUptime),
+            DeviceUptimeRequest { port_id: 9 },
+            &[("port_id", Int(9))],
+        );
+        check_encoding(
+            response(CommandType::DeviceUptime),
+            DeviceUptimeResponse {
+                uptime: Duration::from_micros(0x01020304),
+            },
+            &[("uptime", Int(0x01020304))],
+        );
+
+        check_encoding(
+            request(CommandType::RequestCounter),
+            RequestCounterRequest,
+            &[],
+        );
+        check_encoding(
+            response(CommandType::RequestCounter),
+            RequestCounterResponse {
+                ok_count: 0x0102,
+                err_count: 0x0304,
+            },
+            &[("ok_count", Int(0x0102)), ("err_count", Int(0x0304))],
+        );
+
+        check_encoding(
+            request(CommandType::KeySetUpdate),
+            KeySetUpdateRequest {
+                op: KeyOp::Revoke,
+                key_id: 2,
+                signer_id: 3,
+                key: b"key",
+                signature: b"sig",
+            },
+            &[
+                ("op", Int(1)),
+                ("key_id", Int(2)),
+                ("signer_id", Int(3)),
+                ("key", Bytes(b"key")),
+                ("signature", Bytes(b"sig")),
+            ],
+        );
+    }
+}
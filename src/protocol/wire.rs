@@ -8,6 +8,10 @@
 //! to the core traits in the [`serde`] library. Rather than representing a
 //! generically serializeable type, they represent types that can be converted
 //! to and from Cerberus's wire format, which has a unique, ad-hoc data model.
+//!
+//! Rather than implementing these traits by hand, most types can derive
+//! them with `#[derive(FromWire, ToWire)]`; see the `manticore-derive`
+//! crate for the attributes that control the derived wire format.
 
 use core::fmt;
 
@@ -18,6 +22,10 @@ use crate::io::Write;
 use crate::mem::Arena;
 use crate::mem::OutOfMemory;
 
+pub use manticore_derive::FromWire;
+pub use manticore_derive::ToWire;
+pub use manticore_derive::WireEnum;
+
 /// A type which can be deserialized from the Cerberus wire format.
 ///
 /// The lifetime `'wire` indicates that the type can be deserialized from a
@@ -143,6 +151,149 @@ where
     }
 }
 
+/// A type which can be packed into a bit field within a byte.
+///
+/// This trait is used by `#[derive(FromWire, ToWire)]` for fields marked
+/// `#[wire(bits = N)]`.
+pub trait BitField: Sized + Copy {
+    /// Attempts to parse a value of `Self` from the least significant bits
+    /// of `bits`.
+    fn from_bits(bits: u8) -> Option<Self>;
+
+    /// Converts `self` into bits; only the least significant bits will be
+    /// used.
+    fn to_bits(self) -> u8;
+}
+
+impl BitField for bool {
+    fn from_bits(bits: u8) -> Option<Self> {
+        Some(bits != 0)
+    }
+    fn to_bits(self) -> u8 {
+        self as u8
+    }
+}
+
+impl BitField for u8 {
+    fn from_bits(bits: u8) -> Option<Self> {
+        Some(bits)
+    }
+    fn to_bits(self) -> u8 {
+        self
+    }
+}
+
+impl<E> BitField for E
+where
+    E: WireEnum<Wire = u8>,
+{
+    fn from_bits(bits: u8) -> Option<Self> {
+        Self::from_wire_value(bits)
+    }
+    fn to_bits(self) -> u8 {
+        self.to_wire_value()
+    }
+}
+
+/// Support code for `manticore-derive`; not part of the public API.
+#[doc(hidden)]
+pub mod derive_support {
+    use zerocopy::AsBytes;
+    use zerocopy::FromBytes;
+
+    use crate::io;
+    use crate::io::endian::LeInt;
+    use crate::io::Read;
+    use crate::io::Write;
+    use crate::mem::Arena;
+    use crate::mem::ArenaExt as _;
+    use crate::mem::OutOfMemory;
+
+    /// An integer which can be byte-swapped, for big-endian I/O.
+    pub trait Swap: LeInt {
+        fn swap(self) -> Self;
+    }
+
+    macro_rules! impl_swap {
+        ($($ty:ty),*) => {$(
+            impl Swap for $ty {
+                fn swap(self) -> Self {
+                    self.swap_bytes()
+                }
+            }
+        )*}
+    }
+    impl_swap!(u8, u16, u32, u64);
+
+    pub fn read_be<I: Swap, R: Read>(r: R) -> Result<I, io::Error> {
+        I::read_from(r).map(I::swap)
+    }
+
+    pub fn write_be<I: Swap>(w: impl Write, val: I) -> Result<(), io::Error> {
+        val.swap().write_to(w)
+    }
+
+    pub fn read_ref<T>(
+        mut r: impl Read,
+        arena: &impl Arena,
+    ) -> Result<&T, super::FromWireError>
+    where
+        T: AsBytes + FromBytes + Copy,
+    {
+        let val = arena.alloc::<T>()?;
+        r.read_bytes(val.as_bytes_mut())?;
+        Ok(val)
+    }
+
+    pub fn write_ref<T: AsBytes>(
+        mut w: impl Write,
+        val: &T,
+    ) -> Result<(), io::Error> {
+        w.write_bytes(val.as_bytes())
+    }
+
+    pub fn read_slice<T>(
+        mut r: impl Read,
+        arena: &impl Arena,
+        len: usize,
+    ) -> Result<&[T], super::FromWireError>
+    where
+        T: AsBytes + FromBytes + Copy,
+    {
+        let bytes = len
+            .checked_mul(core::mem::size_of::<T>())
+            .ok_or(OutOfMemory)?;
+        if bytes > r.remaining_data() {
+            return Err(io::Error::BufferExhausted.into());
+        }
+        let slice = arena.alloc_slice::<T>(len)?;
+        r.read_bytes(slice.as_bytes_mut())?;
+        Ok(slice)
+    }
+
+    pub fn read_remaining<T>(
+        r: impl Read,
+        arena: &impl Arena,
+    ) -> Result<&[T], super::FromWireError>
+    where
+        T: AsBytes + FromBytes + Copy,
+    {
+        let size = core::mem::size_of::<T>();
+        let len = r.remaining_data();
+        if size == 0 || len % size != 0 {
+            return Err(super::FromWireError::OutOfRange);
+        }
+        read_slice(r, arena, len / size)
+    }
+
+    pub fn write_slice<T: AsBytes>(
+        mut w: impl Write,
+        val: &[T],
+    ) -> Result<(), io::Error> {
+        w.write_bytes(val.as_bytes())
+    }
+}
+
 /// A deserialization-from-string error.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub struct WireEnumFromStrError;
@@ -245,6 +396,10 @@ macro_rules! wire_enum {
 
 #[cfg(test)]
 mod test {
+    use super::FromWire;
+    use super::ToWire;
+    use super::WireEnum;
+
     wire_enum! {
         /// An enum for testing.
         pub enum DemoEnum: u8 {
@@ -279,4 +434,126 @@ mod test {
         assert_eq!(DemoEnum::First.name(), "First");
         assert_eq!(DemoEnum::Second.name(), "Second");
     }
+
+    /// An enum for testing `#[derive(WireEnum)]`.
+    #[repr(u8)]
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, WireEnum)]
+    pub enum DerivedEnum {
+        /// First enum value
+        First = 0x01,
+        /// Second enum value
+        Second = 0x02,
+    }
+
+    /// A struct for testing `#[derive(FromWire, ToWire)]`.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, FromWire, ToWire)]
+    pub struct Derived<'a> {
+        pub le: u16,
+        #[wire(be)]
+        pub be: u32,
+        pub array: [u8; 3],
+        pub fixed: &'a [u8; 2],
+        #[wire(len_prefix = "u8")]
+        pub prefixed: &'a [u8],
+        #[wire(bits = 1)]
+        pub flag: bool,
+        #[wire(bits = 2, pad = 2)]
+        pub kind: DerivedEnum,
+        #[wire(bits = 3)]
+        pub small: u8,
+        pub nested: DerivedEnum,
+        #[wire(remaining)]
+        pub rest: &'a [u8],
+    }
+
+    /// A big-endian struct for testing `#[derive(FromWire, ToWire)]`.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, FromWire, ToWire)]
+    #[wire(be)]
+    pub struct DerivedBe<'a> {
+        pub be: u16,
+        #[wire(len_prefix = "u16")]
+        pub prefixed: &'a [u8],
+        #[wire(le)]
+        pub le: u16,
+    }
+
+    round_trip_test! {
+        derived_be_round_trip: {
+            bytes: &[
+                0x01, 0x02,  // Big-endian.
+                0x00, 0x02, b'a', b'b',  // Big-endian prefixed slice.
+                0x01, 0x02,  // Little-endian.
+            ],
+            value: DerivedBe {
+                be: 0x0102,
+                prefixed: b"ab",
+                le: 0x0201,
+            },
+        },
+        derived_round_trip: {
+            bytes: &[
+                0x01, 0x02,  // Little-endian.
+                0x01, 0x02, 0x03, 0x04,  // Big-endian.
+                b'a', b'b', b'c',  // Array.
+                b'd', b'e',  // Arena-allocated array.
+                0x02, b'f', b'g',  // Prefixed slice.
+                0b1_10_00_101,  // Bits.
+                0x01,  // Enum.
+                b'h', b'i', b'j',  // Remainder.
+            ],
+            value: Derived {
+                le: 0x0201,
+                be: 0x01020304,
+                array: *b"abc",
+                fixed: b"de",
+                prefixed: b"fg",
+                flag: true,
+                kind: DerivedEnum::Second,
+                small: 0b101,
+                nested: DerivedEnum::First,
+                rest: b"hij",
+            },
+        },
+    }
+
+    #[test]
+    fn derived_enum() {
+        use crate::protocol::wire::*;
+
+        assert_eq!(
+            DerivedEnum::from_wire_value(0x02),
+            Some(DerivedEnum::Second)
+        );
+        assert_eq!(DerivedEnum::from_wire_value(0x03), None);
+        assert_eq!(DerivedEnum::First.to_string(), "First");
+        assert_eq!("Second".parse::<DerivedEnum>(), Ok(DerivedEnum::Second));
+    }
+
+    #[test]
+    fn derived_out_of_range() {
+        use crate::mem::BumpArena;
+        use crate::protocol::wire::*;
+
+        let mut buf = [0; 64];
+        let arena = BumpArena::new(&mut buf);
+        let prefix: &[u8] = b"\x01\x02\x01\x02\x03\x04abcde";
+
+        // `kind` is out of range.
+        let bytes = [prefix, b"\x02fg", &[0b1_11_00_101, 0x01]].concat();
+        assert!(matches!(
+            Derived::from_wire(&mut &bytes[..], &arena),
+            Err(FromWireError::OutOfRange)
+        ));
+
+        // The padding after `kind` is not zero.
+        let bytes = [prefix, b"\x02fg", &[0b1_10_01_101, 0x01]].concat();
+        assert!(matches!(
+            Derived::from_wire(&mut &bytes[..], &arena),
+            Err(FromWireError::OutOfRange)
+        ));
+
+        // The length prefix runs past the end of the message.
+        let bytes = [prefix, b"\xfffg"].concat();
+        assert!(Derived::from_wire(&mut &bytes[..], &arena).is_err());
+    }
 }
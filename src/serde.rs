@@ -30,6 +30,11 @@ pub fn skip_if_true(b: &bool) -> bool {
     *b
 }
 
+/// For skipping field serialization if it's set to its default value.
+pub fn is_default<T: Default + PartialEq>(x: &T) -> bool {
+    *x == T::default()
+}
+
 /// For deserializing a `Vec<u8>` from either a string or a sequence of bytes.
 #[cfg(feature = "std")]
 pub fn de_bytestring<'de, D>(d: D) -> Result<Vec<u8>, D::Error>
@@ -131,3 +136,23 @@ where
 {
     s.serialize_str(&format!("0b{:b}", x))
 }
+
+/// For deserializing a flags type either from its fields or from its raw bits,
+/// which may be given as anything `de_radix()` accepts.
+pub fn de_flags<'de, D, T>(d: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + From<u8>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flags<T> {
+        Bits(#[serde(deserialize_with = "de_radix")] u8),
+        Fields(T),
+    }
+
+    Ok(match Flags::deserialize(d)? {
+        Flags::Bits(bits) => bits.into(),
+        Flags::Fields(flags) => flags,
+    })
+}
@@ -31,11 +31,27 @@
 //!   `MyCommand::Req::TYPE` matches the header's command type (if multiple
 //!   handlers could match, an unspecified one is chosen).
 //! - It parses the rest of `req` as a `MyCommand::Req`, and passes it and the
-//!   server context into the closure.
+//!   server context into the closure. If any bytes are left over after
+//!   parsing, an error is returned instead.
 //! - The closure executes, which returns
 //!   `Result<MyCommand::Resp, protocol::Error>`.
 //! - The resulting response or error is sent using `resp`.
-//! - If no handler is chosen, an error is returned.
+//! - If no handler is chosen, the `.fallback()` handler is called with the raw
+//!   payload of the request, if there is one; otherwise, an error is returned.
+//!
+//! Handlers may be wrapped in a [`Layer`] using `.layer()`; the layer is
+//! consulted before dispatching to any handler attached *before* the call to
+//! `.layer()`, and informed of the outcome afterwards. Handlers attached after
+//! the call bypass the layer.
+//!
+//! `run_async()` is the counterpart of `run()` for a
+//! [`net::asynch::AsyncHostPort`]: it waits for a request without blocking,
+//! processes it as `run()` would, and then waits for the reply to be flushed.
+//!
+//! If `run_with_error_replies()` is used instead, errors that occur before
+//! a response has begun (such as parse failures and unhandled commands) are
+//! additionally reported to the host as a `protocol::Error`. Messages that
+//! are not requests are dropped without a reply.
 //!
 //! This module is not part of `manticore`'s API.
 //!
@@ -66,19 +82,30 @@
 //! is irrelevant, because no two handlers can meaningfully have the same
 //! command type.
 
+use core::future::Future;
 use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
 
+use crate::io::Read as _;
 use crate::mem::Arena;
+use crate::mem::ArenaExt as _;
 use crate::net;
+use crate::net::asynch::AsyncHostPort;
 use crate::protocol;
 use crate::protocol::wire::FromWire;
 use crate::protocol::wire::FromWireError;
 use crate::protocol::wire::ToWire as _;
 use crate::protocol::wire::ToWireError;
+use crate::protocol::CommandByte;
 use crate::protocol::CommandType;
 use crate::protocol::Header;
 use crate::protocol::Request as _;
 use crate::protocol::Response as _;
+use crate::server::layer::Layer;
+use crate::server::layer::Outcome;
+use crate::server::layer::RequestInfo;
 
 /// A `*`-importable prelude that pulls in only the names that are necessary
 /// to make `Handler` work.
@@ -107,7 +134,38 @@ pub enum Error {
 
     /// Indicates that a request could not be handled, because no handler was
     /// provided for it.
-    UnhandledCommand(CommandType),
+    UnhandledCommand(CommandByte),
+
+    /// Indicates that a request was rejected by a [`Layer`], which has
+    /// already sent the contained error to the host.
+    Rejected(protocol::Error),
+
+    /// Indicates that processing finished without replying to the host.
+    NoReply,
+}
+
+impl Error {
+    /// Returns the [`protocol::Error`] that should be sent to the host to
+    /// report this error, if it is possible to do so.
+    ///
+    /// Errors that may occur after a response has already begun, such as
+    /// network and serialization errors, return `None`.
+    pub fn to_protocol_error(self) -> Option<protocol::Error> {
+        use protocol::ErrorCode;
+        let code = match self {
+            Error::FromWireError(FromWireError::OutOfMemory) => {
+                ErrorCode::Unspecified
+            }
+            Error::FromWireError(_)
+            | Error::ReqTooLong(_)
+            | Error::UnhandledCommand(_) => ErrorCode::InvalidRequest,
+            Error::NoReply => ErrorCode::Unspecified,
+            Error::Network(_) | Error::ToWireError(_) | Error::Rejected(_) => {
+                return None
+            }
+        };
+        Some(protocol::Error::new(code))
+    }
 }
 
 impl From<FromWireError> for Error {
@@ -157,6 +215,22 @@ pub struct Cons<Prev, Command, F> {
     _ph: PhantomData<Command>,
 }
 
+/// A [`Layer`] wrapped around a collection of handlers.
+///
+/// See [`HandlerMethods::layer()`].
+pub struct Layered<Prev, L> {
+    prev: Prev,
+    layer: L,
+}
+
+/// A handler for all commands not handled by any other handler.
+///
+/// See [`HandlerMethods::fallback()`].
+pub struct Fallback<Prev, F> {
+    prev: Prev,
+    handler: F,
+}
+
 mod sealed {
     /// A public-in-private trait, for ensuring outside users cannot
     /// accidentally implement `HandlerMethods`.
@@ -233,6 +307,46 @@ pub trait HandlerMethods<'req, 'srv, Server: 'srv>:
         }
     }
 
+    /// Attaches a fallback handler function to a `Handler`.
+    ///
+    /// The fallback handler is called for any request whose command type is
+    /// not matched by a call to `handle()`, including command types not known
+    /// to `manticore`, such as vendor-specific commands. It is given the
+    /// header's command byte and the raw, unparsed payload of the request.
+    ///
+    /// If the fallback handler succeeds, the returned bytes are sent as the
+    /// payload of a response with the same command byte as the request;
+    /// otherwise, the error is sent as a [`protocol::Error`].
+    fn fallback<'out, F>(self, handler: F) -> Fallback<Self, F>
+    where
+        F: FnOnce(
+            Server,
+            CommandByte,
+            &'req [u8],
+        ) -> Result<&'out [u8], protocol::Error>,
+        'srv: 'out,
+        'req: 'out,
+    {
+        Fallback {
+            prev: self,
+            handler,
+        }
+    }
+
+    /// Wraps all handlers attached so far in a [`Layer`].
+    ///
+    /// The layer is consulted before any of these handlers is run, and may
+    /// reject the request, in which case the rejection is sent to the host in
+    /// lieu of a response. Handlers attached after this call bypass the layer.
+    fn layer<L: Layer>(self, layer: L) -> Layered<Self, L> {
+        Layered { prev: self, layer }
+    }
+
+    /// Returns whether this handler would handle a request with the given
+    /// command byte, rather than returning [`Error::UnhandledCommand`].
+    #[doc(hidden)]
+    fn handles(&self, command: CommandByte) -> bool;
+
     /// The "real" run function.
     #[doc(hidden)]
     fn run_with_header<A: Arena>(
@@ -254,12 +368,90 @@ pub trait HandlerMethods<'req, 'srv, Server: 'srv>:
         arena: &'req A,
     ) -> Result<(), Error> {
         let request = host_port.receive()?;
+        self.run_with_request(server, request, arena)
+    }
+
+    /// Executes a `Handler` with the given context, on a port that must not
+    /// block.
+    ///
+    /// The returned future completes once a request has been received from
+    /// `host_port`, processed as by `run()`, and the reply flushed.
+    #[inline]
+    fn run_async<'port, A: Arena>(
+        self,
+        server: Server,
+        host_port: &'port mut dyn AsyncHostPort,
+        arena: &'req A,
+    ) -> RunAsync<'port, 'req, 'srv, Self, Server, A> {
+        RunAsync {
+            handler: Some((self, server)),
+            host_port,
+            arena,
+            _ph: PhantomData,
+        }
+    }
+
+    /// Executes a `Handler` with the given context, on a request that has
+    /// already been received from a [`net::HostPort`].
+    ///
+    /// This is useful for servers that need to inspect the request, such as
+    /// to look up per-host state, before processing it.
+    #[inline]
+    fn run_with_request<A: Arena>(
+        self,
+        server: Server,
+        request: &mut dyn net::HostRequest,
+        arena: &'req A,
+    ) -> Result<(), Error> {
         let header = request.header()?;
         if !header.is_request {
             return Err(FromWireError::OutOfRange.into());
         }
         self.run_with_header(server, header, request, arena)
     }
+
+    /// Executes a `Handler` with the given context, replying to the host with
+    /// a [`protocol::Error`] on failure.
+    ///
+    /// This function behaves like `run()`, except that if processing fails
+    /// before a response has begun (for example, because the request failed
+    /// to parse, had trailing bytes, or had no handler), an error response is
+    /// sent to the host, as given by [`Error::to_protocol_error()`]. The
+    /// original error is still returned.
+    ///
+    /// Messages that are not requests are dropped without a reply.
+    #[inline]
+    fn run_with_error_replies<A: Arena>(
+        self,
+        server: Server,
+        host_port: &mut dyn net::HostPort,
+        arena: &'req A,
+    ) -> Result<(), Error> {
+        let request = host_port.receive()?;
+        let header = request.header()?;
+        if !header.is_request {
+            // Replying to a response could start an endless exchange of
+            // errors with a peer that does the same, so drop it instead.
+            return Err(FromWireError::OutOfRange.into());
+        }
+        let result = self.run_with_header(server, header, request, arena);
+
+        let err = match result {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if let Some(proto_err) = err.to_protocol_error() {
+            let header = Header {
+                is_request: false,
+                command: CommandType::Error.into(),
+            };
+
+            let reply = request.reply(header)?;
+            proto_err.to_wire(reply.sink()?)?;
+            reply.finish()?;
+        }
+        Err(err)
+    }
 }
 
 impl<'req, 'srv, 'out, Server, Prev, Command, F>
@@ -275,6 +467,11 @@ where
         ReqOf<'req, Command>,
     ) -> Result<RespOf<'out, Command>, protocol::Error>,
 {
+    #[inline]
+    fn handles(&self, command: CommandByte) -> bool {
+        command == ReqOf::<'req, Command>::TYPE || self.prev.handles(command)
+    }
+
     #[inline]
     fn run_with_header<A: Arena>(
         self,
@@ -289,7 +486,11 @@ where
             return self.prev.run_with_header(server, header, request, arena);
         }
 
-        let msg = FromWire::from_wire(request.payload()?, arena)?;
+        let payload = request.payload()?;
+        let msg = FromWire::from_wire(&mut *payload, arena)?;
+        if payload.remaining_data() != 0 {
+            return Err(Error::ReqTooLong(payload.remaining_data()));
+        }
 
         match (self.handler)(server, msg) {
             Ok(msg) => {
@@ -306,7 +507,68 @@ where
             Err(err) => {
                 let header = Header {
                     is_request: false,
-                    command: CommandType::Error,
+                    command: CommandType::Error.into(),
+                };
+
+                let reply = request.reply(header)?;
+                err.to_wire(reply.sink()?)?;
+                reply.finish()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'req, 'srv, 'out, Server, Prev, F> HandlerMethods<'req, 'srv, Server>
+    for Fallback<Prev, F>
+where
+    Server: 'srv,
+    Prev: HandlerMethods<'req, 'srv, Server>,
+    F: FnOnce(
+        Server,
+        CommandByte,
+        &'req [u8],
+    ) -> Result<&'out [u8], protocol::Error>,
+{
+    #[inline]
+    fn handles(&self, _: CommandByte) -> bool {
+        true
+    }
+
+    #[inline]
+    fn run_with_header<A: Arena>(
+        self,
+        server: Server,
+        header: Header,
+        request: &mut dyn net::HostRequest,
+        arena: &'req A,
+    ) -> Result<(), Error> {
+        if self.prev.handles(header.command) {
+            return self.prev.run_with_header(server, header, request, arena);
+        }
+
+        let payload = request.payload()?;
+        let bytes = arena
+            .alloc_slice::<u8>(payload.remaining_data())
+            .map_err(FromWireError::from)?;
+        payload.read_bytes(bytes).map_err(FromWireError::from)?;
+
+        match (self.handler)(server, header.command, bytes) {
+            Ok(msg) => {
+                let header = Header {
+                    is_request: false,
+                    command: header.command,
+                };
+
+                let reply = request.reply(header)?;
+                reply.sink()?.write_bytes(msg).map_err(ToWireError::from)?;
+                reply.finish()?;
+                Ok(())
+            }
+            Err(err) => {
+                let header = Header {
+                    is_request: false,
+                    command: CommandType::Error.into(),
                 };
 
                 let reply = request.reply(header)?;
@@ -318,9 +580,149 @@ where
     }
 }
 
+impl<'req, 'srv, Server, Prev, L> HandlerMethods<'req, 'srv, Server>
+    for Layered<Prev, L>
+where
+    Server: 'srv,
+    Prev: HandlerMethods<'req, 'srv, Server>,
+    L: Layer,
+{
+    #[inline]
+    fn handles(&self, command: CommandByte) -> bool {
+        self.prev.handles(command)
+    }
+
+    #[inline]
+    fn run_with_header<A: Arena>(
+        mut self,
+        server: Server,
+        header: Header,
+        request: &mut dyn net::HostRequest,
+        arena: &'req A,
+    ) -> Result<(), Error> {
+        let info = RequestInfo {
+            host_id: request.host_id(),
+            command: header.command,
+        };
+        if let Err(err) = self.layer.before(&info) {
+            let header = Header {
+                is_request: false,
+                command: CommandType::Error.into(),
+            };
+
+            let sent = request.reply(header).map_err(Error::from).and_then(
+                |reply| -> Result<(), Error> {
+                    err.to_wire(reply.sink()?)?;
+                    reply.finish()?;
+                    Ok(())
+                },
+            );
+            let (outcome, e) = match sent {
+                Ok(()) => (Outcome::Rejected(err), Error::Rejected(err)),
+                Err(e) => (Outcome::Failed(e), e),
+            };
+            self.layer.after(&info, &outcome);
+            return Err(e);
+        }
+
+        let mut observed = Observe {
+            request,
+            reply: None,
+        };
+        let result =
+            self.prev
+                .run_with_header(server, header, &mut observed, arena);
+        let (outcome, result) = match (result, observed.reply) {
+            (Err(e), _) => (Outcome::Failed(e), Err(e)),
+            (Ok(()), Some(command)) => (Outcome::Replied(command), Ok(())),
+            // A handler that succeeds always replies; if it somehow didn't,
+            // the host heard nothing, so processing failed.
+            (Ok(()), None) => {
+                (Outcome::Failed(Error::NoReply), Err(Error::NoReply))
+            }
+        };
+        self.layer.after(&info, &outcome);
+        result
+    }
+}
+
+/// The future returned by [`HandlerMethods::run_async()`].
+pub struct RunAsync<'port, 'req, 'srv, H, Server, A> {
+    /// The handler and server; these are taken once a request is received.
+    handler: Option<(H, Server)>,
+    host_port: &'port mut dyn AsyncHostPort,
+    arena: &'req A,
+    _ph: PhantomData<fn(&'srv ())>,
+}
+
+// `RunAsync` never pins any of its fields, so it can be moved even once
+// pinned.
+impl<H, Server, A> Unpin for RunAsync<'_, '_, '_, H, Server, A> {}
+
+impl<'req, 'srv, H, Server, A> Future for RunAsync<'_, 'req, 'srv, H, Server, A>
+where
+    Server: 'srv,
+    H: HandlerMethods<'req, 'srv, Server>,
+    A: Arena,
+{
+    type Output = Result<(), Error>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<(), Error>> {
+        let this = &mut *self;
+        if this.handler.is_some() {
+            match this.host_port.poll_receive(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(result) => result?,
+            }
+
+            let (handler, server) = this.handler.take().unwrap();
+            let request = this.host_port.request()?;
+            handler.run_with_request(server, request, this.arena)?;
+        }
+        this.host_port.poll_flush(cx).map_err(Error::from)
+    }
+}
+
+/// A [`net::HostRequest`] that records the command byte of the reply sent to
+/// it, for reporting to a [`Layer`].
+struct Observe<'a> {
+    request: &'a mut dyn net::HostRequest,
+    reply: Option<CommandByte>,
+}
+
+impl net::HostRequest for Observe<'_> {
+    fn header(&self) -> Result<Header, net::Error> {
+        self.request.header()
+    }
+
+    fn host_id(&self) -> u8 {
+        self.request.host_id()
+    }
+
+    fn payload(&mut self) -> Result<&mut dyn crate::io::Read, net::Error> {
+        self.request.payload()
+    }
+
+    fn reply(
+        &mut self,
+        header: Header,
+    ) -> Result<&mut dyn net::HostResponse, net::Error> {
+        self.reply = Some(header.command);
+        self.request.reply(header)
+    }
+}
+
 impl<'req, 'srv, Server: 'srv> HandlerMethods<'req, 'srv, Server>
     for Handler<Server>
 {
+    #[inline]
+    fn handles(&self, _: CommandByte) -> bool {
+        false
+    }
+
     #[inline]
     fn run_with_header<A: Arena>(
         self,
@@ -334,6 +736,8 @@ impl<'req, 'srv, Server: 'srv> HandlerMethods<'req, 'srv, Server>
 }
 
 impl<P, C, F> sealed::Sealed for Cons<P, C, F> {}
+impl<P, F> sealed::Sealed for Fallback<P, F> {}
+impl<P, L> sealed::Sealed for Layered<P, L> {}
 impl<Server> sealed::Sealed for Handler<Server> {}
 
 #[cfg(test)]
@@ -345,6 +749,35 @@ mod test {
     const VERSION1: &[u8; 32] = &[2; 32];
     const VERSION2: &[u8; 32] = &[5; 32];
 
+    /// A vendor-defined command, which `manticore` knows nothing about.
+    enum Vendor {}
+    impl protocol::Command<'_> for Vendor {
+        type Req = VendorRequest;
+        type Resp = protocol::Error;
+    }
+
+    struct VendorRequest(u8);
+    impl protocol::Request<'_> for VendorRequest {
+        const TYPE: CommandByte = CommandByte(0xf5);
+    }
+    impl FromWire<'_> for VendorRequest {
+        fn from_wire<R: crate::io::Read, A: Arena>(
+            mut r: R,
+            _: &A,
+        ) -> Result<Self, FromWireError> {
+            Ok(Self(r.read_le()?))
+        }
+    }
+    impl crate::protocol::wire::ToWire for VendorRequest {
+        fn to_wire<W: crate::io::Write>(
+            &self,
+            mut w: W,
+        ) -> Result<(), ToWireError> {
+            w.write_le(self.0)?;
+            Ok(())
+        }
+    }
+
     fn simulate_request<
         'a,
         C: protocol::Command<'a>,
@@ -403,7 +836,7 @@ mod test {
 
         assert!(matches!(
             resp,
-            Err(Error::UnhandledCommand(CommandType::FirmwareVersion))
+            Err(Error::UnhandledCommand(c)) if c == CommandType::FirmwareVersion
         ));
     }
 
@@ -464,7 +897,7 @@ mod test {
 
         assert!(matches!(
             resp,
-            Err(Error::UnhandledCommand(CommandType::DeviceId))
+            Err(Error::UnhandledCommand(c)) if c == CommandType::DeviceId
         ));
         assert!(!handler_called);
     }
@@ -574,4 +1007,388 @@ mod test {
         let version = resp.unwrap().version;
         assert!(version == VERSION1 || version == VERSION2);
     }
+
+    fn simulate_raw_request<'a, T: 'a, H: HandlerMethods<'a, 'a, T>>(
+        scratch_space: &'a mut [u8],
+        arena: &'a mut BumpArena<'a>,
+        server: (H, T),
+        command: CommandByte,
+        request: &'a [u8],
+    ) -> Result<(Header, Vec<u8>), Error> {
+        let mut port = net::InMemHost::new(scratch_space);
+        port.request(
+            Header {
+                is_request: true,
+                command,
+            },
+            request,
+        );
+
+        server.0.run(server.1, &mut port, arena)?;
+
+        let (header, resp) = port.response().unwrap();
+        assert!(!header.is_request);
+        Ok((header, resp.to_vec()))
+    }
+
+    #[test]
+    fn vendor_handler() {
+        let mut handler_called = false;
+        let handler = Handler::<()>::new()
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                panic!("called the wrong handler")
+            })
+            .handle::<Vendor, _>(|_, req| {
+                handler_called = true;
+                assert_eq!(req.0, 42);
+                Ok(protocol::Error::new_ack())
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let resp = simulate_request::<Vendor, _, _, _>(
+            &mut scratch,
+            &mut arena,
+            (handler, ()),
+            VendorRequest(42),
+        );
+
+        assert!(handler_called);
+        assert_eq!(resp.unwrap(), protocol::Error::new_ack());
+    }
+
+    #[test]
+    fn unknown_command() {
+        let handler = Handler::<()>::new()
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                panic!("called the wrong handler")
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let resp = simulate_raw_request(
+            &mut scratch,
+            &mut arena,
+            (handler, ()),
+            CommandByte(0xc5),
+            &[1, 2, 3],
+        );
+
+        assert!(matches!(
+            resp,
+            Err(Error::UnhandledCommand(CommandByte(0xc5)))
+        ));
+    }
+
+    #[test]
+    fn fallback_handler() {
+        let mut fallback_called = false;
+        let handler = Handler::<&str>::new()
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                panic!("called the wrong handler")
+            })
+            .fallback(|zelf, command, payload| {
+                fallback_called = true;
+                assert_eq!(zelf, "server state");
+                assert_eq!(command, CommandByte(0xc5));
+                assert_eq!(payload, &[1, 2, 3]);
+                Ok(&[4, 5])
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let (header, resp) = simulate_raw_request(
+            &mut scratch,
+            &mut arena,
+            (handler, "server state"),
+            CommandByte(0xc5),
+            &[1, 2, 3],
+        )
+        .unwrap();
+
+        assert!(fallback_called);
+        assert_eq!(header.command, CommandByte(0xc5));
+        assert_eq!(resp, &[4, 5]);
+    }
+
+    #[test]
+    fn fallback_handler_error() {
+        let handler = Handler::<()>::new().fallback(|_, _, _| {
+            Err(protocol::Error {
+                code: protocol::ErrorCode::Unspecified,
+                data: [1, 2, 3, 4],
+            })
+        });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let (header, resp) = simulate_raw_request(
+            &mut scratch,
+            &mut arena,
+            (handler, ()),
+            CommandByte(0xc5),
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(header.command, CommandType::Error);
+        assert_eq!(resp, &[0x04, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn fallback_not_called() {
+        let mut handler_called = false;
+        let handler = Handler::<()>::new()
+            .fallback(|_, _, _| panic!("called the fallback handler"))
+            .handle::<protocol::DeviceId, _>(|_, _| {
+                panic!("called the wrong handler")
+            })
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                handler_called = true;
+
+                Ok(protocol::firmware_version::FirmwareVersionResponse {
+                    version: VERSION1,
+                })
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let req =
+            protocol::firmware_version::FirmwareVersionRequest { index: 42 };
+        let resp = simulate_request::<protocol::FirmwareVersion, _, _, _>(
+            &mut scratch,
+            &mut arena,
+            (handler, ()),
+            req,
+        );
+
+        assert!(handler_called);
+        assert!(resp.unwrap().version.starts_with(VERSION1));
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let handler = Handler::<()>::new()
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                panic!("handler called despite trailing bytes")
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let resp = simulate_raw_request(
+            &mut scratch,
+            &mut arena,
+            (handler, ()),
+            CommandType::FirmwareVersion.into(),
+            &[42, 0, 0],
+        );
+
+        assert!(matches!(resp, Err(Error::ReqTooLong(2))));
+    }
+
+    fn simulate_with_error_replies<'a, T: 'a, H: HandlerMethods<'a, 'a, T>>(
+        scratch_space: &'a mut [u8],
+        arena: &'a mut BumpArena<'a>,
+        server: (H, T),
+        command: CommandByte,
+        request: &'a [u8],
+    ) -> (Result<(), Error>, protocol::Error) {
+        let mut port = net::InMemHost::new(scratch_space);
+        port.request(
+            Header {
+                is_request: true,
+                command,
+            },
+            request,
+        );
+
+        let result =
+            server.0.run_with_error_replies(server.1, &mut port, arena);
+
+        let (header, mut resp) = port.response().unwrap();
+        assert!(!header.is_request);
+        assert_eq!(header.command, CommandType::Error);
+        let err = protocol::Error::from_wire(&mut resp, arena)
+            .expect("failed to read response");
+        assert_eq!(resp.len(), 0);
+        (result, err)
+    }
+
+    #[test]
+    fn error_reply_unhandled() {
+        let handler = Handler::<()>::new()
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                panic!("called the wrong handler")
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let (result, err) = simulate_with_error_replies(
+            &mut scratch,
+            &mut arena,
+            (handler, ()),
+            CommandByte(0xc5),
+            &[],
+        );
+
+        assert!(matches!(
+            result,
+            Err(Error::UnhandledCommand(CommandByte(0xc5)))
+        ));
+        assert_eq!(err.code, protocol::ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn error_reply_parse_failure() {
+        let handler = Handler::<()>::new()
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                panic!("handler called despite parse failure")
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let (result, err) = simulate_with_error_replies(
+            &mut scratch,
+            &mut arena,
+            (handler, ()),
+            CommandType::FirmwareVersion.into(),
+            &[],
+        );
+
+        assert!(matches!(result, Err(Error::FromWireError(_))));
+        assert_eq!(err.code, protocol::ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn error_reply_trailing_bytes() {
+        let handler = Handler::<()>::new()
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                panic!("handler called despite trailing bytes")
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let (result, err) = simulate_with_error_replies(
+            &mut scratch,
+            &mut arena,
+            (handler, ()),
+            CommandType::FirmwareVersion.into(),
+            &[42, 0],
+        );
+
+        assert!(matches!(result, Err(Error::ReqTooLong(1))));
+        assert_eq!(err.code, protocol::ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn error_reply_ignores_responses() {
+        let handler = Handler::<()>::new()
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                panic!("handler called on a response")
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let arena = BumpArena::new(&mut arena);
+        let mut port = net::InMemHost::new(&mut scratch);
+        port.request(
+            Header {
+                is_request: false,
+                command: CommandType::Error.into(),
+            },
+            &[0; 5],
+        );
+
+        let result = handler.run_with_error_replies((), &mut port, &arena);
+        assert!(matches!(result, Err(Error::FromWireError(_))));
+        assert!(port.response().is_none());
+    }
+
+    #[test]
+    fn layered_handlers() {
+        use crate::server::layer::Authorize;
+
+        let handler = Handler::<()>::new()
+            .handle::<protocol::DeviceId, _>(|_, _| {
+                panic!("called a handler rejected by its layer")
+            })
+            .layer(Authorize(|_: &_| false))
+            .handle::<protocol::FirmwareVersion, _>(|_, _| {
+                Ok(protocol::firmware_version::FirmwareVersionResponse {
+                    version: VERSION1,
+                })
+            });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let arena = BumpArena::new(&mut arena);
+        let mut port = net::InMemHost::new(&mut scratch);
+        port.request(
+            Header {
+                is_request: true,
+                command: CommandType::DeviceId.into(),
+            },
+            &[],
+        );
+
+        let result = handler.run((), &mut port, &arena);
+        assert!(matches!(result, Err(Error::Rejected(e))
+            if e.code == protocol::ErrorCode::AuthenticationFailure));
+
+        let (header, resp) = port.response().unwrap();
+        assert_eq!(header.command, CommandType::Error);
+        assert_eq!(resp, &[0xf2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn run_async() {
+        use crate::net::asynch::test::block_on;
+        use crate::net::asynch::test::Slow;
+
+        let mut handler_called = false;
+        let handler = Handler::<&str>::new()
+            .handle::<protocol::FirmwareVersion, _>(|zelf, req| {
+                handler_called = true;
+                assert_eq!(zelf, "server state");
+                assert_eq!(req.index, 42);
+
+                Ok(protocol::firmware_version::FirmwareVersionResponse {
+                    version: VERSION1,
+                })
+            });
+
+        let mut scratch = [0; 1024];
+        let mut port = Slow {
+            host: net::InMemHost::new(&mut scratch),
+            waited: false,
+        };
+        port.host.request(
+            Header {
+                is_request: true,
+                command: CommandType::FirmwareVersion.into(),
+            },
+            &[42],
+        );
+
+        let mut arena = [0; 64];
+        let arena = BumpArena::new(&mut arena);
+        let (result, pending) =
+            block_on(handler.run_async("server state", &mut port, &arena));
+        result.unwrap();
+        assert_eq!(pending, 2);
+        assert!(handler_called);
+
+        let (header, resp) = port.host.response().unwrap();
+        assert_eq!(header.command, CommandType::FirmwareVersion);
+        assert!(resp.starts_with(VERSION1));
+    }
 }
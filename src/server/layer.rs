@@ -0,0 +1,342 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Middleware layers for `manticore` servers.
+//!
+//! A [`Layer`] observes every request a server processes: it is consulted
+//! before a request is dispatched to its handler, and may reject it outright,
+//! and is informed of the [`Outcome`] once processing is done. This makes it
+//! possible to implement cross-cutting concerns, such as logging, rate
+//! limiting, authorization, and metrics, without every command handler having
+//! to implement them.
+//!
+//! Layers compose: a pair `(A, B)` of layers is itself a layer, which consults
+//! `A` before `B` and informs `B` before `A`, and `()` is a layer that does
+//! nothing.
+//!
+//! This module provides a handful of ready-made layers:
+//! - [`Log`], which passes each request and its outcome to a function.
+//! - [`RateLimit`], which limits how often each command may be issued.
+//! - [`Authorize`], which rejects requests that fail a predicate.
+//! - [`Metrics`], which counts requests by outcome.
+//!
+//! None of these require an allocator.
+
+use core::time::Duration;
+
+use crate::hardware;
+use crate::protocol;
+use crate::protocol::CommandByte;
+use crate::protocol::CommandType;
+use crate::protocol::ErrorCode;
+use crate::server::Error;
+
+/// Information about an incoming request, as seen by a [`Layer`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RequestInfo {
+    /// The ID of the host that sent the request; see
+    /// [`net::HostRequest::host_id()`](crate::net::HostRequest::host_id).
+    pub host_id: u8,
+    /// The command byte from the request's header.
+    pub command: CommandByte,
+}
+
+/// The outcome of processing a request, as seen by a [`Layer`].
+#[derive(Copy, Clone, Debug)]
+pub enum Outcome {
+    /// A response was sent to the host with the given command byte.
+    ///
+    /// If the handler returned a [`protocol::Error`], this is
+    /// [`CommandType::Error`].
+    Replied(CommandByte),
+    /// The request was rejected by a layer with the given error, which was
+    /// sent to the host.
+    Rejected(protocol::Error),
+    /// Processing failed with the given error.
+    Failed(Error),
+}
+
+impl Outcome {
+    /// Returns whether the host was sent anything other than a successful
+    /// response.
+    pub fn is_error(&self) -> bool {
+        match self {
+            Outcome::Replied(command) => *command == CommandType::Error,
+            Outcome::Rejected(_) | Outcome::Failed(_) => true,
+        }
+    }
+}
+
+/// A middleware layer around a server's request handlers.
+///
+/// See the module documentation for more information.
+pub trait Layer {
+    /// Called before a request is dispatched to its handler.
+    ///
+    /// Returning an error rejects the request: the handler is not called, and
+    /// the error is sent to the host instead. `after()` is then called with
+    /// [`Outcome::Rejected`] on every layer, including this one and any whose
+    /// `before()` was never reached.
+    fn before(&mut self, req: &RequestInfo) -> Result<(), protocol::Error> {
+        let _ = req;
+        Ok(())
+    }
+
+    /// Called once a request has been processed or rejected.
+    fn after(&mut self, req: &RequestInfo, outcome: &Outcome) {
+        let _ = (req, outcome);
+    }
+}
+
+impl Layer for () {}
+
+impl<L: Layer + ?Sized> Layer for &'_ mut L {
+    fn before(&mut self, req: &RequestInfo) -> Result<(), protocol::Error> {
+        L::before(*self, req)
+    }
+
+    fn after(&mut self, req: &RequestInfo, outcome: &Outcome) {
+        L::after(*self, req, outcome)
+    }
+}
+
+impl<A: Layer, B: Layer> Layer for (A, B) {
+    fn before(&mut self, req: &RequestInfo) -> Result<(), protocol::Error> {
+        self.0.before(req)?;
+        self.1.before(req)
+    }
+
+    fn after(&mut self, req: &RequestInfo, outcome: &Outcome) {
+        self.1.after(req, outcome);
+        self.0.after(req, outcome);
+    }
+}
+
+/// A [`Layer`] that passes every processed request, along with its outcome,
+/// to a function.
+///
+/// This is intended for request logging; the function can format its inputs
+/// to whatever logging facility the integration provides.
+pub struct Log<F>(pub F);
+
+impl<F: FnMut(&RequestInfo, &Outcome)> Layer for Log<F> {
+    fn after(&mut self, req: &RequestInfo, outcome: &Outcome) {
+        (self.0)(req, outcome)
+    }
+}
+
+/// A per-command limit enforced by [`RateLimit`].
+#[derive(Copy, Clone, Debug)]
+pub struct Limit {
+    command: CommandByte,
+    max: u16,
+    window: Duration,
+
+    window_start: Duration,
+    count: u16,
+}
+
+impl Limit {
+    /// Creates a new `Limit`, which allows at most `max` requests with the
+    /// command byte `command` in any period of length `window`.
+    pub fn new(
+        command: impl Into<CommandByte>,
+        max: u16,
+        window: Duration,
+    ) -> Self {
+        Self {
+            command: command.into(),
+            max,
+            window,
+            window_start: Duration::from_secs(0),
+            count: 0,
+        }
+    }
+}
+
+/// A [`Layer`] that limits how often commands may be issued.
+///
+/// Time is measured using [`hardware::Reset::uptime()`]. Requests in excess
+/// of the relevant [`Limit`] are rejected with [`ErrorCode::Busy`]; commands
+/// without a `Limit` are never rejected. Limits are shared by all hosts.
+pub struct RateLimit<'a, Reset> {
+    reset: &'a Reset,
+    limits: &'a mut [Limit],
+}
+
+impl<'a, Reset: hardware::Reset> RateLimit<'a, Reset> {
+    /// Creates a new `RateLimit` which enforces `limits`, using `reset` as a
+    /// clock.
+    pub fn new(reset: &'a Reset, limits: &'a mut [Limit]) -> Self {
+        Self { reset, limits }
+    }
+}
+
+impl<Reset: hardware::Reset> Layer for RateLimit<'_, Reset> {
+    fn before(&mut self, req: &RequestInfo) -> Result<(), protocol::Error> {
+        let limit =
+            match self.limits.iter_mut().find(|l| l.command == req.command) {
+                Some(limit) => limit,
+                None => return Ok(()),
+            };
+
+        let now = self.reset.uptime();
+        if limit.count == 0 || now >= limit.window_start + limit.window {
+            limit.window_start = now;
+            limit.count = 0;
+        }
+        if limit.count >= limit.max {
+            return Err(protocol::Error::new(ErrorCode::Busy));
+        }
+        limit.count += 1;
+        Ok(())
+    }
+}
+
+/// A [`Layer`] that rejects requests for which a predicate returns `false`
+/// with [`ErrorCode::AuthenticationFailure`].
+///
+/// For example, a server might only allow firmware update commands from hosts
+/// that have established an authenticated session.
+pub struct Authorize<F>(pub F);
+
+impl<F: FnMut(&RequestInfo) -> bool> Layer for Authorize<F> {
+    fn before(&mut self, req: &RequestInfo) -> Result<(), protocol::Error> {
+        if (self.0)(req) {
+            Ok(())
+        } else {
+            Err(protocol::Error::new(ErrorCode::AuthenticationFailure))
+        }
+    }
+}
+
+/// A [`Layer`] that counts requests by their outcome.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// The number of requests that were sent a successful response.
+    pub ok: u32,
+    /// The number of requests that were sent a [`protocol::Error`] by their
+    /// handler.
+    pub error_replies: u32,
+    /// The number of requests that were rejected by a layer.
+    pub rejected: u32,
+    /// The number of requests whose processing failed.
+    pub failed: u32,
+}
+
+impl Metrics {
+    /// Creates a new `Metrics`, with all counts set to zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the total number of requests counted.
+    pub fn total(&self) -> u32 {
+        self.ok
+            .wrapping_add(self.error_replies)
+            .wrapping_add(self.rejected)
+            .wrapping_add(self.failed)
+    }
+}
+
+impl Layer for Metrics {
+    fn after(&mut self, _: &RequestInfo, outcome: &Outcome) {
+        let counter = match outcome {
+            Outcome::Replied(c) if *c == CommandType::Error => {
+                &mut self.error_replies
+            }
+            Outcome::Replied(_) => &mut self.ok,
+            Outcome::Rejected(_) => &mut self.rejected,
+            Outcome::Failed(_) => &mut self.failed,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cell::Cell;
+
+    const FW: RequestInfo = RequestInfo {
+        host_id: 1,
+        command: CommandType::FirmwareVersion.to_byte(),
+    };
+    const ID: RequestInfo = RequestInfo {
+        host_id: 2,
+        command: CommandType::DeviceId.to_byte(),
+    };
+
+    struct Clock(Cell<Duration>);
+    impl hardware::Reset for Clock {
+        fn resets_since_power_on(&self) -> u32 {
+            0
+        }
+        fn uptime(&self) -> Duration {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn rate_limit() {
+        let clock = Clock(Cell::new(Duration::from_millis(5)));
+        let mut limits = [Limit::new(
+            CommandType::FirmwareVersion,
+            2,
+            Duration::from_millis(10),
+        )];
+        let mut layer = RateLimit::new(&clock, &mut limits);
+
+        assert!(layer.before(&FW).is_ok());
+        assert!(layer.before(&FW).is_ok());
+        let err = layer.before(&FW).unwrap_err();
+        assert_eq!(err.code, ErrorCode::Busy);
+        for _ in 0..5 {
+            assert!(layer.before(&ID).is_ok());
+        }
+
+        clock.0.set(Duration::from_millis(14));
+        assert!(layer.before(&FW).is_err());
+        clock.0.set(Duration::from_millis(15));
+        assert!(layer.before(&FW).is_ok());
+        assert!(layer.before(&FW).is_ok());
+        assert!(layer.before(&FW).is_err());
+    }
+
+    #[test]
+    fn composition() {
+        let log = Cell::new(None);
+        let mut metrics = Metrics::new();
+        {
+            // Layers after the one that rejects a request still see it.
+            let mut layer = (
+                Authorize(|req: &RequestInfo| req.host_id == 1),
+                (
+                    Log(|req: &RequestInfo, outcome: &Outcome| {
+                        log.set(Some((req.host_id, outcome.is_error())));
+                    }),
+                    &mut metrics,
+                ),
+            );
+
+            assert!(layer.before(&FW).is_ok());
+            layer.after(&FW, &Outcome::Replied(FW.command));
+            assert_eq!(log.get(), Some((1, false)));
+
+            let err = layer.before(&ID).unwrap_err();
+            assert_eq!(err.code, ErrorCode::AuthenticationFailure);
+            layer.after(&ID, &Outcome::Rejected(err));
+        }
+
+        assert_eq!(log.get(), Some((2, true)));
+        assert_eq!(
+            metrics,
+            Metrics {
+                ok: 1,
+                rejected: 1,
+                ..Metrics::new()
+            }
+        );
+    }
+}
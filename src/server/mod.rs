@@ -10,6 +10,7 @@
 //! TODO: description of how to use a server.
 
 mod handler;
+pub mod layer;
 pub use handler::Error;
 
 pub mod pa_rot;
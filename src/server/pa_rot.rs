@@ -8,18 +8,40 @@
 //! requests to a PA-RoT.
 
 use crate::crypto::rsa;
+use crate::crypto::sha256;
+use crate::crypto::sig;
 use crate::hardware;
+use crate::hardware::flash::Flash;
+use crate::io;
+use crate::manifest;
+use crate::manifest::key_set::KeyRing;
+use crate::manifest::key_set::KeySet as _;
+use crate::manifest::key_set::Verified;
+use crate::manifest::Container;
+use crate::manifest::HasSigType;
+use crate::manifest::Manifest;
+use crate::manifest::SigType;
 use crate::mem::Arena;
+use crate::mem::ArenaExt as _;
 use crate::net;
+use crate::net::asynch::AsyncHostPort;
 use crate::protocol;
 use crate::protocol::capabilities;
+use crate::protocol::capabilities::Capabilities;
 use crate::protocol::device_id;
+use crate::protocol::key_set_update::KeySetUpdateRequest;
+use crate::protocol::wire::FromWire;
+use crate::protocol::wire::ToWire;
+use crate::server::layer::Layer;
 use crate::server::Error;
 
 use crate::server::handler::prelude::*;
 
 /// Options struct for initializing a [`PaRot`].
-pub struct Options<'a, Identity, Reset, Rsa> {
+pub struct Options<'a, Identity, Reset, Rsa>
+where
+    Rsa: rsa::Builder<rsa::RsaPkcs1Sha256>,
+{
     /// A handle to the "hardware identity" of the device.
     pub identity: &'a Identity,
     /// A handle for looking up reset-related information for the current
@@ -36,17 +58,73 @@ pub struct Options<'a, Identity, Reset, Rsa> {
     pub networking: capabilities::Networking,
     /// Integration-provided "acceptable timeout" lengths.
     pub timeouts: capabilities::Timeouts,
+
+    /// The keys this device trusts to sign manifests.
+    pub keys: Keys<'a, Rsa::Verify>,
+}
+
+/// The keys a [`PaRot`] trusts to sign manifests, which hosts may update with
+/// [`protocol::KeySetUpdate`] requests.
+pub struct Keys<'a, Verify> {
+    /// The trusted keys.
+    pub ring: KeyRing<'a, Verify>,
+    /// Converts the key in a request that adds a key into a verifier,
+    /// returning `None` if it is malformed or unsupported.
+    pub parse_key: fn(&[u8]) -> Option<Verify>,
+}
+
+impl<Verify> Keys<'_, Verify> {
+    /// Returns a `Keys` that trusts no keys, so that every
+    /// [`protocol::KeySetUpdate`] request is refused.
+    pub fn empty() -> Self {
+        Self {
+            ring: KeyRing::new(&mut []),
+            parse_key: |_| None,
+        }
+    }
 }
 
+/// The maximum number of hosts a [`PaRot`] will remember negotiated
+/// capabilities for.
+///
+/// If more hosts than this negotiate capabilities, the least recently
+/// negotiated host's capabilities are forgotten.
+pub const MAX_HOSTS: usize = 4;
+
 /// A PA-RoT, or "Platform Root of Trust", server.
 ///
 /// This type implements the request -> response "business logic" of the
 /// host <-> PA-RoT interaction. That is, it accepts input and output buffers,
 /// and from those, parses incoming requests and processes them into responses.
-pub struct PaRot<'a, Identity, Reset, Rsa> {
+///
+/// When a host sends a `DeviceCapabilities` request, the PA-RoT replies with
+/// its own capabilities, negotiates capabilities with the host (see
+/// [`Capabilities::negotiate()`]), and remembers the result for that host, as
+/// identified by [`net::HostRequest::host_id()`]. Hosts that advertise a
+/// maximum message size below [`capabilities::MIN_MESSAGE_SIZE`] are
+/// rejected.
+/// Responses to later requests from that host that would not fit in the
+/// negotiated maximum message size are replaced with a
+/// [`protocol::ErrorCode::MessageOverflow`] error, and requests that need
+/// security modes or key strengths the host did not negotiate, or that come
+/// from a host that has not negotiated at all, are refused.
+///
+/// Hosts may add and revoke trusted manifest-signing keys with
+/// [`protocol::KeySetUpdate`] requests; see [`PaRot::verify_manifest()`].
+pub struct PaRot<'a, Identity, Reset, Rsa>
+where
+    Rsa: rsa::Builder<rsa::RsaPkcs1Sha256>,
+{
     opts: Options<'a, Identity, Reset, Rsa>,
     ok_count: u16,
     err_count: u16,
+
+    /// Negotiated capabilities for each host, by host ID.
+    hosts: [Option<(u8, Capabilities)>; MAX_HOSTS],
+    /// The index in `hosts` to evict next.
+    next_host: usize,
+    /// The host ID of the request currently being processed.
+    current_host: u8,
 }
 
 impl<'a, Identity, Reset, Rsa> PaRot<'a, Identity, Reset, Rsa>
@@ -54,6 +132,7 @@ where
     Identity: hardware::Identity,
     Reset: hardware::Reset,
     Rsa: rsa::Builder<rsa::RsaPkcs1Sha256>,
+    Rsa::Verify: HasSigType,
 {
     /// Create a new `PaRot` with the given `Options`.
     pub fn new(opts: Options<'a, Identity, Reset, Rsa>) -> Self {
@@ -61,6 +140,161 @@ where
             opts,
             ok_count: 0,
             err_count: 0,
+            hosts: [None; MAX_HOSTS],
+            next_host: 0,
+            current_host: 0,
+        }
+    }
+
+    /// Returns the capabilities of this device, as it would advertise them
+    /// prior to negotiation.
+    pub fn capabilities(&self) -> Capabilities {
+        use protocol::capabilities::*;
+        let rsa_strength = RsaKeyStrength::from_builder(self.opts.rsa);
+
+        // Hosts can only authenticate to this device by signing requests with
+        // one of its trusted keys.
+        let security = if self.opts.keys.ring.is_empty() {
+            Security::empty()
+        } else {
+            Security::AUTHENTICATION
+        };
+
+        Capabilities {
+            networking: self.opts.networking,
+            security,
+
+            has_pfm_support: false,
+            has_policy_support: false,
+            has_firmware_protection: false,
+
+            has_ecdsa: false,
+            has_ecc: false,
+            has_rsa: !rsa_strength.is_empty(),
+            has_aes: false,
+
+            ecc_strength: EccKeyStrength::empty(),
+            rsa_strength,
+            aes_strength: AesKeyStrength::empty(),
+        }
+    }
+
+    /// Returns the capabilities negotiated with the host with the given ID,
+    /// if it has negotiated capabilities with this device.
+    pub fn host_capabilities(&self, host_id: u8) -> Option<&Capabilities> {
+        self.hosts
+            .iter()
+            .flatten()
+            .find(|(id, _)| *id == host_id)
+            .map(|(_, caps)| caps)
+    }
+
+    /// Parses and verifies a manifest stored in `flash`, using whichever of
+    /// this device's trusted keys signed it.
+    ///
+    /// See [`Container::parse_and_verify_with_keys()`].
+    pub fn verify_manifest<'f, M: Manifest, F: Flash>(
+        &mut self,
+        flash: &'f F,
+        sha: &impl sha256::Builder,
+        toc_arena: &'f impl Arena,
+        verify_arena: &impl Arena,
+    ) -> Result<Container<'f, M, F>, manifest::Error> {
+        Container::parse_and_verify_with_keys(
+            flash,
+            sha,
+            &mut self.opts.keys.ring,
+            toc_arena,
+            verify_arena,
+        )
+    }
+
+    /// Returns the capabilities in effect for the host that sent the current
+    /// request: either the negotiated ones, or this device's own.
+    ///
+    /// This is only suitable for sizing responses; it must not be used to
+    /// decide what a host is allowed to do.
+    fn effective_capabilities(&self) -> Capabilities {
+        self.host_capabilities(self.current_host)
+            .copied()
+            .unwrap_or_else(|| self.capabilities())
+    }
+
+    /// Checks that the current host has negotiated authentication, and
+    /// support for signatures of type `sig_type`, if it is known.
+    ///
+    /// Unlike [`PaRot::fit()`], this does not fall back to this device's own
+    /// capabilities: a host that has not negotiated is refused.
+    fn check_authentication(
+        &self,
+        sig_type: Option<SigType>,
+    ) -> Result<(), protocol::Error> {
+        use protocol::capabilities::*;
+        let caps = match self.host_capabilities(self.current_host) {
+            Some(caps) => caps,
+            None => {
+                return Err(protocol::Error::new(
+                    protocol::ErrorCode::InvalidRequest,
+                ))
+            }
+        };
+        let supported = match sig_type {
+            None => true,
+            Some(SigType::Rsa2048) => {
+                caps.has_rsa
+                    && caps.rsa_strength.contains(RsaKeyStrength::BITS_2048)
+            }
+            Some(SigType::Rsa3072) => {
+                caps.has_rsa
+                    && caps.rsa_strength.contains(RsaKeyStrength::BITS_3072)
+            }
+            Some(SigType::Rsa4096) => {
+                caps.has_rsa
+                    && caps.rsa_strength.contains(RsaKeyStrength::BITS_4096)
+            }
+            Some(SigType::EcdsaP256) => {
+                caps.has_ecdsa
+                    && caps.ecc_strength.contains(EccKeyStrength::BITS_256)
+            }
+            Some(SigType::EcdsaP384) => false,
+        };
+        if !supported || !caps.security.contains(Security::AUTHENTICATION) {
+            return Err(protocol::Error::new(
+                protocol::ErrorCode::InvalidRequest,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Records `caps` as the negotiated capabilities for the current host.
+    fn remember_capabilities(&mut self, caps: Capabilities) {
+        let host = self.current_host;
+        let slot = match self
+            .hosts
+            .iter()
+            .position(|h| matches!(h, Some((id, _)) if *id == host))
+        {
+            Some(idx) => idx,
+            None => {
+                let idx = self.next_host;
+                self.next_host = (self.next_host + 1) % MAX_HOSTS;
+                idx
+            }
+        };
+        self.hosts[slot] = Some((host, caps));
+    }
+
+    /// Checks that `resp` fits in the maximum message size in effect for the
+    /// current host, returning an error to send instead if it does not.
+    fn fit<R: ToWire>(&self, resp: R) -> Result<R, protocol::Error> {
+        let mut len = LenCounter(protocol::HEADER_LEN);
+        let max_len =
+            self.effective_capabilities().networking.max_message_size as usize;
+        match resp.to_wire(&mut len) {
+            Ok(()) if len.0 <= max_len => Ok(resp),
+            _ => {
+                Err(protocol::Error::new(protocol::ErrorCode::MessageOverflow))
+            }
         }
     }
 
@@ -74,17 +308,183 @@ where
         host_port: &mut dyn net::HostPort,
         arena: &'req impl Arena,
     ) -> Result<(), Error> {
-        let result = Handler::<&mut Self>::new()
+        self.process_request_with_layer(host_port, (), arena)
+    }
+
+    /// Process a single incoming request, wrapping all command handlers in
+    /// `layer`.
+    ///
+    /// See [`layer`](crate::server::layer) for more information.
+    pub fn process_request_with_layer<'req>(
+        &mut self,
+        host_port: &mut dyn net::HostPort,
+        layer: impl Layer,
+        arena: &'req impl Arena,
+    ) -> Result<(), Error> {
+        let result = match host_port.receive() {
+            Ok(request) => self.process(request, layer, arena, None),
+            Err(e) => Err(e.into()),
+        };
+        self.count(&result);
+        result
+    }
+
+    /// Process a single incoming request from a port that must not block.
+    ///
+    /// This function behaves like `process_request()`, except that it waits
+    /// for a request to arrive, for any signature it carries to be verified,
+    /// and for its reply to be flushed, without blocking.
+    pub async fn process_request_async<'req>(
+        &mut self,
+        host_port: &mut dyn AsyncHostPort,
+        arena: &'req impl Arena,
+    ) -> Result<(), Error>
+    where
+        Rsa::Verify: sig::AsyncVerify,
+    {
+        self.process_request_async_with_layer(host_port, (), arena)
+            .await
+    }
+
+    /// Process a single incoming request from a port that must not block,
+    /// wrapping all command handlers in `layer`.
+    ///
+    /// Signatures are verified before `layer` is consulted, since this
+    /// requires reading the whole request. The payload of such a request is
+    /// copied into `arena`, so it needs more room than it would with
+    /// `process_request_with_layer()`.
+    ///
+    /// See [`layer`](crate::server::layer) for more information.
+    pub async fn process_request_async_with_layer<'req>(
+        &mut self,
+        host_port: &mut dyn AsyncHostPort,
+        layer: impl Layer,
+        arena: &'req impl Arena,
+    ) -> Result<(), Error>
+    where
+        Rsa::Verify: sig::AsyncVerify,
+    {
+        let request = match host_port.receive().await {
+            Ok(request) => request,
+            Err(e) => {
+                self.err_count += 1;
+                return Err(e.into());
+            }
+        };
+        let mut result = match self.pre_verify(request, arena).await {
+            Ok(Some((payload, verified))) => {
+                let mut request = Buffered { request, payload };
+                self.process(&mut request, layer, arena, verified)
+            }
+            Ok(None) => self.process(request, layer, arena, None),
+            Err(e) => Err(e.into()),
+        };
+
+        // Error replies need to be flushed too, but a request only succeeds
+        // once its reply has actually been sent.
+        let flushed = host_port.flush().await;
+        if result.is_ok() {
+            result = flushed.map_err(Error::from);
+        }
+        self.count(&result);
+        result
+    }
+
+    /// Verifies the signature on `request` ahead of processing it, if it is a
+    /// [`protocol::KeySetUpdate`], so that verification can be awaited.
+    ///
+    /// Doing so consumes the payload, so this returns a copy of it, to be
+    /// processed in place of the original.
+    async fn pre_verify<'req>(
+        &mut self,
+        request: &mut dyn net::HostRequest,
+        arena: &'req impl Arena,
+    ) -> Result<Option<(&'req [u8], Option<PreVerified<'req>>)>, net::Error>
+    where
+        Rsa::Verify: sig::AsyncVerify,
+    {
+        // Anything unusual about the request is left for `process()` to
+        // report.
+        match request.header() {
+            Ok(header)
+                if header.is_request
+                    && header.command
+                        == protocol::CommandType::KeySetUpdate => {}
+            _ => return Ok(None),
+        }
+        let payload = request.payload()?;
+        let buf = match arena.alloc_slice::<u8>(payload.remaining_data()) {
+            Ok(buf) => buf,
+            Err(_) => return Ok(None),
+        };
+        payload.read_bytes(buf)?;
+        let payload = &*buf;
+
+        let update = match KeySetUpdateRequest::from_wire(&mut &*payload, arena)
+        {
+            Ok(update) => update,
+            Err(_) => return Ok(Some((payload, None))),
+        };
+        self.current_host = request.host_id();
+        if self.check_key_set_update(&update).is_err() {
+            return Ok(Some((payload, None)));
+        }
+
+        let result = self.opts.keys.ring.verify_async(&update, arena).await;
+        Ok(Some((payload, Some(PreVerified { update, result }))))
+    }
+
+    /// Records the outcome of a request in the request counters.
+    fn count(&mut self, result: &Result<(), Error>) {
+        match result {
+            Ok(_) => self.ok_count += 1,
+            Err(_) => self.err_count += 1,
+        }
+    }
+
+    /// Checks that the current host may send `update`, before its signature
+    /// is verified.
+    fn check_key_set_update(
+        &mut self,
+        update: &KeySetUpdateRequest,
+    ) -> Result<(), protocol::Error> {
+        // Unknown signers are refused by `KeyRing::apply()`.
+        let sig_type = self
+            .opts
+            .keys
+            .ring
+            .verifier(update.signer_id)
+            .ok()
+            .map(|v| v.sig_type());
+        self.check_authentication(sig_type)
+    }
+
+    /// Process a request that has already been received.
+    ///
+    /// If the request's signature has already been checked, `verified` holds
+    /// the result.
+    fn process<'req>(
+        &mut self,
+        request: &mut dyn net::HostRequest,
+        layer: impl Layer,
+        arena: &'req impl Arena,
+        verified: Option<PreVerified<'req>>,
+    ) -> Result<(), Error> {
+        self.current_host = request.host_id();
+
+        Handler::<&mut Self>::new()
             .handle::<protocol::FirmwareVersion, _>(|zelf, req| {
                 use protocol::firmware_version::FirmwareVersionResponse;
                 if req.index == 0 {
-                    return Ok(FirmwareVersionResponse {
+                    return zelf.fit(FirmwareVersionResponse {
                         version: zelf.opts.identity.firmware_version(),
                     });
                 }
 
                 match zelf.opts.identity.vendor_firmware_version(req.index) {
-                    Some(version) => Ok(FirmwareVersionResponse { version }),
+                    Some(version) => {
+                        zelf.fit(FirmwareVersionResponse { version })
+                    }
                     None => Err(protocol::Error {
                         code: protocol::ErrorCode::Unspecified,
                         data: [0; 4],
@@ -92,44 +492,31 @@ where
                 }
             })
             .handle::<protocol::DeviceCapabilities, _>(|zelf, req| {
-                use protocol::capabilities::*;
-                // For now, we drop the client's capabilities on the ground.
-                // Eventually, these should be used for negotiation of crypto
-                // use.
-                let _ = req.capabilities;
-
-                let rsa_strength = RsaKeyStrength::from_builder(zelf.opts.rsa);
-
-                let capabilities = Capabilities {
-                    networking: zelf.opts.networking,
-                    security: Security::empty(),
-
-                    has_pfm_support: false,
-                    has_policy_support: false,
-                    has_firmware_protection: false,
-
-                    has_ecdsa: false,
-                    has_ecc: false,
-                    has_rsa: !rsa_strength.is_empty(),
-                    has_aes: false,
+                let max_message_size =
+                    req.capabilities.networking.max_message_size;
+                if max_message_size < capabilities::MIN_MESSAGE_SIZE {
+                    return Err(protocol::Error::new(
+                        protocol::ErrorCode::InvalidRequest,
+                    ));
+                }
 
-                    ecc_strength: EccKeyStrength::empty(),
-                    rsa_strength,
-                    aes_strength: AesKeyStrength::empty(),
-                };
+                let capabilities = zelf.capabilities();
+                zelf.remember_capabilities(
+                    capabilities.negotiate(&req.capabilities),
+                );
 
-                Ok(protocol::capabilities::DeviceCapabilitiesResponse {
+                zelf.fit(protocol::capabilities::DeviceCapabilitiesResponse {
                     capabilities,
                     timeouts: zelf.opts.timeouts,
                 })
             })
             .handle::<protocol::DeviceId, _>(|zelf, _| {
-                Ok(protocol::device_id::DeviceIdResponse {
+                zelf.fit(protocol::device_id::DeviceIdResponse {
                     id: zelf.opts.device_id,
                 })
             })
             .handle::<protocol::DeviceInfo, _>(|zelf, _| {
-                Ok(protocol::device_info::DeviceInfoResponse {
+                zelf.fit(protocol::device_info::DeviceInfoResponse {
                     info: zelf.opts.identity.unique_device_identity(),
                 })
             })
@@ -144,7 +531,7 @@ where
                     });
                 }
 
-                Ok(ResetCounterResponse {
+                zelf.fit(ResetCounterResponse {
                     count: zelf.opts.reset.resets_since_power_on() as u16,
                 })
             })
@@ -157,24 +544,50 @@ where
                         data: [0; 4],
                     });
                 }
-                Ok(DeviceUptimeResponse {
+                zelf.fit(DeviceUptimeResponse {
                     uptime: zelf.opts.reset.uptime(),
                 })
             })
             .handle::<protocol::RequestCounter, _>(|zelf, _| {
                 use protocol::request_counter::*;
-                Ok(RequestCounterResponse {
+                zelf.fit(RequestCounterResponse {
                     ok_count: zelf.ok_count,
                     err_count: zelf.err_count,
                 })
             })
-            .run(self, host_port, arena);
+            .handle::<protocol::KeySetUpdate, _>(|zelf, req| {
+                use protocol::ErrorCode;
+                zelf.check_key_set_update(&req)?;
 
-        match result {
-            Ok(_) => self.ok_count += 1,
-            Err(_) => self.err_count += 1,
-        }
-        result
+                let keys = &mut zelf.opts.keys;
+                let result = match verified {
+                    Some(verified) if verified.update == req => {
+                        verified.result.and_then(|v| {
+                            keys.ring.apply_verified(v, keys.parse_key)
+                        })
+                    }
+                    _ => keys.ring.apply(&req, arena, keys.parse_key),
+                };
+                let code = match result {
+                    Ok(()) => ErrorCode::Ok,
+                    Err(manifest::Error::SignatureFailure)
+                    | Err(manifest::Error::UnknownKey(_))
+                    | Err(manifest::Error::RevokedKey(_)) => {
+                        ErrorCode::AuthenticationFailure
+                    }
+                    Err(manifest::Error::DuplicateKey(_))
+                    | Err(manifest::Error::OutOfRange) => {
+                        ErrorCode::InvalidRequest
+                    }
+                    Err(_) => ErrorCode::Unspecified,
+                };
+                if code != ErrorCode::Ok {
+                    return Err(protocol::Error::new(code));
+                }
+                zelf.fit(protocol::Error::new(code))
+            })
+            .layer(layer)
+            .run_with_request(self, request, arena)
     }
 
     /// Start and process a outgoing request.
@@ -191,8 +604,52 @@ where
     }
 }
 
+/// A `Write` that merely counts the bytes written to it.
+struct LenCounter(usize);
+
+impl io::Write for LenCounter {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), io::Error> {
+        self.0 += buf.len();
+        Ok(())
+    }
+}
+
+/// A [`protocol::KeySetUpdate`] request whose signature was checked before
+/// it was processed.
+struct PreVerified<'req> {
+    update: KeySetUpdateRequest<'req>,
+    result: Result<Verified<'req>, manifest::Error>,
+}
+
+/// A [`net::HostRequest`] whose payload has already been read into memory.
+struct Buffered<'a, 'req> {
+    request: &'a mut dyn net::HostRequest,
+    payload: &'req [u8],
+}
+
+impl net::HostRequest for Buffered<'_, '_> {
+    fn header(&self) -> Result<net::Header, net::Error> {
+        self.request.header()
+    }
+
+    fn host_id(&self) -> u8 {
+        self.request.host_id()
+    }
+
+    fn payload(&mut self) -> Result<&mut dyn io::Read, net::Error> {
+        Ok(&mut self.payload)
+    }
+
+    fn reply(
+        &mut self,
+        header: net::Header,
+    ) -> Result<&mut dyn net::HostResponse, net::Error> {
+        self.request.reply(header)
+    }
+}
+
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
     use core::time::Duration;
 
@@ -207,19 +664,19 @@ mod test {
     use crate::protocol::wire::ToWire;
     use crate::protocol::Header;
 
-    const NETWORKING: Networking = Networking {
+    pub(crate) const NETWORKING: Networking = Networking {
         max_message_size: 1024,
         max_packet_size: 256,
         mode: RotMode::Platform,
         roles: BusRole::HOST,
     };
 
-    const TIMEOUTS: Timeouts = Timeouts {
+    pub(crate) const TIMEOUTS: Timeouts = Timeouts {
         regular: Duration::from_millis(30),
         crypto: Duration::from_millis(200),
     };
 
-    const DEVICE_ID: device_id::DeviceIdentifier =
+    pub(crate) const DEVICE_ID: device_id::DeviceIdentifier =
         device_id::DeviceIdentifier {
             vendor_id: 1,
             device_id: 2,
@@ -227,11 +684,39 @@ mod test {
             subsys_id: 4,
         };
 
+    /// Returns the options for a test server, with the fake hardware handles
+    /// given and no trusted keys.
+    pub(crate) fn options<'a>(
+        identity: &'a fake::Identity,
+        reset: &'a fake::Reset,
+        rsa: &'a ring::rsa::Builder,
+    ) -> Options<'a, fake::Identity, fake::Reset, ring::rsa::Builder> {
+        Options {
+            identity,
+            reset,
+            rsa,
+            device_id: DEVICE_ID,
+            networking: NETWORKING,
+            timeouts: TIMEOUTS,
+            keys: Keys::empty(),
+        }
+    }
+
     fn simulate_request<'a, C: protocol::Command<'a>, A: Arena>(
         scratch_space: &'a mut [u8],
         arena: &'a mut A,
         server: &mut PaRot<fake::Identity, fake::Reset, ring::rsa::Builder>,
         request: C::Req,
+    ) -> Result<Result<C::Resp, protocol::Error>, Error> {
+        simulate_request_from::<C, A>(0, scratch_space, arena, server, request)
+    }
+
+    fn simulate_request_from<'a, C: protocol::Command<'a>, A: Arena>(
+        host_id: u8,
+        scratch_space: &'a mut [u8],
+        arena: &'a mut A,
+        server: &mut PaRot<fake::Identity, fake::Reset, ring::rsa::Builder>,
+        request: C::Req,
     ) -> Result<Result<C::Resp, protocol::Error>, Error> {
         use crate::protocol::Response;
 
@@ -244,7 +729,8 @@ mod test {
         let request_bytes = cursor.take_consumed_bytes();
 
         let mut host_port = net::InMemHost::new(port_scratch);
-        host_port.request(
+        host_port.request_from(
+            host_id,
             Header {
                 is_request: true,
                 command: <C::Req as protocol::Request<'a>>::TYPE,
@@ -327,14 +813,7 @@ mod test {
         );
         let reset = fake::Reset::new(0, Duration::from_millis(1));
         let rsa = ring::rsa::Builder::new();
-        let mut server = PaRot::new(Options {
-            identity: &identity,
-            reset: &reset,
-            rsa: &rsa,
-            device_id: DEVICE_ID,
-            networking: NETWORKING,
-            timeouts: TIMEOUTS,
-        });
+        let mut server = PaRot::new(options(&identity, &reset, &rsa));
 
         let mut scratch = [0; 1024];
         let mut arena = [0; 64];
@@ -404,4 +883,439 @@ mod test {
         .expect("got error from client");
         assert_eq!(resp.id, DEVICE_ID);
     }
+
+    #[test]
+    fn negotiation() {
+        let identity = fake::Identity::new(b"test version", &[], b"bits");
+        let reset = fake::Reset::new(0, Duration::from_millis(1));
+        let rsa = ring::rsa::Builder::new();
+        let mut server = PaRot::new(options(&identity, &reset, &rsa));
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+
+        let client = Capabilities {
+            networking: Networking {
+                max_message_size: 20,
+                max_packet_size: 20,
+                mode: RotMode::Platform,
+                roles: BusRole::HOST,
+            },
+            rsa_strength: RsaKeyStrength::BITS_2048,
+            ..server.capabilities()
+        };
+        let req = protocol::capabilities::DeviceCapabilitiesRequest {
+            capabilities: client,
+        };
+        let resp = simulate_request_from::<protocol::DeviceCapabilities, _>(
+            5,
+            &mut scratch,
+            &mut arena,
+            &mut server,
+            req,
+        )
+        .expect("got error from server")
+        .expect("got error message from server");
+        assert_eq!(resp.capabilities, server.capabilities());
+        let negotiated = server.host_capabilities(5).unwrap();
+        assert_eq!(negotiated.networking.max_message_size, 20);
+        assert_eq!(negotiated.rsa_strength, RsaKeyStrength::BITS_2048);
+        assert_eq!(server.host_capabilities(0), None);
+
+        arena.reset();
+
+        // The firmware version response does not fit in twenty bytes.
+        let req =
+            protocol::firmware_version::FirmwareVersionRequest { index: 0 };
+        let resp = simulate_request_from::<protocol::FirmwareVersion, _>(
+            5,
+            &mut scratch,
+            &mut arena,
+            &mut server,
+            req,
+        )
+        .expect("got error from server")
+        .expect_err("got non-error message from server");
+        assert_eq!(resp.code, protocol::ErrorCode::MessageOverflow);
+
+        arena.reset();
+
+        // Other hosts are unaffected.
+        let req =
+            protocol::firmware_version::FirmwareVersionRequest { index: 0 };
+        let resp = simulate_request_from::<protocol::FirmwareVersion, _>(
+            6,
+            &mut scratch,
+            &mut arena,
+            &mut server,
+            req,
+        )
+        .expect("got error from server")
+        .expect("got error message from server");
+        assert_eq!(resp.version, identity.firmware_version());
+
+        arena.reset();
+
+        // A host that could not receive even an error is turned away.
+        let req = protocol::capabilities::DeviceCapabilitiesRequest {
+            capabilities: Capabilities {
+                networking: Networking {
+                    max_message_size: 0,
+                    ..client.networking
+                },
+                ..client
+            },
+        };
+        let resp = simulate_request_from::<protocol::DeviceCapabilities, _>(
+            7,
+            &mut scratch,
+            &mut arena,
+            &mut server,
+            req,
+        )
+        .expect("got error from server")
+        .expect_err("got non-error message from server");
+        assert_eq!(resp.code, protocol::ErrorCode::InvalidRequest);
+        assert_eq!(server.host_capabilities(7), None);
+    }
+
+    #[test]
+    fn layers() {
+        use crate::server::layer::Authorize;
+        use crate::server::layer::Metrics;
+        use crate::server::layer::RequestInfo;
+
+        let identity = fake::Identity::new(b"test version", &[], b"bits");
+        let reset = fake::Reset::new(0, Duration::from_millis(1));
+        let rsa = ring::rsa::Builder::new();
+        let mut server = PaRot::new(options(&identity, &reset, &rsa));
+
+        let mut metrics = Metrics::new();
+        let mut arena = [0; 64];
+        let arena = BumpArena::new(&mut arena);
+        let requests: &[(u8, protocol::CommandType, &[u8])] = &[
+            (1, protocol::CommandType::DeviceId, &[]),
+            (2, protocol::CommandType::DeviceId, &[]),
+            (1, protocol::CommandType::FirmwareVersion, &[0]),
+            // Trailing bytes cause processing to fail.
+            (1, protocol::CommandType::DeviceId, &[0]),
+        ];
+        for &(host_id, command, payload) in requests {
+            let mut scratch = [0; 256];
+            let mut host_port = net::InMemHost::new(&mut scratch);
+            host_port.request_from(
+                host_id,
+                Header {
+                    is_request: true,
+                    command: command.into(),
+                },
+                payload,
+            );
+
+            // Metrics placed after the authorizing layer still count the
+            // requests it rejects.
+            let layer = (
+                Authorize(|req: &RequestInfo| req.host_id == 1),
+                &mut metrics,
+            );
+            let _ = server.process_request_with_layer(
+                &mut host_port,
+                layer,
+                &arena,
+            );
+        }
+
+        assert_eq!(
+            metrics,
+            Metrics {
+                ok: 2,
+                rejected: 1,
+                failed: 1,
+                ..Metrics::new()
+            }
+        );
+        // Rejected requests are not counted as successes.
+        assert_eq!((server.ok_count, server.err_count), (2, 2));
+    }
+
+    #[test]
+    fn key_set_update() {
+        use crate::crypto::testdata;
+        use crate::hardware::flash::Ram;
+        use crate::manifest::owned;
+        use crate::manifest::pfm::Pfm;
+        use crate::mem::OutOfMemory;
+        use crate::protocol::key_set_update::*;
+
+        let identity = fake::Identity::new(b"test version", &[], b"bits");
+        let reset = fake::Reset::new(0, Duration::from_millis(1));
+        let rsa = ring::rsa::Builder::new();
+        let (verifier, _) = testdata::rsa();
+        let mut keys = [Some((1, verifier)), None];
+        let mut server = PaRot::new(Options {
+            keys: Keys {
+                ring: KeyRing::new(&mut keys),
+                parse_key: testdata::parse_rsa_key,
+            },
+            ..options(&identity, &reset, &rsa)
+        });
+
+        // Sends a `KeySetUpdate` signed by the test key from `host_id`,
+        // returning the error code the server replies with.
+        let update =
+            |server: &mut PaRot<_, _, _>, host_id, op, key_id, signer_id| {
+                let bytes =
+                    testdata::signed_key_set_update(op, key_id, signer_id);
+                let mut buf = [0; 1024];
+                let buf = BumpArena::new(&mut buf);
+                let req = KeySetUpdateRequest::from_wire(&mut &bytes[..], &buf)
+                    .unwrap();
+
+                let mut scratch = [0; 2048];
+                let mut arena = [0; 1024];
+                let mut arena = BumpArena::new(&mut arena);
+                let resp = simulate_request_from::<KeySetUpdate, _>(
+                    host_id,
+                    &mut scratch,
+                    &mut arena,
+                    server,
+                    req,
+                )
+                .unwrap();
+                resp.unwrap_err().code
+            };
+
+        // Hosts that have not negotiated, or that negotiated away
+        // authentication or the strength of the signing key, may not update
+        // keys.
+        assert_eq!(
+            update(&mut server, 0, KeyOp::Revoke, 1, 1),
+            protocol::ErrorCode::InvalidRequest
+        );
+        assert_eq!(server.capabilities().security, Security::AUTHENTICATION);
+        let negotiated = [
+            (0, Security::AUTHENTICATION, RsaKeyStrength::BITS_2048),
+            (1, Security::empty(), RsaKeyStrength::BITS_2048),
+            (2, Security::AUTHENTICATION, RsaKeyStrength::BITS_4096),
+        ];
+        for &(host_id, security, rsa_strength) in &negotiated {
+            let mut scratch = [0; 1024];
+            let mut arena = [0; 64];
+            let mut arena = BumpArena::new(&mut arena);
+            let req = protocol::capabilities::DeviceCapabilitiesRequest {
+                capabilities: Capabilities {
+                    security,
+                    rsa_strength,
+                    ..server.capabilities()
+                },
+            };
+            simulate_request_from::<protocol::DeviceCapabilities, _>(
+                host_id,
+                &mut scratch,
+                &mut arena,
+                &mut server,
+                req,
+            )
+            .unwrap()
+            .unwrap();
+        }
+        for &host_id in &[1, 2] {
+            assert_eq!(
+                update(&mut server, host_id, KeyOp::Revoke, 1, 1),
+                protocol::ErrorCode::InvalidRequest
+            );
+        }
+
+        // Trust the test key under ID 2 as well, then retire ID 1.
+        assert_eq!(
+            update(&mut server, 0, KeyOp::Add, 2, 1),
+            protocol::ErrorCode::Ok
+        );
+        assert_eq!(
+            update(&mut server, 0, KeyOp::Revoke, 1, 2),
+            protocol::ErrorCode::Ok
+        );
+        assert_eq!(
+            update(&mut server, 0, KeyOp::Revoke, 2, 1),
+            protocol::ErrorCode::AuthenticationFailure
+        );
+        assert_eq!(
+            update(&mut server, 0, KeyOp::Add, 2, 2),
+            protocol::ErrorCode::InvalidRequest
+        );
+
+        // Manifests signed with the new key are trusted; ones signed with
+        // the revoked key are not.
+        let signed_pfm = |key_id| {
+            let sha = ring::sha256::Builder::new();
+            let (_, mut signer) = testdata::rsa();
+            let mut pfm: owned::Pfm =
+                serde_json::from_str(r#"{ "version_id": 42, "elements": [] }"#)
+                    .unwrap();
+            pfm.metadata.key_id = key_id;
+            Ram(pfm.sign(0x0, &sha, &mut signer).unwrap())
+        };
+        let sha = ring::sha256::Builder::new();
+        let flash = signed_pfm(2);
+        let manifest: Result<Container<'_, Pfm, _>, _> =
+            server.verify_manifest(&flash, &sha, &OutOfMemory, &OutOfMemory);
+        assert_eq!(manifest.unwrap().metadata().key_id, 2);
+        let flash = signed_pfm(1);
+        let manifest: Result<Container<'_, Pfm, _>, _> =
+            server.verify_manifest(&flash, &sha, &OutOfMemory, &OutOfMemory);
+        assert!(matches!(manifest, Err(manifest::Error::RevokedKey(1))));
+    }
+
+    #[test]
+    fn process_request_async() {
+        use crate::net::asynch::test::block_on;
+        use crate::net::asynch::test::Slow;
+
+        let identity = fake::Identity::new(b"test version", &[], b"bits");
+        let reset = fake::Reset::new(0, Duration::from_millis(1));
+        let rsa = ring::rsa::Builder::new();
+        let mut server = PaRot::new(options(&identity, &reset, &rsa));
+
+        let mut scratch = [0; 256];
+        let mut port = Slow {
+            host: net::InMemHost::new(&mut scratch),
+            waited: false,
+        };
+        port.host.request(
+            Header {
+                is_request: true,
+                command: protocol::CommandType::DeviceId.into(),
+            },
+            &[],
+        );
+
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let (result, _) =
+            block_on(server.process_request_async(&mut port, &arena));
+        result.unwrap();
+
+        let (header, mut resp) = port.host.response().unwrap();
+        assert_eq!(header.command, protocol::CommandType::DeviceId);
+        arena.reset();
+        let resp = device_id::DeviceIdResponse::from_wire(&mut resp, &arena)
+            .expect("failed to read response");
+        assert_eq!(resp.id, DEVICE_ID);
+    }
+
+    #[test]
+    fn process_request_async_key_set_update() {
+        use crate::crypto::testdata;
+        use crate::net::asynch::test::block_on;
+        use crate::net::asynch::test::Slow;
+        use crate::protocol::key_set_update::KeyOp;
+        use crate::server::layer::Metrics;
+        use core::task::Context;
+        use core::task::Poll;
+
+        let identity = fake::Identity::new(b"test version", &[], b"bits");
+        let reset = fake::Reset::new(0, Duration::from_millis(1));
+        let rsa = ring::rsa::Builder::new();
+        let (verifier, _) = testdata::rsa();
+        let mut keys = [Some((1, verifier)), None];
+        let mut server = PaRot::new(Options {
+            keys: Keys {
+                ring: KeyRing::new(&mut keys),
+                parse_key: testdata::parse_rsa_key,
+            },
+            ..options(&identity, &reset, &rsa)
+        });
+
+        let mut scratch = [0; 1024];
+        let mut arena = [0; 64];
+        let mut arena = BumpArena::new(&mut arena);
+        let req = DeviceCapabilitiesRequest {
+            capabilities: server.capabilities(),
+        };
+        simulate_request::<protocol::DeviceCapabilities, _>(
+            &mut scratch,
+            &mut arena,
+            &mut server,
+            req,
+        )
+        .unwrap()
+        .unwrap();
+
+        let bytes = testdata::signed_key_set_update(KeyOp::Add, 2, 1);
+        let mut scratch = [0; 256];
+        let mut port = Slow {
+            host: net::InMemHost::new(&mut scratch),
+            waited: false,
+        };
+        port.host.request(
+            Header {
+                is_request: true,
+                command: protocol::CommandType::KeySetUpdate.into(),
+            },
+            &bytes,
+        );
+
+        let mut metrics = Metrics::new();
+        let mut arena = [0; 4096];
+        let mut arena = BumpArena::new(&mut arena);
+        let (result, _) = block_on(server.process_request_async_with_layer(
+            &mut port,
+            &mut metrics,
+            &arena,
+        ));
+        result.unwrap();
+        assert_eq!(metrics.total(), 1);
+
+        let (header, mut resp) = port.host.response().unwrap();
+        assert_eq!(header.command, protocol::CommandType::Error);
+        arena.reset();
+        let resp = protocol::Error::from_wire(&mut resp, &arena)
+            .expect("failed to read response");
+        assert_eq!(resp.code, protocol::ErrorCode::Ok);
+        assert!(server.opts.keys.ring.verifier(2).is_ok());
+        assert_eq!((server.ok_count, server.err_count), (2, 0));
+
+        // A request whose reply cannot be flushed was not successful.
+        struct Unflushable<'a>(net::InMemHost<'a>);
+        impl AsyncHostPort for Unflushable<'_> {
+            fn poll_receive(
+                &mut self,
+                cx: &mut Context,
+            ) -> Poll<Result<(), net::Error>> {
+                self.0.poll_receive(cx)
+            }
+
+            fn request(
+                &mut self,
+            ) -> Result<&mut dyn net::HostRequest, net::Error> {
+                AsyncHostPort::request(&mut self.0)
+            }
+
+            fn poll_flush(
+                &mut self,
+                _: &mut Context,
+            ) -> Poll<Result<(), net::Error>> {
+                Poll::Ready(Err(net::Error::Disconnected))
+            }
+        }
+
+        let mut scratch = [0; 256];
+        let mut port = Unflushable(net::InMemHost::new(&mut scratch));
+        port.0.request(
+            Header {
+                is_request: true,
+                command: protocol::CommandType::DeviceId.into(),
+            },
+            &[],
+        );
+        arena.reset();
+        let (result, _) =
+            block_on(server.process_request_async(&mut port, &arena));
+        assert!(matches!(
+            result,
+            Err(Error::Network(net::Error::Disconnected))
+        ));
+        assert_eq!((server.ok_count, server.err_count), (2, 1));
+    }
 }
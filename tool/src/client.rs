@@ -0,0 +1,504 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A Cerberus client, which speaks to a device over a socket.
+//!
+//! Requests are sent and responses received using the frame format from
+//! [`manticore::net::socket`], so this client can speak to a device simulated
+//! by the `serve` subcommand.
+
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use structopt::StructOpt;
+
+use manticore::io::write::StdWrite;
+use manticore::mem::OutOfMemory;
+use manticore::net;
+use manticore::net::socket;
+use manticore::protocol::capabilities::AesKeyStrength;
+use manticore::protocol::capabilities::BusRole;
+use manticore::protocol::capabilities::Capabilities;
+use manticore::protocol::capabilities::DeviceCapabilitiesRequest;
+use manticore::protocol::capabilities::EccKeyStrength;
+use manticore::protocol::capabilities::Networking;
+use manticore::protocol::capabilities::RotMode;
+use manticore::protocol::capabilities::RsaKeyStrength;
+use manticore::protocol::capabilities::Security;
+use manticore::protocol::device_id::DeviceIdRequest;
+use manticore::protocol::device_info::DeviceInfoRequest;
+use manticore::protocol::device_info::InfoIndex;
+use manticore::protocol::device_uptime::DeviceUptimeRequest;
+use manticore::protocol::firmware_version::FirmwareVersionRequest;
+use manticore::protocol::request_counter::RequestCounterRequest;
+use manticore::protocol::reset_counter::ResetCounterRequest;
+use manticore::protocol::reset_counter::ResetType;
+use manticore::protocol::wire::FromWire;
+use manticore::protocol::wire::ToWire;
+use manticore::protocol::wire::WireEnum;
+use manticore::protocol::CommandByte;
+use manticore::protocol::CommandType;
+use manticore::protocol::Header;
+use manticore::protocol::Request;
+
+/// The request to send to a device.
+///
+/// Exactly one of these options must be given.
+#[derive(Debug, StructOpt)]
+#[structopt(group = structopt::clap::ArgGroup::with_name("request").required(true))]
+pub struct Requests {
+    /// Send a request of this command type, given as JSON.
+    #[structopt(short = "t", long, group = "request")]
+    cmd_type: Option<CommandByte>,
+
+    /// JSON file containing the request for `--cmd-type`; defaults to stdin.
+    #[structopt(short = "i", long, parse(from_os_str), requires = "cmd-type")]
+    input: Option<PathBuf>,
+
+    /// Request the firmware version in the given slot.
+    #[structopt(long, group = "request")]
+    firmware_version: Option<u8>,
+
+    /// Negotiate capabilities with the device.
+    #[structopt(long, group = "request")]
+    capabilities: bool,
+
+    /// Request the device's identifier.
+    #[structopt(long, group = "request")]
+    device_id: bool,
+
+    /// Request the given kind of device information.
+    #[structopt(long, group = "request")]
+    device_info: Option<InfoIndex>,
+
+    /// Request the uptime of the device on the given port.
+    #[structopt(long, group = "request")]
+    device_uptime: Option<u8>,
+
+    /// Request the given kind of reset counter, for the port given by
+    /// `--port-id`.
+    #[structopt(long, group = "request")]
+    reset_counter: Option<ResetType>,
+
+    /// The port to request a reset counter for.
+    #[structopt(long, default_value = "0")]
+    port_id: u8,
+
+    /// Request the device's request counters.
+    #[structopt(long, group = "request")]
+    request_counter: bool,
+
+    /// Query everything that can be learned about the device: its
+    /// capabilities, identifier, information and firmware versions.
+    #[structopt(long, group = "request")]
+    probe: bool,
+
+    /// Read requests from stdin, one JSON object per line, and send each
+    /// over the same connection.
+    ///
+    /// Each line has the form
+    /// `{"cmd_type": "FirmwareVersion", "request": {"index": 0}}`; `request`
+    /// may be omitted for requests without fields.
+    #[structopt(long, group = "request")]
+    interactive: bool,
+}
+
+/// A request read from a line of input in interactive mode.
+#[derive(Deserialize)]
+struct Line {
+    /// The command type of the request.
+    cmd_type: String,
+    /// The request itself, in the same form as for `--cmd-type`.
+    #[serde(default)]
+    request: Value,
+}
+
+/// A device endpoint to connect to.
+pub enum Address<'a> {
+    /// A TCP address, such as `localhost:1234`.
+    Tcp(&'a str),
+    /// A path to a Unix domain socket.
+    Unix(&'a Path),
+}
+
+/// A connected byte stream.
+trait Stream: Read + Write {}
+impl<S: Read + Write> Stream for S {}
+
+/// Connects to the device at `addr`.
+fn connect(addr: Address) -> io::Result<Box<dyn Stream>> {
+    match addr {
+        Address::Tcp(addr) => {
+            // Frames are written in pieces, which would otherwise be held
+            // back waiting for acknowledgements, slowing down every request.
+            let stream = TcpStream::connect(addr)?;
+            stream.set_nodelay(true)?;
+            Ok(Box::new(stream))
+        }
+        #[cfg(unix)]
+        Address::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+        #[cfg(not(unix))]
+        Address::Unix(_) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Unix sockets are not supported on this platform",
+        )),
+    }
+}
+
+/// The capabilities this client advertises when negotiating with a device.
+fn host_capabilities() -> Capabilities {
+    Capabilities {
+        networking: Networking {
+            max_message_size: socket::MAX_FRAME_LEN as u16,
+            max_packet_size: socket::MAX_FRAME_LEN as u16,
+            mode: RotMode::Platform,
+            roles: BusRole::HOST,
+        },
+        security: Security::all(),
+
+        has_pfm_support: true,
+        has_policy_support: true,
+        has_firmware_protection: true,
+
+        has_ecdsa: true,
+        has_ecc: true,
+        has_rsa: true,
+        has_aes: true,
+
+        ecc_strength: EccKeyStrength::all(),
+        rsa_strength: RsaKeyStrength::all(),
+        aes_strength: AesKeyStrength::all(),
+    }
+}
+
+/// Encodes `req`, along with its header, in wire format.
+fn encode<'a, R: Request<'a>>(req: R) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut w = StdWrite(&mut buf);
+    Header {
+        command: R::TYPE,
+        is_request: true,
+    }
+    .to_wire(&mut w)
+    .expect("failed to write header");
+    req.to_wire(&mut w).expect("failed to write request");
+    buf
+}
+
+/// A connection to a device.
+struct Client {
+    stream: Box<dyn Stream>,
+}
+
+impl Client {
+    /// Sends `request`, a header + message in wire format, and returns the
+    /// response as JSON, along with whether it was a
+    /// [`manticore::protocol::Error`].
+    fn send(&mut self, request: &[u8]) -> io::Result<(Value, bool)> {
+        socket::write_frame(&mut *self.stream, request)
+            .map_err(|e| net_error("failed to send request", e))?;
+        let mut response = Vec::new();
+        socket::read_frame(&mut *self.stream, &mut response)
+            .map_err(|e| net_error("failed to receive response", e))?;
+
+        let header = Header::from_wire(&mut response.as_slice(), &OutOfMemory)
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("failed to read response header: {:?}", e),
+                )
+            })?;
+        let is_error = header.command == CommandType::Error;
+        Ok((crate::to_json_value(&response), is_error))
+    }
+
+    /// Sends the typed request `req`, and returns the response as JSON.
+    fn request<'a>(
+        &mut self,
+        req: impl Request<'a>,
+    ) -> io::Result<(Value, bool)> {
+        self.send(&encode(req))
+    }
+
+    /// Queries everything that can be learned about the device.
+    fn probe(&mut self) -> io::Result<Value> {
+        let (capabilities, _) = self.request(DeviceCapabilitiesRequest {
+            capabilities: host_capabilities(),
+        })?;
+        let (device_id, _) = self.request(DeviceIdRequest)?;
+
+        let mut device_info = Map::new();
+        for index in (0..=u8::MAX).filter_map(InfoIndex::from_wire_value) {
+            let (info, _) = self.request(DeviceInfoRequest { index })?;
+            device_info.insert(index.to_string(), info);
+        }
+
+        // Slot 0 is the device's own firmware. Vendor slots need not be
+        // contiguous, so all of them are tried, skipping any the device
+        // reports an error for.
+        let mut firmware_versions = Map::new();
+        for index in 0..=u8::MAX {
+            let (version, is_error) =
+                self.request(FirmwareVersionRequest { index })?;
+            if is_error && index != 0 {
+                continue;
+            }
+            firmware_versions.insert(index.to_string(), version);
+        }
+
+        Ok(json!({
+            "capabilities": capabilities,
+            "device_id": device_id,
+            "device_info": device_info,
+            "firmware_versions": firmware_versions,
+        }))
+    }
+
+    /// Sends each line of `input` as a request, passing each response to
+    /// `emit` as it arrives.
+    ///
+    /// Stops at the end of `input`, or at the first line that is not a
+    /// well-formed request.
+    fn interactive(
+        &mut self,
+        input: impl BufRead,
+        mut emit: impl FnMut(&Value),
+    ) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line: Line = serde_json::from_str(&line)?;
+            let cmd_type =
+                line.cmd_type.parse::<CommandByte>().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "bad command type {:?}: {:?}",
+                            line.cmd_type, e
+                        ),
+                    )
+                })?;
+
+            let json = serde_json::to_vec(&line.request)?;
+            let mut request = Vec::new();
+            crate::from_json(cmd_type, true, json.as_slice(), &mut request);
+            let (response, _) = self.send(&request)?;
+            emit(&response);
+        }
+        Ok(())
+    }
+}
+
+/// Converts a networking error into an I/O error, with some context.
+fn net_error(context: &str, e: net::Error) -> io::Error {
+    let kind = match e {
+        net::Error::Disconnected => io::ErrorKind::UnexpectedEof,
+        net::Error::Timeout => io::ErrorKind::TimedOut,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{}: {:?}", context, e))
+}
+
+/// Connects to the device at `addr`, sends the request described by `req`,
+/// and passes each response, as JSON, to `emit`.
+pub fn run(
+    addr: Address,
+    req: Requests,
+    mut emit: impl FnMut(&Value),
+) -> io::Result<()> {
+    let mut client = Client {
+        stream: connect(addr)?,
+    };
+
+    if req.interactive {
+        let stdin = io::stdin();
+        return client.interactive(stdin.lock(), emit);
+    }
+
+    let (response, _) = if let Some(cmd_type) = req.cmd_type {
+        let (input, _) = crate::open_files(req.input, None);
+        let mut request = Vec::new();
+        crate::from_json(cmd_type, true, input, &mut request);
+        client.send(&request)
+    } else if let Some(index) = req.firmware_version {
+        client.request(FirmwareVersionRequest { index })
+    } else if req.capabilities {
+        client.request(DeviceCapabilitiesRequest {
+            capabilities: host_capabilities(),
+        })
+    } else if req.device_id {
+        client.request(DeviceIdRequest)
+    } else if let Some(index) = req.device_info {
+        client.request(DeviceInfoRequest { index })
+    } else if let Some(port_id) = req.device_uptime {
+        client.request(DeviceUptimeRequest { port_id })
+    } else if let Some(reset_type) = req.reset_counter {
+        client.request(ResetCounterRequest {
+            reset_type,
+            port_id: req.port_id,
+        })
+    } else if req.request_counter {
+        client.request(RequestCounterRequest)
+    } else {
+        let probe = client.probe()?;
+        emit(&probe);
+        return Ok(());
+    }?;
+    emit(&response);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use manticore::protocol::device_id::DeviceIdResponse;
+    use manticore::protocol::device_id::DeviceIdentifier;
+    use manticore::protocol::firmware_version::FirmwareVersionResponse;
+    use manticore::protocol::Response;
+
+    /// A stream that replays canned bytes, and records what is written to it.
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Creates a client whose device replies with `input`, returning it
+    /// along with a handle to everything the client sends.
+    fn client(input: Vec<u8>) -> (Client, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let stream = Pipe {
+            input: Cursor::new(input),
+            output: Rc::clone(&output),
+        };
+        let client = Client {
+            stream: Box::new(stream),
+        };
+        (client, output)
+    }
+
+    /// Frames `msg` the way a device would send it.
+    fn response_frame<'a, R: Response<'a>>(msg: R) -> Vec<u8> {
+        let mut wire = Vec::new();
+        let mut w = StdWrite(&mut wire);
+        Header {
+            command: R::TYPE,
+            is_request: false,
+        }
+        .to_wire(&mut w)
+        .unwrap();
+        msg.to_wire(&mut w).unwrap();
+
+        let mut frame = Vec::new();
+        socket::write_frame(&mut frame, &wire).unwrap();
+        frame
+    }
+
+    #[test]
+    fn send_disconnected() {
+        let (mut client, _) = client(Vec::new());
+        let err = client.request(DeviceIdRequest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn send_bad_header() {
+        let mut input = Vec::new();
+        socket::write_frame(&mut input, &[]).unwrap();
+        let (mut client, _) = client(input);
+        let err = client.request(DeviceIdRequest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn interactive() {
+        let id = DeviceIdentifier {
+            vendor_id: 1,
+            device_id: 2,
+            subsys_vendor_id: 3,
+            subsys_id: 4,
+        };
+        let version = [7; 32];
+        let mut input = response_frame(DeviceIdResponse { id });
+        input.extend(response_frame(FirmwareVersionResponse {
+            version: &version,
+        }));
+        let (mut client, output) = client(input);
+
+        let lines = concat!(
+            r#"{"cmd_type": "DeviceId"}"#,
+            "\n\n",
+            r#"{"cmd_type": "FirmwareVersion", "request": {"index": 3}}"#,
+            "\n",
+        );
+        let mut responses = Vec::new();
+        client
+            .interactive(lines.as_bytes(), |r| responses.push(r.clone()))
+            .unwrap();
+
+        assert_eq!(
+            responses,
+            vec![
+                serde_json::to_value(DeviceIdResponse { id }).unwrap(),
+                serde_json::to_value(FirmwareVersionResponse {
+                    version: &version,
+                })
+                .unwrap(),
+            ],
+        );
+
+        let mut expected = Vec::new();
+        socket::write_frame(&mut expected, &encode(DeviceIdRequest)).unwrap();
+        socket::write_frame(
+            &mut expected,
+            &encode(FirmwareVersionRequest { index: 3 }),
+        )
+        .unwrap();
+        assert_eq!(*output.borrow(), expected);
+    }
+
+    #[test]
+    fn interactive_bad_line() {
+        let (mut client, output) = client(Vec::new());
+        let err = client
+            .interactive(&b"{\"cmd_type\": \"Bogus\"}\n"[..], |_| {
+                panic!("unexpected response")
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(output.borrow().is_empty());
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Structural diffs between manifests.
+//!
+//! Elements are matched up between the two manifests by a key derived from
+//! the element, such as its type and firmware ID, rather than by position,
+//! so that inserting an element does not cause every following one to be
+//! reported as modified.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use manticore::manifest::owned;
+use manticore::manifest::owned::pfm;
+
+/// An element type which can be diffed.
+pub trait Keyed: Serialize + PartialEq {
+    /// Returns a key for this element, which identifies it among its
+    /// siblings.
+    ///
+    /// Elements with the same key are considered to be the same element,
+    /// possibly modified.
+    fn key(&self) -> String;
+}
+
+impl Keyed for pfm::Element {
+    fn key(&self) -> String {
+        match self {
+            Self::FlashDevice { .. } => "FlashDevice".into(),
+            Self::PlatformId { .. } => "PlatformId".into(),
+            Self::AllowableFw { firmware_id, .. } => {
+                format!("AllowableFw[{}]", escape(firmware_id))
+            }
+            Self::FwVersion { version_str, .. } => {
+                format!("FwVersion[{}]", escape(version_str))
+            }
+            Self::Raw { element_type, .. } => {
+                format!("Raw[{:#04x}]", element_type)
+            }
+        }
+    }
+}
+
+/// Escapes a byte string for display.
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect()
+}
+
+/// A single difference between two manifests.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// The manifest's version ID changed.
+    VersionId(u32, u32),
+    /// The element at the given path was added.
+    Added(String, Value),
+    /// The element at the given path was removed.
+    Removed(String),
+    /// A field of the element at the given path changed.
+    Modified {
+        path: String,
+        field: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::VersionId(old, new) => {
+                write!(f, "~ version_id: {} -> {}", old, new)
+            }
+            Self::Added(path, value) => write!(f, "+ {}: {}", path, value),
+            Self::Removed(path) => write!(f, "- {}", path),
+            Self::Modified {
+                path,
+                field,
+                old,
+                new,
+            } if field.is_empty() => {
+                write!(f, "~ {}: {} -> {}", path, old, new)
+            }
+            Self::Modified {
+                path,
+                field,
+                old,
+                new,
+            } => write!(f, "~ {}.{}: {} -> {}", path, field, old, new),
+        }
+    }
+}
+
+/// Computes the differences between `old` and `new`.
+pub fn diff<E: Keyed>(
+    old: &owned::Container<E>,
+    new: &owned::Container<E>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if old.metadata.version_id != new.metadata.version_id {
+        changes.push(Change::VersionId(
+            old.metadata.version_id,
+            new.metadata.version_id,
+        ));
+    }
+    diff_nodes("", &old.elements, &new.elements, &mut changes);
+    changes
+}
+
+/// Recursive helper for diffing sibling lists of nodes.
+fn diff_nodes<E: Keyed>(
+    parent: &str,
+    old: &[owned::Node<E>],
+    new: &[owned::Node<E>],
+    changes: &mut Vec<Change>,
+) {
+    let old = keyed(parent, old);
+    let new = keyed(parent, new);
+
+    for (path, node) in &old {
+        let other = new.iter().find(|(p, _)| p == path).map(|(_, n)| n);
+        let other = match other {
+            Some(other) => other,
+            None => {
+                changes.push(Change::Removed(path.clone()));
+                continue;
+            }
+        };
+
+        if node.hashed != other.hashed {
+            changes.push(Change::Modified {
+                path: path.clone(),
+                field: "hashed".into(),
+                old: node.hashed.into(),
+                new: other.hashed.into(),
+            });
+        }
+        if node.element != other.element {
+            diff_fields(path, &node.element, &other.element, changes);
+        }
+        diff_nodes(path, &node.children, &other.children, changes);
+    }
+
+    for (path, node) in &new {
+        if old.iter().all(|(p, _)| p != path) {
+            let value = serde_json::to_value(node)
+                .expect("failed to serialize to JSON");
+            changes.push(Change::Added(path.clone(), value));
+        }
+    }
+}
+
+/// Computes the path of each node in `nodes`.
+///
+/// Nodes with duplicate keys are paired up in order; their paths are made
+/// unique by appending an index.
+fn keyed<'a, E: Keyed>(
+    parent: &str,
+    nodes: &'a [owned::Node<E>],
+) -> Vec<(String, &'a owned::Node<E>)> {
+    let mut counts = BTreeMap::<String, usize>::new();
+    let mut keyed = Vec::new();
+    for node in nodes {
+        let key = node.element.key();
+        let count = counts.entry(key.clone()).or_default();
+        let path = match *count {
+            0 => format!("{}/{}", parent, key),
+            n => format!("{}/{}#{}", parent, key, n),
+        };
+        *count += 1;
+        keyed.push((path, node));
+    }
+    keyed
+}
+
+/// Records each top-level field of `old` and `new` that differs.
+fn diff_fields<E: Keyed>(
+    path: &str,
+    old: &E,
+    new: &E,
+    changes: &mut Vec<Change>,
+) {
+    let old = serde_json::to_value(old).expect("failed to serialize to JSON");
+    let new = serde_json::to_value(new).expect("failed to serialize to JSON");
+    let (old, new) = match (old, new) {
+        (Value::Object(old), Value::Object(new)) => (old, new),
+        (old, new) => {
+            changes.push(Change::Modified {
+                path: path.into(),
+                field: "".into(),
+                old,
+                new,
+            });
+            return;
+        }
+    };
+
+    let fields = old
+        .keys()
+        .chain(new.keys().filter(|k| !old.contains_key(*k)));
+    for field in fields {
+        let old = old.get(field).cloned().unwrap_or(Value::Null);
+        let new = new.get(field).cloned().unwrap_or(Value::Null);
+        if old != new {
+            changes.push(Change::Modified {
+                path: path.into(),
+                field: field.clone(),
+                old,
+                new,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    /// Builds a PFM out of its JSON representation.
+    ///
+    /// This goes through text, the same way the tool reads manifests from
+    /// files.
+    fn pfm(json: Value) -> owned::Pfm {
+        serde_json::from_str(&json.to_string()).expect("failed to parse PFM")
+    }
+
+    /// Builds an image region covering a single range, in the form `diff()`
+    /// reports it.
+    fn image(hash: u8) -> Value {
+        json!({
+            "flags": { "must_validate_on_boot": true },
+            "hash_type": "Sha256",
+            "hash": vec![hash; 32],
+            "regions": [{ "offset": "0x10000", "len": "0x1000" }],
+        })
+    }
+
+    /// Builds a `FwVersion` element with a single image region.
+    fn fw_version(version: &str, hash: u8) -> Value {
+        json!({
+            "version_addr": "0x1000",
+            "version_str": version,
+            "rw_regions": [],
+            "image_regions": [image(hash)],
+        })
+    }
+
+    #[test]
+    fn pfm_changes() {
+        let old = pfm(json!({
+            "version_id": 1,
+            "elements": [
+                { "blank_byte": "0xff" },
+                { "blank_byte": "0x00" },
+                { "platform_id": "my platform" },
+                {
+                    "version_count": 1,
+                    "firmware_id": "fw-a",
+                    "flags": 0,
+                    "children": [fw_version("v1", 1)],
+                },
+                {
+                    "version_count": 1,
+                    "firmware_id": "fw-b",
+                    "flags": 0,
+                    "children": [fw_version("v1", 3)],
+                },
+            ],
+        }));
+        let new = pfm(json!({
+            "version_id": 2,
+            "elements": [
+                { "blank_byte": "0xff" },
+                { "blank_byte": "0x11" },
+                {
+                    "version_count": 2,
+                    "firmware_id": "fw-a",
+                    "flags": 0,
+                    "hashed": false,
+                    "children": [fw_version("v1", 2), fw_version("v2", 2)],
+                },
+                {
+                    "version_count": 1,
+                    "firmware_id": "fw-b",
+                    "flags": 0,
+                    "children": [fw_version("v1", 3)],
+                },
+                { "element_type": "0x42", "format_version": 0, "data": [] },
+            ],
+        }));
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Change::VersionId(1, 2),
+                Change::Modified {
+                    path: "/FlashDevice#1".into(),
+                    field: "blank_byte".into(),
+                    old: json!("0x0"),
+                    new: json!("0x11"),
+                },
+                Change::Removed("/PlatformId".into()),
+                Change::Modified {
+                    path: "/AllowableFw[fw-a]".into(),
+                    field: "hashed".into(),
+                    old: json!(true),
+                    new: json!(false),
+                },
+                Change::Modified {
+                    path: "/AllowableFw[fw-a]".into(),
+                    field: "version_count".into(),
+                    old: json!(1),
+                    new: json!(2),
+                },
+                Change::Modified {
+                    path: "/AllowableFw[fw-a]/FwVersion[v1]".into(),
+                    field: "image_regions".into(),
+                    old: json!([image(1)]),
+                    new: json!([image(2)]),
+                },
+                Change::Added(
+                    "/AllowableFw[fw-a]/FwVersion[v2]".into(),
+                    json!({
+                        "version_addr": "0x1000",
+                        "version_str": b"v2".to_vec(),
+                        "rw_regions": [],
+                        "image_regions": [image(2)],
+                    }),
+                ),
+                Change::Added(
+                    "/Raw[0x42]".into(),
+                    json!({
+                        "element_type": "0x42",
+                        "format_version": 0,
+                        "data": [],
+                    }),
+                ),
+            ],
+        );
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! JSON deserialization for messages that borrow their contents.
+//!
+//! Many Manticore messages contain borrowed byte slices, which are serialized
+//! as JSON arrays of integers. `serde_json` cannot produce a borrowed `&[u8]`
+//! from such an array, so this module provides a [`Document`], which parses
+//! JSON into a tree that also holds a byte copy of every array of bytes, and
+//! deserializes messages that borrow from that tree.
+
+use std::io::Read;
+
+use serde::de;
+use serde::de::value::Error;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::value::MapDeserializer;
+use serde::de::value::SeqDeserializer;
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde_json::Value;
+
+/// A parsed JSON document, which values can be deserialized from by borrowing
+/// its strings and byte arrays.
+pub struct Document(Node);
+
+impl Document {
+    /// Parses a JSON document from `reader`.
+    pub fn from_reader(reader: impl Read) -> Result<Self, Error> {
+        let value: Value = serde_json::from_reader(reader)
+            .map_err(<Error as de::Error>::custom)?;
+        Ok(Self(value.into()))
+    }
+
+    /// Deserializes a `T` that borrows from this document.
+    pub fn deserialize<'a, T: Deserialize<'a>>(&'a self) -> Result<T, Error> {
+        T::deserialize(&self.0)
+    }
+}
+
+/// A JSON value, in a form that can be borrowed from during deserialization.
+enum Node {
+    Null,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Str(String),
+    /// An array, along with its contents as bytes if every element is a byte.
+    Seq(Vec<Node>, Option<Vec<u8>>),
+    Map(Vec<(Node, Node)>),
+}
+
+impl From<Value> for Node {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Node::Null,
+            Value::Bool(b) => Node::Bool(b),
+            Value::Number(n) => {
+                if let Some(n) = n.as_u64() {
+                    Node::U64(n)
+                } else if let Some(n) = n.as_i64() {
+                    Node::I64(n)
+                } else {
+                    Node::F64(n.as_f64().unwrap_or_default())
+                }
+            }
+            Value::String(s) => Node::Str(s),
+            Value::Array(a) => {
+                let seq = a.into_iter().map(Node::from).collect::<Vec<_>>();
+                let bytes = seq
+                    .iter()
+                    .map(|b| match b {
+                        Node::U64(b) if *b <= u8::MAX as u64 => Some(*b as u8),
+                        _ => None,
+                    })
+                    .collect();
+                Node::Seq(seq, bytes)
+            }
+            Value::Object(o) => Node::Map(
+                o.into_iter()
+                    .map(|(k, v)| (Node::Str(k), v.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for &'de Node {
+    type Deserializer = Self;
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for &'de Node {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            Node::Null => visitor.visit_unit(),
+            Node::Bool(b) => visitor.visit_bool(*b),
+            Node::U64(n) => visitor.visit_u64(*n),
+            Node::I64(n) => visitor.visit_i64(*n),
+            Node::F64(n) => visitor.visit_f64(*n),
+            Node::Str(s) => visitor.visit_borrowed_str(s),
+            Node::Seq(seq, _) => {
+                let mut seq = SeqDeserializer::new(seq.iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Node::Map(map) => {
+                let mut map =
+                    MapDeserializer::new(map.iter().map(|(k, v)| (k, v)));
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            Node::Seq(_, Some(bytes)) => visitor.visit_borrowed_bytes(bytes),
+            Node::Seq(_, None) => {
+                Err(<Error as de::Error>::custom("expected an array of bytes"))
+            }
+            Node::Str(s) => visitor.visit_borrowed_bytes(s.as_bytes()),
+            other => other.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            Node::Null => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            Node::Str(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            Node::Map(map) => visitor.visit_enum(MapAccessDeserializer::new(
+                MapDeserializer::new(map.iter().map(|(k, v)| (k, v))),
+            )),
+            _ => Err(<Error as de::Error>::custom("expected an enum")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
@@ -16,26 +16,42 @@ use std::io;
 use std::io::BufReader;
 use std::io::Read;
 use std::io::Write;
+use std::num::ParseIntError;
 use std::path::PathBuf;
+use std::process;
 
 use serde::de::Deserialize;
+use serde::Serialize;
 
 use structopt::StructOpt;
 
+mod client;
+mod diff;
+mod json;
+mod serve;
+
+use manticore::cert::PublicKeyParams;
 use manticore::crypto::ring;
 use manticore::crypto::rsa::Builder as _;
 use manticore::crypto::rsa::KeyPair as _;
 use manticore::io::write::StdWrite;
 use manticore::io::Read as _;
+use manticore::manifest::lint;
 use manticore::manifest::owned;
 use manticore::manifest::ManifestType;
+use manticore::manifest::SigType;
 use manticore::mem::BumpArena;
-use manticore::protocol::firmware_version;
+use manticore::protocol;
+use manticore::protocol::schema;
 use manticore::protocol::wire::FromWire;
 use manticore::protocol::wire::ToWire;
 use manticore::protocol::wire::WireEnum;
+use manticore::protocol::Command;
+use manticore::protocol::CommandByte;
 use manticore::protocol::CommandType;
 use manticore::protocol::Header;
+use manticore::protocol::Request as _;
+use manticore::protocol::Response as _;
 
 /// Opens the given input and output files.
 ///
@@ -63,19 +79,73 @@ fn open_files(
     (input, output)
 }
 
-/// Deserializes a message in JSON format from `reader` and then serializes the
+/// Deserializes a message from the JSON document `doc` and then serializes the
 /// message in wire format to `writer`.
-fn from_json_to_wire<'de, T, R, W>(reader: R, writer: W)
+fn from_json_to_wire<'a, T, W>(doc: &'a json::Document, writer: W)
 where
-    T: ToWire + Deserialize<'de>,
-    R: Read,
+    T: ToWire + Deserialize<'a>,
     W: manticore::io::Write,
 {
-    let mut de = serde_json::Deserializer::from_reader(reader);
-    let msg = T::deserialize(&mut de).expect("failed to deserialize JSON");
+    let msg: T = doc.deserialize().expect("failed to deserialize JSON");
     msg.to_wire(writer).expect("failed to write request");
 }
 
+/// Dispatches on a message's request bit and command byte, expanding
+/// `$body!(T, (...))` with the corresponding message type `T`.
+///
+/// This is a workaround for the fact that Rust does not have a way to turn a
+/// runtime command byte into a type parameter.
+///
+/// The first rule contains the list of all commands understood by this tool:
+/// first, every `CommandType` (omitting a new one is a compile error), and
+/// then any commands defined outside of `manticore`, such as vendor-defined
+/// commands, given as paths to their `Command` types.
+macro_rules! dispatch_message {
+    ($is_request:expr, $command:expr, $body:ident!$args:tt) => {
+        dispatch_message!(@types [
+            FirmwareVersion,
+            DeviceCapabilities,
+            DeviceId,
+            DeviceInfo,
+            ResetCounter,
+            DeviceUptime,
+            RequestCounter,
+            KeySetUpdate,
+        ] [
+            // Vendor-defined commands go here.
+        ] $is_request, $command, $body!$args)
+    };
+    (@types [$($variant:ident,)*] [$($vendor:ty,)*]
+        $is_request:expr, $command:expr, $body:ident!$args:tt) => {{
+        // Ensures that every `CommandType` is dispatched on.
+        let _ = |c: CommandType| match c {
+            CommandType::Error => {}
+            $(CommandType::$variant => {})*
+        };
+        dispatch_message!(@commands [$(protocol::$variant,)* $($vendor,)*]
+            $is_request, $command, $body!$args)
+    }};
+    (@commands [$($cmd:ty,)*]
+        $is_request:expr, $command:expr, $body:ident!$args:tt) => {{
+        let is_request: bool = $is_request;
+        let command: CommandByte = $command;
+        match is_request {
+            false if command == protocol::Error::TYPE => {
+                $body!(protocol::Error, $args)
+            }
+            $(
+                true if command == <$cmd as Command>::Req::TYPE => {
+                    $body!(<$cmd as Command<'_>>::Req, $args)
+                }
+                false if command == <$cmd as Command>::Resp::TYPE => {
+                    $body!(<$cmd as Command<'_>>::Resp, $args)
+                }
+            )*
+            _ => panic!("unsupported message type {}", command),
+        }
+    }};
+}
+
 /// Converts a JSON object into a Manticore command.
 ///
 /// This function deserializes a message in JSON format from `input_file` and
@@ -84,7 +154,7 @@ where
 /// Uses `cmd_type` and `is_request` to determine the message type since the
 /// JSON format does not include the message header.
 fn from_json(
-    cmd_type: CommandType,
+    cmd_type: CommandByte,
     is_request: bool,
     input: impl Read,
     mut output: impl Write,
@@ -92,25 +162,19 @@ fn from_json(
     let mut stdwrite = StdWrite(&mut output);
 
     Header {
-        is_request: is_request,
+        is_request,
         command: cmd_type,
     }
     .to_wire(&mut stdwrite)
     .expect("failed to write header");
 
-    match (is_request, cmd_type) {
-        (true, CommandType::FirmwareVersion) => {
-            from_json_to_wire::<firmware_version::FirmwareVersionRequest, _, _>(
-                input, stdwrite,
-            )
-        }
-        (false, CommandType::FirmwareVersion) => {
-            from_json_to_wire::<firmware_version::FirmwareVersionResponse, _, _>(
-                input, stdwrite,
-            )
-        }
-        _ => panic!("Unsupported message type"),
+    let doc = json::Document::from_reader(input).expect("failed to read JSON");
+    macro_rules! from_json_to_wire {
+        ($ty:ty, ($doc:expr, $output:expr)) => {
+            from_json_to_wire::<$ty, _>($doc, $output)
+        };
     }
+    dispatch_message!(is_request, cmd_type, from_json_to_wire!(&doc, stdwrite));
 }
 
 /// Macro to deserialize wire format from an input file and then run an
@@ -122,7 +186,7 @@ fn from_json(
 /// * `input_file`: Identifier to get the input file name from.
 /// * `body`: A "generic" closure to run with the results of the parse.
 macro_rules! read_wire_and_operate {
-    ($input:ident, $body:expr) => {
+    ($input:ident, $body:expr) => {{
         let mut input = $input;
         let mut read_buf = Vec::new();
         input
@@ -135,30 +199,17 @@ macro_rules! read_wire_and_operate {
         let mut read_buf_slice = read_buf.as_slice();
         let header = Header::from_wire(&mut read_buf_slice, &arena)
             .expect("failed to read header");
-        match (header.is_request, header.command) {
-            (true, CommandType::FirmwareVersion) => {
-                let message =
-                    firmware_version::FirmwareVersionRequest::from_wire(
-                        &mut read_buf_slice,
-                        &arena,
-                    )
-                    .expect("failed to read response");
-                let body = $body;
-                body(message)
-            }
-            (false, CommandType::FirmwareVersion) => {
-                let message =
-                    firmware_version::FirmwareVersionResponse::from_wire(
-                        &mut read_buf_slice,
-                        &arena,
-                    )
-                    .expect("failed to read response");
+
+        macro_rules! operate {
+            ($ty:ty, ()) => {{
+                let message = <$ty>::from_wire(&mut read_buf_slice, &arena)
+                    .expect("failed to read message");
                 let body = $body;
                 body(message)
-            }
-            _ => panic!("unsupported response type {:?}", header.command),
+            }};
         }
-    };
+        dispatch_message!(header.is_request, header.command, operate!())
+    }};
 }
 
 /// Converts a Manticore command into a JSON object.
@@ -166,14 +217,61 @@ macro_rules! read_wire_and_operate {
 /// This funciton deserializes a header + message in wire format from `input`
 /// and then serialize the message as JSON to `output`.
 fn to_json(pretty: bool, input: impl Read, output: impl Write) {
+    read_wire_and_operate!(input, |msg| write_json(pretty, output, &msg));
+}
+
+/// Converts a header + message in wire format into a JSON value.
+fn to_json_value(input: &[u8]) -> serde_json::Value {
     read_wire_and_operate!(input, |msg| {
-        if pretty {
-            serde_json::to_writer_pretty(output, &msg)
-        } else {
-            serde_json::to_writer(output, &msg)
-        }
-        .expect("failed to serialize to JSON")
-    });
+        serde_json::to_value(&msg).expect("failed to serialize to JSON")
+    })
+}
+
+/// Parses a `u32`, which may be given in hex with a leading `0x`.
+fn parse_u32(s: &str) -> Result<u32, ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Reads the manifest type out of a manifest's header.
+fn manifest_type(manifest: &[u8]) -> ManifestType {
+    let mut r = manifest;
+    let _ = r.read_le::<u16>().expect("input len < 4");
+    let manifest_type = r.read_le::<u16>().expect("input len < 4");
+    ManifestType::from_wire_value(manifest_type).unwrap_or_else(|| {
+        panic!("unknown manifest type: 0x{:04x}", manifest_type)
+    })
+}
+
+/// Reports any verification failures in `parse` to stderr, and returns the
+/// parsed container.
+fn check_parse<E>(parse: owned::Parse<E>) -> owned::Container<E> {
+    if parse.bad_signature {
+        eprintln!("signature verification failed");
+    }
+    if parse.bad_toc_hash {
+        eprintln!("TOC hash verification failed");
+    }
+    for idx in parse.bad_hashes {
+        eprintln!("bad hash for toc entry {}", idx);
+    }
+    parse.container
+}
+
+/// Serializes `value` as JSON to `output`.
+fn write_json<T: Serialize + ?Sized>(
+    pretty: bool,
+    output: impl Write,
+    value: &T,
+) {
+    if pretty {
+        serde_json::to_writer_pretty(output, value)
+    } else {
+        serde_json::to_writer(output, value)
+    }
+    .expect("failed to serialize to JSON")
 }
 
 #[deny(missing_docs)]
@@ -185,9 +283,10 @@ fn to_json(pretty: bool, input: impl Read, output: impl Write) {
 enum CliCommand {
     /// Construct a Cerberus message from a JSON representation.
     FromJson {
-        /// The command type for the message.
+        /// The command type for the message, either as a name or as a raw
+        /// command byte, such as `0xf5`.
         #[structopt(short = "t", long)]
-        cmd_type: CommandType,
+        cmd_type: CommandByte,
 
         /// Whether this message is a request.
         #[structopt(short = "r", long)]
@@ -215,6 +314,62 @@ enum CliCommand {
         #[structopt(short = "o", long, parse(from_os_str))]
         output: Option<PathBuf>,
     },
+    /// Describe the wire layout of Cerberus messages as JSON.
+    Schema {
+        /// The command type to describe; if missing, all commands are
+        /// described.
+        #[structopt(short = "t", long)]
+        cmd_type: Option<CommandType>,
+
+        /// Whether to pretty-print the resulting JSON.
+        #[structopt(short = "p", long)]
+        pretty: bool,
+
+        /// JSON output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Run a simulated PA-RoT that serves requests over a socket.
+    Serve {
+        /// JSON file describing the simulated device; if missing, defaults
+        /// are used.
+        #[structopt(short = "c", long, parse(from_os_str))]
+        config: Option<PathBuf>,
+
+        /// PKCS#8-encoded RSA key for the simulated device.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: PathBuf,
+
+        /// TCP address to listen on, such as `localhost:1234`.
+        #[structopt(long, required_unless = "unix", conflicts_with = "unix")]
+        tcp: Option<String>,
+
+        /// Path of a Unix domain socket to listen on.
+        #[structopt(long, parse(from_os_str))]
+        unix: Option<PathBuf>,
+    },
+    /// Send a request to a device over a socket, and display the response
+    /// as JSON.
+    Client {
+        /// TCP address of the device, such as `localhost:1234`.
+        #[structopt(long, required_unless = "unix", conflicts_with = "unix")]
+        tcp: Option<String>,
+
+        /// Path of a Unix domain socket to connect to the device over.
+        #[structopt(long, parse(from_os_str))]
+        unix: Option<PathBuf>,
+
+        /// Whether to pretty-print the resulting JSON.
+        #[structopt(short = "p", long)]
+        pretty: bool,
+
+        /// JSON output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        requests: client::Requests,
+    },
     /// Create a new signed manifest.
     SignManifest {
         /// PKCS#8-encoded RSA signing key to sign with.
@@ -233,6 +388,105 @@ enum CliCommand {
         #[structopt(short = "o", long, parse(from_os_str))]
         output: Option<PathBuf>,
     },
+    /// Build a signed PFM for a host firmware image.
+    BuildPfm {
+        /// PKCS#8-encoded RSA signing key to sign with.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: PathBuf,
+
+        /// JSON file describing how the firmware image is laid out.
+        #[structopt(short = "l", long, parse(from_os_str))]
+        layout: PathBuf,
+
+        /// Host flash image to build the PFM for; defaults to stdin.
+        #[structopt(short = "i", long, parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Binary output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Encode the to-be-signed portion of a manifest, for signing with an
+    /// external key, such as one held in an HSM.
+    ///
+    /// The signature must be over the SHA-256 digest of the output, rather
+    /// than the output itself; see `attach-signature`.
+    PrepareManifest {
+        /// The manifest type for this operation.
+        #[structopt(short = "t", long)]
+        manifest_type: ManifestType,
+
+        /// The type of signature that will be attached, such as `Rsa2048`.
+        #[structopt(short = "s", long)]
+        sig_type: SigType,
+
+        /// The length of the signature, in bytes; required if it cannot be
+        /// inferred from `sig_type`.
+        #[structopt(long)]
+        sig_len: Option<usize>,
+
+        /// JSON file containing the manifest to encode; defaults to stdin.
+        #[structopt(short = "i", long, parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Binary output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Attach an externally-produced signature to the output of
+    /// `prepare-manifest`, verifying the completed manifest.
+    AttachSignature {
+        /// DER-encoded RSA public key (a `SubjectPublicKeyInfo`) to verify
+        /// the signature with.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: PathBuf,
+
+        /// File containing the raw signature.
+        #[structopt(short = "s", long, parse(from_os_str))]
+        signature: PathBuf,
+
+        /// File containing the output of `prepare-manifest`; defaults to
+        /// stdin.
+        #[structopt(short = "i", long, parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Binary output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Check a manifest for policy and structural mistakes.
+    ///
+    /// Exits with a nonzero status if any are found.
+    LintManifest {
+        /// The manifest type for this operation.
+        #[structopt(short = "t", long)]
+        manifest_type: ManifestType,
+
+        /// The size of the flash device the manifest describes, such as
+        /// `0x1000000`; if present, regions are checked to fit within it.
+        #[structopt(long, parse(try_from_str = parse_u32))]
+        flash_size: Option<u32>,
+
+        /// JSON file containing the manifest to check; defaults to stdin.
+        #[structopt(short = "i", long, parse(from_os_str))]
+        input: Option<PathBuf>,
+    },
+    /// Show the differences between two manifests.
+    ///
+    /// Exits with a nonzero status if the manifests differ.
+    DiffManifest {
+        /// PKCS#8-encoded RSA public key to optionally verify the signatures.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: Option<PathBuf>,
+
+        /// Binary file containing the original manifest.
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// Binary file containing the new manifest.
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
     /// Inspect an existing manifest.
     ShowManifest {
         /// PKCS#8-encoded RSA public key to optionally verify the signature.
@@ -272,6 +526,60 @@ fn main() {
             let (input, output) = open_files(input, output);
             to_json(pretty, input, output);
         }
+        CliCommand::Schema {
+            cmd_type,
+            pretty,
+            output,
+        } => {
+            let (_, output) = open_files(None, output);
+            match cmd_type {
+                Some(c) => write_json(pretty, output, schema::command(c)),
+                None => write_json(pretty, output, schema::COMMANDS),
+            }
+        }
+        CliCommand::Serve {
+            config,
+            key,
+            tcp,
+            unix,
+        } => {
+            let config = match config {
+                Some(path) => {
+                    let file = File::open(path).expect("failed to open file");
+                    serde_json::from_reader(BufReader::new(file))
+                        .expect("failed to parse config")
+                }
+                None => serve::Config::default(),
+            };
+            let key = fs::read(key).expect("failed to open file");
+
+            let addr = match (&tcp, &unix) {
+                (Some(addr), _) => serve::Address::Tcp(addr),
+                (_, Some(path)) => serve::Address::Unix(path),
+                _ => unreachable!(),
+            };
+            serve::serve(config, &key, addr).expect("failed to serve");
+        }
+        CliCommand::Client {
+            tcp,
+            unix,
+            pretty,
+            output,
+            requests,
+        } => {
+            let addr = match (&tcp, &unix) {
+                (Some(addr), _) => client::Address::Tcp(addr),
+                (_, Some(path)) => client::Address::Unix(path),
+                _ => unreachable!(),
+            };
+            let (_, mut output) = open_files(None, output);
+            client::run(addr, requests, |response| {
+                write_json(pretty, &mut output, response);
+                writeln!(output).expect("failed to write output");
+                output.flush().expect("failed to write output");
+            })
+            .expect("failed to talk to device");
+        }
         CliCommand::SignManifest {
             key,
             manifest_type,
@@ -303,6 +611,159 @@ fn main() {
                 .write_all(&manifest)
                 .expect("failed to write manifest");
         }
+        CliCommand::BuildPfm {
+            key,
+            layout,
+            input,
+            output,
+        } => {
+            let (mut input, mut output) = open_files(input, output);
+
+            let key = fs::read(key).expect("failed to open file");
+            let keypair = ring::rsa::KeyPair::from_pkcs8(&key)
+                .expect("failed to parse key");
+            let mut signer = ring::rsa::Builder::new()
+                .new_signer(keypair)
+                .expect("failed to create signing engine");
+            let sha = ring::sha256::Builder::new();
+
+            let layout = fs::read(layout).expect("failed to open file");
+            let layout: owned::pfm::Layout = serde_json::from_slice(&layout)
+                .expect("failed to parse layout");
+
+            let mut image = Vec::new();
+            input.read_to_end(&mut image).expect("failed to read file");
+            let pfm = layout
+                .build(&image, &sha)
+                .expect("failed to build PFM")
+                .sign(0x00, &sha, &mut signer)
+                .expect("failed to sign PFM");
+
+            output.write_all(&pfm).expect("failed to write manifest");
+        }
+        CliCommand::PrepareManifest {
+            manifest_type,
+            sig_type,
+            sig_len,
+            input,
+            output,
+        } => {
+            let (mut input, mut output) = open_files(input, output);
+
+            let sig_len =
+                sig_len.or_else(|| sig_type.sig_len()).unwrap_or_else(|| {
+                    panic!("signature length for {} must be given", sig_type)
+                });
+            let sha = ring::sha256::Builder::new();
+
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf).expect("failed to read file");
+            let tbs = match manifest_type {
+                ManifestType::Pfm => {
+                    let pfm: owned::Pfm = serde_json::from_slice(&buf)
+                        .expect("failed to parse PFM");
+                    pfm.to_be_signed(0x00, &sha, sig_type, sig_len)
+                        .expect("failed to encode PFM")
+                }
+            };
+
+            output.write_all(&tbs).expect("failed to write manifest");
+        }
+        CliCommand::AttachSignature {
+            key,
+            signature,
+            input,
+            output,
+        } => {
+            let (mut input, mut output) = open_files(input, output);
+
+            let key = fs::read(key).expect("failed to open file");
+            let key = match PublicKeyParams::from_spki(&key)
+                .expect("failed to parse key")
+            {
+                PublicKeyParams::Rsa { modulus, exponent } => {
+                    ring::rsa::PublicKey::new(modulus.into(), exponent.into())
+                        .expect("unsupported RSA key size")
+                }
+            };
+            let mut engine = ring::rsa::Builder::new()
+                .new_verifier(key)
+                .expect("failed to create signature verification engine");
+            let sha = ring::sha256::Builder::new();
+
+            let signature = fs::read(signature).expect("failed to open file");
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf).expect("failed to read file");
+
+            let manifest = match manifest_type(&buf) {
+                ManifestType::Pfm => owned::Pfm::attach_signature(
+                    &buf,
+                    &signature,
+                    &sha,
+                    &mut engine,
+                )
+                .expect("failed to attach signature"),
+            };
+
+            output
+                .write_all(&manifest)
+                .expect("failed to write manifest");
+        }
+        CliCommand::LintManifest {
+            manifest_type,
+            flash_size,
+            input,
+        } => {
+            let (mut input, _) = open_files(input, None);
+
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf).expect("failed to read file");
+            let lints = match manifest_type {
+                ManifestType::Pfm => {
+                    let pfm: owned::Pfm = serde_json::from_slice(&buf)
+                        .expect("failed to parse PFM");
+                    lint::pfm(&pfm, flash_size)
+                }
+            };
+
+            for lint in &lints {
+                println!("{}", lint);
+            }
+            if !lints.is_empty() {
+                process::exit(1);
+            }
+        }
+        CliCommand::DiffManifest { key, old, new } => {
+            let mut engine = key.map(|key| {
+                let key = fs::read(key).expect("failed to open file");
+                let keypair = ring::rsa::KeyPair::from_pkcs8(&key)
+                    .expect("failed to parse key");
+                ring::rsa::Builder::new()
+                    .new_verifier(keypair.public())
+                    .expect("failed to create signature verification engine")
+            });
+            let sha = ring::sha256::Builder::new();
+
+            let old = fs::read(old).expect("failed to open file");
+            let new = fs::read(new).expect("failed to open file");
+
+            let changes = match (manifest_type(&old), manifest_type(&new)) {
+                (ManifestType::Pfm, ManifestType::Pfm) => {
+                    let old = owned::Pfm::parse(&old, &sha, engine.as_mut())
+                        .expect("failed to parse old PFM");
+                    let new = owned::Pfm::parse(&new, &sha, engine.as_mut())
+                        .expect("failed to parse new PFM");
+                    diff::diff(&check_parse(old), &check_parse(new))
+                }
+            };
+
+            for change in &changes {
+                println!("{}", change);
+            }
+            if !changes.is_empty() {
+                process::exit(1);
+            }
+        }
         CliCommand::ShowManifest {
             key,
             pretty,
@@ -324,36 +785,155 @@ fn main() {
             let mut buf = Vec::new();
             input.read_to_end(&mut buf).expect("failed to read file");
 
-            let mut r = &buf[..];
-            let _ = r.read_le::<u16>().expect("input len < 4");
-            let manifest_type = r.read_le::<u16>().expect("input len < 4");
-
-            match ManifestType::from_wire_value(manifest_type) {
-                Some(ManifestType::Pfm) => {
+            match manifest_type(&buf) {
+                ManifestType::Pfm => {
                     let parse = owned::Pfm::parse(&buf, &sha, engine.as_mut())
                         .expect("failed to parse PFM");
-
-                    if parse.bad_signature {
-                        eprintln!("signature verification failed");
-                    }
-                    if parse.bad_toc_hash {
-                        eprintln!("TOC hash verification failed");
-                    }
-                    for idx in parse.bad_hashes {
-                        eprintln!("bad hash for toc entry {}", idx);
-                    }
+                    let pfm = check_parse(parse);
 
                     if pretty {
-                        serde_json::to_writer_pretty(output, &parse.container)
+                        serde_json::to_writer_pretty(output, &pfm)
                     } else {
-                        serde_json::to_writer(output, &parse.container)
+                        serde_json::to_writer(output, &pfm)
                     }
                     .expect("failed to serialize PFM");
                 }
-                None => {
-                    panic!("unknown manifest type: 0x{:04x}", manifest_type)
-                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use manticore::mem::OutOfMemory;
+    use manticore::protocol::capabilities::*;
+    use manticore::protocol::device_id::*;
+    use manticore::protocol::device_info::*;
+    use manticore::protocol::device_uptime::*;
+    use manticore::protocol::firmware_version::*;
+    use manticore::protocol::key_set_update::*;
+    use manticore::protocol::request_counter::*;
+    use manticore::protocol::reset_counter::*;
+
+    /// Encodes `msg`, preceded by a header, as `to_json()` expects to read it.
+    fn encode(
+        is_request: bool,
+        command: CommandByte,
+        msg: impl ToWire,
+    ) -> Vec<u8> {
+        let mut wire = Vec::new();
+        let mut w = StdWrite(&mut wire);
+        Header {
+            command,
+            is_request,
+        }
+        .to_wire(&mut w)
+        .unwrap();
+        msg.to_wire(&mut w).unwrap();
+        wire
+    }
+
+    fn request<'a, M: protocol::Request<'a> + ToWire>(msg: M) -> Vec<u8> {
+        encode(true, M::TYPE, msg)
+    }
+
+    fn response<'a, M: protocol::Response<'a> + ToWire>(msg: M) -> Vec<u8> {
+        encode(false, M::TYPE, msg)
+    }
+
+    /// Checks that converting `wire` to JSON and back reproduces it.
+    fn check_round_trip(wire: &[u8]) {
+        let mut r = wire;
+        let header = Header::from_wire(&mut r, &OutOfMemory)
+            .expect("failed to read header");
+        let json = serde_json::to_vec(&to_json_value(wire)).unwrap();
+
+        let mut out = Vec::new();
+        from_json(header.command, header.is_request, &json[..], &mut out);
+        assert_eq!(
+            out,
+            wire,
+            "{} did not round-trip through {}",
+            header.command,
+            String::from_utf8_lossy(&json),
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let capabilities = Capabilities {
+            networking: Networking {
+                max_message_size: 1024,
+                max_packet_size: 256,
+                mode: RotMode::Platform,
+                roles: BusRole::HOST,
+            },
+            security: Security::AUTHENTICATION,
+            has_pfm_support: true,
+            has_policy_support: false,
+            has_firmware_protection: false,
+            has_ecdsa: false,
+            has_ecc: false,
+            has_rsa: true,
+            has_aes: false,
+            ecc_strength: EccKeyStrength::empty(),
+            rsa_strength: RsaKeyStrength::BITS_2048,
+            aes_strength: AesKeyStrength::empty(),
+        };
+
+        let messages = [
+            response(protocol::Error::new(protocol::ErrorCode::Busy)),
+            request(FirmwareVersionRequest { index: 3 }),
+            response(FirmwareVersionResponse { version: &[7; 32] }),
+            request(DeviceCapabilitiesRequest { capabilities }),
+            response(DeviceCapabilitiesResponse {
+                capabilities,
+                timeouts: Timeouts {
+                    regular: Duration::from_millis(30),
+                    crypto: Duration::from_millis(200),
+                },
+            }),
+            request(DeviceIdRequest),
+            response(DeviceIdResponse {
+                id: DeviceIdentifier {
+                    vendor_id: 1,
+                    device_id: 2,
+                    subsys_vendor_id: 3,
+                    subsys_id: 4,
+                },
+            }),
+            request(DeviceInfoRequest {
+                index: InfoIndex::UniqueChipIndex,
+            }),
+            response(DeviceInfoResponse { info: b"chip" }),
+            request(ResetCounterRequest {
+                reset_type: ResetType::External,
+                port_id: 1,
+            }),
+            response(ResetCounterResponse { count: 42 }),
+            request(DeviceUptimeRequest { port_id: 0 }),
+            response(DeviceUptimeResponse {
+                uptime: Duration::from_micros(12345),
+            }),
+            request(RequestCounterRequest),
+            response(RequestCounterResponse {
+                ok_count: 5,
+                err_count: 6,
+            }),
+            request(KeySetUpdateRequest {
+                op: KeyOp::Add,
+                key_id: 2,
+                signer_id: 1,
+                key: b"key",
+                signature: b"sig",
+            }),
+        ];
+        for wire in &messages {
+            check_round_trip(wire);
+        }
+    }
+}
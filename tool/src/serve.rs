@@ -0,0 +1,273 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A simulated PA-RoT, served over a socket.
+//!
+//! The simulated device's hardware is described by a JSON [`Config`]; its
+//! cryptography is backed by `ring`, using a software RSA key.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use manticore::crypto::ring;
+use manticore::crypto::rsa;
+use manticore::crypto::rsa::KeyPair as _;
+use manticore::crypto::sig;
+use manticore::hardware;
+use manticore::mem::Arena as _;
+use manticore::mem::BumpArena;
+use manticore::net;
+use manticore::net::socket::SocketHost;
+use manticore::protocol::capabilities::BusRole;
+use manticore::protocol::capabilities::Networking;
+use manticore::protocol::capabilities::RotMode;
+use manticore::protocol::capabilities::Timeouts;
+use manticore::protocol::device_id::DeviceIdentifier;
+use manticore::server;
+use manticore::server::pa_rot::PaRot;
+
+/// Configuration for a simulated PA-RoT.
+///
+/// Every field is optional; missing fields take on the values in
+/// `Config::default()`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The firmware version string, which is zero-padded to 32 bytes.
+    pub firmware_version: String,
+    /// Vendor firmware version strings, by slot number.
+    pub vendor_firmware_versions: BTreeMap<u8, String>,
+    /// The device's unique identity.
+    pub unique_device_identity: Vec<u8>,
+    /// The number of resets since power-on to report.
+    pub resets_since_power_on: u32,
+
+    /// The device's silicon identifier.
+    pub device_id: DeviceIdentifier,
+    /// The device's networking capabilities.
+    pub networking: Networking,
+    /// The device's advertised timeouts.
+    pub timeouts: Timeouts,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            firmware_version: "manticore-tool".to_string(),
+            vendor_firmware_versions: BTreeMap::new(),
+            unique_device_identity: b"simulated PA-RoT".to_vec(),
+            resets_since_power_on: 0,
+            device_id: DeviceIdentifier {
+                vendor_id: 0,
+                device_id: 0,
+                subsys_vendor_id: 0,
+                subsys_id: 0,
+            },
+            networking: Networking {
+                max_message_size: 1024,
+                max_packet_size: 256,
+                mode: RotMode::Platform,
+                roles: BusRole::HOST,
+            },
+            timeouts: Timeouts {
+                regular: Duration::from_millis(30),
+                crypto: Duration::from_millis(200),
+            },
+        }
+    }
+}
+
+/// Zero-pads a version string to 32 bytes.
+fn pad_version(version: &str) -> [u8; 32] {
+    let bytes = version.as_bytes();
+    assert!(
+        bytes.len() <= 32,
+        "version string longer than 32 bytes: {:?}",
+        version
+    );
+
+    let mut padded = [0; 32];
+    padded[..bytes.len()].copy_from_slice(bytes);
+    padded
+}
+
+/// Simulated identity hardware, as described by a [`Config`].
+struct Identity {
+    firmware_version: [u8; 32],
+    vendor_firmware_versions: BTreeMap<u8, [u8; 32]>,
+    unique_device_identity: Vec<u8>,
+}
+
+impl hardware::Identity for Identity {
+    fn firmware_version(&self) -> &[u8; 32] {
+        &self.firmware_version
+    }
+
+    fn vendor_firmware_version(&self, slot: u8) -> Option<&[u8; 32]> {
+        self.vendor_firmware_versions.get(&slot)
+    }
+
+    fn unique_device_identity(&self) -> &[u8] {
+        &self.unique_device_identity
+    }
+}
+
+/// Simulated reset hardware, which measures uptime from when the simulation
+/// started.
+struct Reset {
+    resets_since_power_on: u32,
+    power_on: Instant,
+}
+
+impl hardware::Reset for Reset {
+    fn resets_since_power_on(&self) -> u32 {
+        self.resets_since_power_on
+    }
+
+    fn uptime(&self) -> Duration {
+        self.power_on.elapsed()
+    }
+}
+
+/// A `ring` RSA engine which advertises support only for the modulus length
+/// of the simulated device's key.
+struct Rsa {
+    engine: ring::rsa::Builder,
+    len: rsa::ModulusLength,
+}
+
+impl rsa::Builder<rsa::RsaPkcs1Sha256> for Rsa {
+    type Verify = ring::rsa::Verify256;
+    type Sign = ring::rsa::Sign256;
+    type Key = ring::rsa::PublicKey;
+    type KeyPair = ring::rsa::KeyPair;
+
+    fn supports_modulus(&self, len: rsa::ModulusLength) -> bool {
+        len == self.len
+    }
+
+    fn new_verifier(
+        &self,
+        key: Self::Key,
+    ) -> Result<Self::Verify, sig::VerifyError<Self::Verify>> {
+        self.engine.new_verifier(key)
+    }
+
+    fn new_signer(
+        &self,
+        keypair: Self::KeyPair,
+    ) -> Result<Self::Sign, sig::SignError<Self::Sign>> {
+        self.engine.new_signer(keypair)
+    }
+}
+
+/// A socket address to serve on.
+pub enum Address<'a> {
+    /// A TCP address, such as `localhost:1234`.
+    Tcp(&'a str),
+    /// A path to a Unix domain socket.
+    Unix(&'a Path),
+}
+
+/// Serves a simulated PA-RoT described by `config`, with the PKCS#8-encoded
+/// RSA key `key`, on `addr`.
+///
+/// Connections are handled one at a time, and requests on each connection are
+/// processed until the host disconnects. This function only returns if
+/// listening on `addr` fails.
+pub fn serve(config: Config, key: &[u8], addr: Address) -> io::Result<()> {
+    let keypair =
+        ring::rsa::KeyPair::from_pkcs8(key).expect("failed to parse key");
+    let rsa = Rsa {
+        engine: ring::rsa::Builder::new(),
+        len: keypair.pub_len(),
+    };
+
+    let identity = Identity {
+        firmware_version: pad_version(&config.firmware_version),
+        vendor_firmware_versions: config
+            .vendor_firmware_versions
+            .iter()
+            .map(|(&slot, version)| (slot, pad_version(version)))
+            .collect(),
+        unique_device_identity: config.unique_device_identity,
+    };
+    let reset = Reset {
+        resets_since_power_on: config.resets_since_power_on,
+        power_on: Instant::now(),
+    };
+
+    let mut server = PaRot::new(server::pa_rot::Options {
+        identity: &identity,
+        reset: &reset,
+        rsa: &rsa,
+        device_id: config.device_id,
+        networking: config.networking,
+        timeouts: config.timeouts,
+        keys: server::pa_rot::Keys::empty(),
+    });
+
+    let mut arena = vec![0; config.networking.max_message_size as usize];
+    let mut arena = BumpArena::new(&mut arena);
+
+    match addr {
+        Address::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            eprintln!("listening on {}", listener.local_addr()?);
+            for stream in listener.incoming() {
+                // See `client::connect()`.
+                let stream = stream?;
+                stream.set_nodelay(true)?;
+                serve_connection(&mut server, &mut arena, stream);
+            }
+        }
+        #[cfg(unix)]
+        Address::Unix(path) => {
+            let listener = UnixListener::bind(path)?;
+            eprintln!("listening on {}", path.display());
+            for stream in listener.incoming() {
+                serve_connection(&mut server, &mut arena, stream?);
+            }
+        }
+        #[cfg(not(unix))]
+        Address::Unix(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Unix sockets are not supported on this platform",
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Processes requests from `stream` until the host on the other end
+/// disconnects.
+fn serve_connection(
+    server: &mut PaRot<Identity, Reset, Rsa>,
+    arena: &mut BumpArena,
+    stream: impl io::Read + io::Write,
+) {
+    let mut host = SocketHost::new(stream);
+    loop {
+        let result = server.process_request(&mut host, &*arena);
+        arena.reset();
+        match result {
+            Ok(()) => {}
+            Err(server::Error::Network(net::Error::Disconnected)) => return,
+            Err(server::Error::Network(net::Error::Io(e))) => {
+                eprintln!("connection failed: {:?}", e);
+                return;
+            }
+            Err(e) => eprintln!("failed to process request: {:?}", e),
+        }
+    }
+}